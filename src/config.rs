@@ -0,0 +1,119 @@
+//! Seeds, account counts and limits of the program in one place, the numbers an
+//! audit of the escrow's accounts and amounts starts from
+
+use solana_program::pubkey::MAX_SEED_LEN;
+
+/// Seed of the PDA owning the temp accounts, and prefix of nonce derived escrows
+///
+/// A fork sets `ESCROW_SEED_PREFIX` at build time to keep its addresses apart from
+/// this deployment's, it defaults to `escrow`.
+pub const ESCROW_SEED: &[u8] = match option_env!("ESCROW_SEED_PREFIX") {
+    Some(prefix) => prefix.as_bytes(),
+    None => b"escrow",
+};
+const _: () = assert!(!ESCROW_SEED.is_empty() && ESCROW_SEED.len() <= MAX_SEED_LEN);
+
+/// Seed of the program's `Config` PDA
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// How long an escrow without a deadline can be filled, in seconds from its
+/// `created_at`. Set a later `expires_at` to keep one open longer
+pub const MAX_ESCROW_AGE_SECONDS: i64 = 90 * 24 * 60 * 60;
+
+/// The largest amount of either token an escrow opens with or a fill moves, past it
+/// init and exchange fail with `AmountTooLarge`. A guard against fat-fingered orders
+///
+/// A fork sets `MAX_ESCROW_AMOUNT` at build time to lower it, there is no limit by
+/// default.
+pub const MAX_ESCROW_AMOUNT: u64 = match option_env!("MAX_ESCROW_AMOUNT") {
+    Some(max) => parse_amount(max),
+    None => u64::MAX,
+};
+
+/// Parses a decimal `u64` at compile time, a malformed one fails the build
+const fn parse_amount(digits: &str) -> u64 {
+    let digits = digits.as_bytes();
+    assert!(!digits.is_empty(), "MAX_ESCROW_AMOUNT is empty");
+    let mut amount: u64 = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(
+            digits[i].is_ascii_digit(),
+            "MAX_ESCROW_AMOUNT isn't a number"
+        );
+        amount = amount * 10 + (digits[i] - b'0') as u64;
+        i += 1;
+    }
+    amount
+}
+
+/// How many slots before the exchange an escrow's price feed may have last
+/// published, about ten seconds
+pub const MAX_ORACLE_AGE_SLOTS: u64 = 25;
+
+/// Basis points in a whole, see `Config::rent_fee_bps`
+pub const MAX_BPS: u16 = 10_000;
+
+/// Thousandths in a whole, see `EscrowInstruction::PartialExchangePermille`
+pub const MAX_PERMILLE: u16 = 1_000;
+
+/// The most escrows a single `BatchExchange` fills
+pub const MAX_BATCH_FILLS: usize = 8;
+
+/// The longest memo an `Exchange` records, in bytes
+pub const MAX_MEMO_LEN: usize = 128;
+
+// The fewest accounts each instruction takes, optional trailing ones left out.
+// Instructions without optional accounts take exactly these and fail with
+// `TooManyAccounts` on more, the others ignore any they don't look for.
+pub const INIT_ESCROW_ACCOUNTS: usize = 7;
+pub const EXCHANGE_ACCOUNTS: usize = 10;
+pub const CANCEL_ESCROW_ACCOUNTS: usize = 6;
+pub const SET_FROZEN_ACCOUNTS: usize = 2;
+pub const SET_EXPIRY_ACCOUNTS: usize = 2;
+pub const TOP_UP_ACCOUNTS: usize = 6;
+pub const WITHDRAW_PARTIAL_ACCOUNTS: usize = 6;
+pub const MIGRATE_ACCOUNTS: usize = 4;
+pub const SET_RENT_FEE_ACCOUNTS: usize = 3;
+pub const TRANSFER_MAKER_ACCOUNTS: usize = 4;
+pub const RELIST_ACCOUNTS: usize = 7;
+pub const LOG_STATE_ACCOUNTS: usize = 1;
+pub const SET_HOOK_ACCOUNTS: usize = 2;
+pub const ADMIN_ACCOUNTS: usize = 2;
+pub const SET_AUDIT_CLOSE_ACCOUNTS: usize = 2;
+pub const FINALIZE_CLOSED_ACCOUNTS: usize = 3;
+/// `CancelAll` takes these, then `CANCEL_ALL_ESCROW_ACCOUNTS` per escrow
+pub const CANCEL_ALL_ACCOUNTS: usize = 3;
+pub const CANCEL_ALL_ESCROW_ACCOUNTS: usize = 3;
+/// `BatchExchange` takes these, then `BATCH_FILL_ACCOUNTS` per fill
+pub const BATCH_EXCHANGE_ACCOUNTS: usize = 6;
+pub const BATCH_FILL_ACCOUNTS: usize = 4;
+/// `ScanExpired` takes these, then each escrow alone, or with
+/// `SCAN_EXPIRED_RECLAIM_ACCOUNTS` per escrow when reclaiming
+pub const SCAN_EXPIRED_ACCOUNTS: usize = 3;
+pub const SCAN_EXPIRED_RECLAIM_ACCOUNTS: usize = 4;
+
+// Compute units an instruction takes at most, for clients to pass to
+// `ComputeBudgetInstruction::set_compute_unit_limit`. `cargo test-bpf` fails once
+// the program outgrows one by more than its margin, see tests/program_test.rs.
+// Native runs don't meter compute, the values are estimates until measured there:
+// the program logs of `cargo test-bpf --test program_test compute_units` show what
+// each one consumed.
+pub const INIT_CU: u32 = 40_000;
+pub const EXCHANGE_CU: u32 = 80_000;
+pub const CANCEL_CU: u32 = 50_000;
+// The compute unit tests run init under the later limits, all within the runtime's
+// default of 200k per instruction
+const _: () = assert!(INIT_CU <= EXCHANGE_CU && INIT_CU <= CANCEL_CU && EXCHANGE_CU <= 200_000);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("0"), 0);
+        assert_eq!(parse_amount("1000000"), 1_000_000);
+        assert_eq!(parse_amount("18446744073709551615"), u64::MAX);
+    }
+}
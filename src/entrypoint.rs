@@ -1,8 +1,9 @@
 use solana_program::{
     account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg, pubkey::Pubkey,
 };
+use std::panic::{self, AssertUnwindSafe};
 
-use crate::processor::Processor;
+use crate::{error::EscrowError, processor::Processor};
 
 entrypoint!(process_instruction);
 fn process_instruction(
@@ -16,41 +17,46 @@ fn process_instruction(
         accounts.len(),
         instruction_data
     );
-    Processor::process(program_id, accounts, instruction_data)
+    catch_panic(|| Processor::process(program_id, accounts, instruction_data))
 }
 
-#[cfg(test)]
-mod test {
-    use {
-        super::*,
-        assert_matches::*,
-        solana_program::instruction::{AccountMeta, Instruction},
-        solana_program_test::*,
-        solana_sdk::{signature::Signer, transaction::Transaction},
-    };
-
-    #[tokio::test]
-    async fn test_transaction() {
-        let program_id = Pubkey::new_unique();
+/// Runs `process`, turning a panic into `ProgramPanicked` after logging its message.
+/// BPF builds abort on a panic before it gets here, only targets that unwind such
+/// as the native `ProgramTest` runtime see the error
+fn catch_panic(process: impl FnOnce() -> ProgramResult) -> ProgramResult {
+    // A failed instruction's account changes are thrown away, nothing half-written
+    // outlives the panic
+    panic::catch_unwind(AssertUnwindSafe(process)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        msg!("Program panicked: {}", message);
+        Err(EscrowError::ProgramPanicked.into())
+    })
+}
 
-        let (mut banks_client, payer, recent_blockhash) = ProgramTest::new(
-            "bpf_program_template",
-            program_id,
-            processor!(process_instruction),
-        )
-        .start()
-        .await;
+/// Run with `cargo test --features test-internals`
+#[cfg(all(test, feature = "test-internals"))]
+mod test {
+    use super::*;
 
-        let mut transaction = Transaction::new_with_payer(
-            &[Instruction {
-                program_id,
-                accounts: vec![AccountMeta::new(payer.pubkey(), false)],
-                data: vec![1, 2, 3],
-            }],
-            Some(&payer.pubkey()),
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| Ok(())), Ok(()));
+        assert_eq!(
+            catch_panic(|| Err(EscrowError::SameMint.into())),
+            Err(EscrowError::SameMint.into())
+        );
+        let data = [1u8; 2];
+        let index = data.len() + 1;
+        assert_eq!(
+            catch_panic(|| {
+                msg!("{}", data[index]);
+                Ok(())
+            }),
+            Err(EscrowError::ProgramPanicked.into())
         );
-        transaction.sign(&[&payer], recent_blockhash);
-
-        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
     }
 }
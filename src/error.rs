@@ -2,7 +2,11 @@ use thiserror::Error;
 
 use solana_program::program_error::ProgramError;
 
-#[derive(Error, Debug, Copy, Clone)]
+/// What every fallible helper of the program returns
+pub type EscrowResult<T> = Result<T, ProgramError>;
+
+/// Only ever append variants, the discriminants are the codes clients match on
+#[derive(Error, Debug, Copy, Clone, PartialEq)]
 pub enum EscrowError {
     #[error("Invalid Instruction")]
     InvalidInstruction,
@@ -10,6 +14,7 @@ pub enum EscrowError {
     #[error("mint mismatch")]
     ExpectedMintMismatch,
 
+    // The request doesn't fit the escrow, e.g. an exchange of nothing
     #[error("amount mismatch")]
     ExpectedAmountMismatch,
 
@@ -21,10 +26,347 @@ pub enum EscrowError {
 
     #[error("Amount Overflow")]
     AmountOverflow,
+
+    #[error("decimals mismatch")]
+    DecimalsMismatch,
+
+    #[error("escrow is frozen")]
+    EscrowFrozen,
+
+    #[error("slippage exceeded")]
+    SlippageExceeded,
+
+    // The temp token's mint doesn't match the account it's released into
+    #[error("taker side mint mismatch")]
+    TakerMintMismatch,
+
+    // The taker pays in a mint the maker didn't ask for
+    #[error("maker side mint mismatch")]
+    MakerMintMismatch,
+
+    #[error("refund account not owned by the maker")]
+    RefundAccountNotOwned,
+
+    #[error("fill below the escrow's minimum")]
+    BelowMinimumFill,
+
+    #[error("escrow is busy")]
+    EscrowBusy,
+
+    #[error("receive account not owned by the taker")]
+    ReceiveAccountNotOwned,
+
+    // The escrow no longer holds what the taker asked for, its temp account was
+    // drained or closed
+    #[error("escrow amount is stale")]
+    StaleAmount,
+
+    #[error("escrow has expired")]
+    EscrowExpired,
+
+    #[error("escrow is partially filled")]
+    PartiallyFilled,
+
+    #[error("escrow is already in the current layout")]
+    AlreadyMigrated,
+
+    // A token account the exchange pays into is frozen by its mint's freeze authority
+    #[error("token account is frozen")]
+    AccountFrozen,
+
+    // Relisting takes an escrow kept open by its cancel, not one still trading
+    #[error("escrow is still active")]
+    EscrowActive,
+
+    // A delegate could still move the temp tokens after the PDA takes them over
+    #[error("temp token account has a delegate")]
+    TempAccountHasDelegate,
+
+    // No longer returned, the escrow authority's bump is derived on every call.
+    // Kept so the codes after it stay put
+    #[error("invalid escrow authority")]
+    InvalidPda,
+
+    // The refund account doesn't hold the temp token's mint, the log names the right one
+    #[error("refund account mint mismatch")]
+    RefundMintMismatch,
+
+    // The escrow would trade a token for the same token, see `allow_same_mint`
+    #[error("temp and receive mints are the same")]
+    SameMint,
+
+    // An instruction with a fixed account list got more than it takes
+    #[error("too many accounts")]
+    TooManyAccounts,
+
+    // The exchange's memo is over `MAX_MEMO_LEN` bytes
+    #[error("memo too long")]
+    MemoTooLong,
+
+    // The price feed isn't trading or hasn't published within `MAX_ORACLE_AGE_SLOTS`
+    #[error("stale oracle price")]
+    StaleOracle,
+
+    // The price feed left the escrow's `min_price..=max_price`
+    #[error("oracle price out of bounds")]
+    OraclePriceOutOfBounds,
+
+    // The admin paused the program, only cancels go through
+    #[error("program paused")]
+    ProgramPaused,
+
+    // The quoted amount's return data is missing, not a `u64` or set by another
+    // program than the quote program
+    #[error("invalid quote")]
+    InvalidQuote,
+
+    // An amount init or exchange moves is over `MAX_ESCROW_AMOUNT`
+    #[error("amount too large")]
+    AmountTooLarge,
+
+    // The program panicked, caught by the entrypoint where the target unwinds
+    #[error("program panicked")]
+    ProgramPanicked,
+
+    // The fill's price rounds down to nothing, the taker would get it for free
+    #[error("maker payment rounds to zero")]
+    ZeroMakerPayment,
+
+    // The escrow expects nothing for its temp tokens, every fill would be free
+    #[error("escrow expects a zero amount")]
+    ZeroAmount,
 }
 
+/// The `ProgramError::Custom` code of every error by name. Codes are part of the
+/// program's interface, new errors go at the end and existing ones never move
+pub const ERROR_CODES: &[(&str, u32)] = &[
+    ("InvalidInstruction", 0),
+    ("ExpectedMintMismatch", 1),
+    ("ExpectedAmountMismatch", 2),
+    ("NotEnoughBalanceToSent", 3),
+    ("InvalidAccountData", 4),
+    ("AmountOverflow", 5),
+    ("DecimalsMismatch", 6),
+    ("EscrowFrozen", 7),
+    ("SlippageExceeded", 8),
+    ("TakerMintMismatch", 9),
+    ("MakerMintMismatch", 10),
+    ("RefundAccountNotOwned", 11),
+    ("BelowMinimumFill", 12),
+    ("EscrowBusy", 13),
+    ("ReceiveAccountNotOwned", 14),
+    ("StaleAmount", 15),
+    ("EscrowExpired", 16),
+    ("PartiallyFilled", 17),
+    ("AlreadyMigrated", 18),
+    ("AccountFrozen", 19),
+    ("EscrowActive", 20),
+    ("TempAccountHasDelegate", 21),
+    ("InvalidPda", 22),
+    ("RefundMintMismatch", 23),
+    ("SameMint", 24),
+    ("TooManyAccounts", 25),
+    ("MemoTooLong", 26),
+    ("StaleOracle", 27),
+    ("OraclePriceOutOfBounds", 28),
+    ("ProgramPaused", 29),
+    ("InvalidQuote", 30),
+    ("AmountTooLarge", 31),
+    ("ProgramPanicked", 32),
+    ("ZeroMakerPayment", 33),
+    ("ZeroAmount", 34),
+];
+
 impl From<EscrowError> for ProgramError {
     fn from(e: EscrowError) -> Self {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl EscrowError {
+    /// Maps a `ProgramError::Custom` code back to its error, the inverse of `as u32`
+    pub fn from_u32(code: u32) -> Option<EscrowError> {
+        use EscrowError::*;
+        Some(match code {
+            0 => InvalidInstruction,
+            1 => ExpectedMintMismatch,
+            2 => ExpectedAmountMismatch,
+            3 => NotEnoughBalanceToSent,
+            4 => InvalidAccountData,
+            5 => AmountOverflow,
+            6 => DecimalsMismatch,
+            7 => EscrowFrozen,
+            8 => SlippageExceeded,
+            9 => TakerMintMismatch,
+            10 => MakerMintMismatch,
+            11 => RefundAccountNotOwned,
+            12 => BelowMinimumFill,
+            13 => EscrowBusy,
+            14 => ReceiveAccountNotOwned,
+            15 => StaleAmount,
+            16 => EscrowExpired,
+            17 => PartiallyFilled,
+            18 => AlreadyMigrated,
+            19 => AccountFrozen,
+            20 => EscrowActive,
+            21 => TempAccountHasDelegate,
+            22 => InvalidPda,
+            23 => RefundMintMismatch,
+            24 => SameMint,
+            25 => TooManyAccounts,
+            26 => MemoTooLong,
+            27 => StaleOracle,
+            28 => OraclePriceOutOfBounds,
+            29 => ProgramPaused,
+            30 => InvalidQuote,
+            31 => AmountTooLarge,
+            32 => ProgramPanicked,
+            33 => ZeroMakerPayment,
+            34 => ZeroAmount,
+            _ => return None,
+        })
+    }
+
+    /// Describes a custom error code as seen in RPC responses
+    pub fn describe(code: u32) -> String {
+        match Self::from_u32(code) {
+            Some(e) => e.to_string(),
+            None => format!("unknown escrow error code {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_u32_round_trip() {
+        use EscrowError::*;
+        let all = [
+            InvalidInstruction,
+            ExpectedMintMismatch,
+            ExpectedAmountMismatch,
+            NotEnoughBalanceToSent,
+            InvalidAccountData,
+            AmountOverflow,
+            DecimalsMismatch,
+            EscrowFrozen,
+            SlippageExceeded,
+            TakerMintMismatch,
+            MakerMintMismatch,
+            RefundAccountNotOwned,
+            BelowMinimumFill,
+            EscrowBusy,
+            ReceiveAccountNotOwned,
+            StaleAmount,
+            EscrowExpired,
+            PartiallyFilled,
+            AlreadyMigrated,
+            AccountFrozen,
+            EscrowActive,
+            TempAccountHasDelegate,
+            InvalidPda,
+            RefundMintMismatch,
+            SameMint,
+            TooManyAccounts,
+            MemoTooLong,
+            StaleOracle,
+            OraclePriceOutOfBounds,
+            ProgramPaused,
+            InvalidQuote,
+            AmountTooLarge,
+            ProgramPanicked,
+            ZeroMakerPayment,
+            ZeroAmount,
+        ];
+        for e in all {
+            assert_eq!(EscrowError::from_u32(e as u32), Some(e));
+            assert_eq!(ProgramError::from(e), ProgramError::Custom(e as u32));
+        }
+        assert_eq!(EscrowError::from_u32(all.len() as u32), None);
+    }
+
+    #[test]
+    fn test_error_codes_pinned() {
+        for (name, code) in ERROR_CODES {
+            let e = EscrowError::from_u32(*code).unwrap();
+            assert_eq!(format!("{:?}", e), *name);
+            assert_eq!(e as u32, *code, "{} moved", name);
+        }
+        assert_eq!(EscrowError::from_u32(ERROR_CODES.len() as u32), None);
+        assert_eq!(EscrowError::AmountOverflow as u32, 5);
+    }
+
+    #[test]
+    fn test_variants_append_only() {
+        use EscrowError::*;
+        // A failure here means a variant moved, append new ones at the end instead
+        let codes = [
+            (InvalidInstruction, 0),
+            (ExpectedMintMismatch, 1),
+            (ExpectedAmountMismatch, 2),
+            (NotEnoughBalanceToSent, 3),
+            (InvalidAccountData, 4),
+            (AmountOverflow, 5),
+            (DecimalsMismatch, 6),
+            (EscrowFrozen, 7),
+            (SlippageExceeded, 8),
+            (TakerMintMismatch, 9),
+            (MakerMintMismatch, 10),
+            (RefundAccountNotOwned, 11),
+            (BelowMinimumFill, 12),
+            (EscrowBusy, 13),
+            (ReceiveAccountNotOwned, 14),
+            (StaleAmount, 15),
+            (EscrowExpired, 16),
+            (PartiallyFilled, 17),
+            (AlreadyMigrated, 18),
+            (AccountFrozen, 19),
+            (EscrowActive, 20),
+            (TempAccountHasDelegate, 21),
+            (InvalidPda, 22),
+            (RefundMintMismatch, 23),
+            (SameMint, 24),
+            (TooManyAccounts, 25),
+            (MemoTooLong, 26),
+            (StaleOracle, 27),
+            (OraclePriceOutOfBounds, 28),
+            (ProgramPaused, 29),
+            (InvalidQuote, 30),
+            (AmountTooLarge, 31),
+            (ProgramPanicked, 32),
+            (ZeroMakerPayment, 33),
+            (ZeroAmount, 34),
+        ];
+        for (e, code) in codes {
+            assert_eq!(e as u32, code, "{:?} moved", e);
+        }
+        assert_eq!(codes.len(), ERROR_CODES.len());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(EscrowError::describe(7), "escrow is frozen");
+        assert_eq!(
+            EscrowError::describe(1000),
+            "unknown escrow error code 1000"
+        );
+    }
+
+    #[test]
+    fn test_amount_error_codes() {
+        assert_eq!(
+            ProgramError::from(EscrowError::ExpectedAmountMismatch),
+            ProgramError::Custom(2)
+        );
+        assert_eq!(
+            ProgramError::from(EscrowError::NotEnoughBalanceToSent),
+            ProgramError::Custom(3)
+        );
+        assert_eq!(
+            ProgramError::from(EscrowError::StaleAmount),
+            ProgramError::Custom(15)
+        );
+    }
+}
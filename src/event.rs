@@ -0,0 +1,161 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, program::set_return_data, pubkey::Pubkey};
+
+/// Emitted as program data logs so indexers don't have to scrape `msg!` text
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum EscrowEvent {
+    Initialized {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        temp_token_account: Pubkey,
+        expected_amount: u64,
+    },
+    Exchanged {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        taker: Pubkey,
+        // What the maker got from the taker
+        amount_sent: u64,
+        // What the taker got out of the temp account
+        amount_received: u64,
+    },
+    Cancelled {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        amount_refunded: u64,
+    },
+}
+
+impl EscrowEvent {
+    pub fn emit(&self) {
+        // Serializing into a Vec can't fail
+        let data = self.try_to_vec().unwrap();
+        sol_log_data(&[&data]);
+    }
+}
+
+/// Decodes one field of a `Program data:` log line, after base64 decoding
+pub fn parse_event(log: &[u8]) -> Option<EscrowEvent> {
+    EscrowEvent::try_from_slice(log).ok()
+}
+
+/// Set as return data by `Exchange` and `SimulateExchange`, for the caller to read
+/// with `get_return_data`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ExchangeReceipt {
+    // What the taker paid the maker
+    pub amount_in: u64,
+    // What the taker got out of the temp account
+    pub amount_out: u64,
+    // Whether the exchange emptied and closed the escrow
+    pub closed: bool,
+}
+
+impl ExchangeReceipt {
+    pub fn set(&self) {
+        // Serializing into a Vec can't fail
+        let data = self.try_to_vec().unwrap();
+        set_return_data(&data);
+    }
+}
+
+/// Decodes the return data of an exchange
+pub fn parse_exchange_receipt(data: &[u8]) -> Option<ExchangeReceipt> {
+    ExchangeReceipt::try_from_slice(data).ok()
+}
+
+/// Instruction data of the call to an escrow's hook program after a fill, see
+/// `SetHook`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SettlementHook {
+    pub escrow: Pubkey,
+    pub taker: Pubkey,
+    // What the taker got out of the temp account
+    pub amount: u64,
+}
+
+/// Decodes the instruction data a hook program gets
+pub fn parse_settlement_hook(data: &[u8]) -> Option<SettlementHook> {
+    SettlementHook::try_from_slice(data).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(event: EscrowEvent) {
+        let data = event.try_to_vec().unwrap();
+        assert_eq!(parse_event(&data), Some(event));
+        assert_eq!(parse_event(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_initialized_round_trip() {
+        round_trip(EscrowEvent::Initialized {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            temp_token_account: Pubkey::new_unique(),
+            expected_amount: 500,
+        });
+    }
+
+    #[test]
+    fn test_exchanged_round_trip() {
+        round_trip(EscrowEvent::Exchanged {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            taker: Pubkey::new_unique(),
+            amount_sent: 500,
+            amount_received: 100,
+        });
+    }
+
+    #[test]
+    fn test_cancelled_round_trip() {
+        round_trip(EscrowEvent::Cancelled {
+            escrow: Pubkey::new_unique(),
+            initializer: Pubkey::new_unique(),
+            amount_refunded: 100,
+        });
+    }
+
+    #[test]
+    fn test_parse_unknown_variant() {
+        assert_eq!(parse_event(&[3]), None);
+        assert_eq!(parse_event(&[]), None);
+    }
+
+    #[test]
+    fn test_exchange_receipt_round_trip() {
+        let receipt = ExchangeReceipt {
+            amount_in: 500,
+            amount_out: 100,
+            closed: true,
+        };
+        let data = receipt.try_to_vec().unwrap();
+        assert_eq!(data.len(), 17);
+        assert_eq!(parse_exchange_receipt(&data), Some(receipt));
+        assert_eq!(parse_exchange_receipt(&data[..16]), None);
+    }
+
+    #[test]
+    fn test_settlement_hook_layout() {
+        let hook = SettlementHook {
+            escrow: Pubkey::new_unique(),
+            taker: Pubkey::new_unique(),
+            amount: 100,
+        };
+        let data = hook.try_to_vec().unwrap();
+        assert_eq!(
+            data,
+            [
+                hook.escrow.as_ref(),
+                hook.taker.as_ref(),
+                &100u64.to_le_bytes()
+            ]
+            .concat()
+        );
+        assert_eq!(parse_settlement_hook(&data), Some(hook));
+        assert_eq!(parse_settlement_hook(&data[..71]), None);
+    }
+}
@@ -1,8 +1,33 @@
-use solana_program::program_error::ProgramError;
-use std::convert::TryInto;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, system_program, sysvar,
+};
+use std::convert::{TryFrom, TryInto};
 
-use crate::error::EscrowError::InvalidInstruction;
+// Clients reach these from before `config` through here
+pub use crate::config::{
+    ADMIN_ACCOUNTS, BATCH_EXCHANGE_ACCOUNTS, BATCH_FILL_ACCOUNTS, CANCEL_ALL_ACCOUNTS,
+    CANCEL_ALL_ESCROW_ACCOUNTS, CANCEL_CU, CANCEL_ESCROW_ACCOUNTS, EXCHANGE_ACCOUNTS, EXCHANGE_CU,
+    INIT_CU, INIT_ESCROW_ACCOUNTS, LOG_STATE_ACCOUNTS, MAX_BATCH_FILLS, MAX_MEMO_LEN,
+    MIGRATE_ACCOUNTS, RELIST_ACCOUNTS, SET_EXPIRY_ACCOUNTS, SET_FROZEN_ACCOUNTS, SET_HOOK_ACCOUNTS,
+    SET_RENT_FEE_ACCOUNTS, TOP_UP_ACCOUNTS, TRANSFER_MAKER_ACCOUNTS, WITHDRAW_PARTIAL_ACCOUNTS,
+};
+use crate::{
+    config::{MAX_BPS, MAX_PERMILLE},
+    error::EscrowError::{self, InvalidInstruction},
+    state::{
+        find_config_address, find_escrow_address, find_escrow_authority, MakerReceiveSplit,
+        MAX_ACCEPTED_MINTS, MAX_RECEIVE_SPLITS,
+    },
+};
 
+/// Instruction data is the tag byte followed by the fields in order. Integers are
+/// little-endian on every platform, clients serialize them with `to_le_bytes`.
+#[derive(Debug, PartialEq)]
 pub enum EscrowInstruction {
     /// Starts the trade by creating and populating an escrow account and
     /// transferring ownership of the given temp token account to the PDA
@@ -14,11 +39,48 @@ pub enum EscrowInstruction {
     /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer
     /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
     /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
-    /// 4. `[]` The rent sysvar
+    ///    It needs at least `Rent::minimum_balance(Escrow::LEN)` lamports
+    /// 4. `[]` (optional) The rent sysvar, read from the runtime when left out,
+    ///    the accounts after it move up one then. Any other account there fails
+    ///    with `InvalidArgument`
     /// 5. `[]` The token program
+    /// 6. `[]` The clock sysvar, stamps the escrow's `created_at`
+    /// 7. `[]` The program's config from `find_config_address`. While the admin has
+    ///    the program paused, see `Admin`, opening an escrow fails with `ProgramPaused`
+    /// 8. `[]` (optional) The mint of the temp token account
+    /// 9. `[]` (optional) The mint of the token to receive
+    ///
+    /// Passing both mints turns on strict mode: their decimals are recorded
+    /// in the escrow and checked again on exchange.
+    ///
+    /// The temp token account can't have a delegate with an allowance left, revoke
+    /// it first.
+    ///
+    /// Trading a mint for itself is most likely a client mixing up the accounts,
+    /// so it fails with `SameMint` unless `allow_same_mint` is set.
+    ///
+    /// If the temp token account is owned by an SPL multisig rather than account 0,
+    /// the multisig follows the mints, then the multisig signers approving the
+    /// handover, each `[signer]`.
+    ///
+    /// Account 2 may also be the initializer's associated token account before it
+    /// exists, the mint to receive has to follow the config then. The first
+    /// exchange creates it, see `Exchange`.
+    ///
+    /// With a nonce, account 3 is the uncreated address from `find_escrow_address`
+    /// and the program creates it, so the system program has to come after the
+    /// config, ahead of any multisig.
     InitEscrow {
         /// The amount party A expects to receive of token Y
         amount: u64,
+        /// The smallest partial fill accepted, optional and 0 (no minimum) by default
+        min_fill: u64,
+        /// Optional, follows `min_fill`. Makes init idempotent, see below
+        nonce: Option<[u8; 32]>,
+        /// Lets the temp token and the token to receive share a mint, which fails
+        /// with `SameMint` otherwise. Optional and false by default, follows the
+        /// nonce if there is one
+        allow_same_mint: bool,
     },
 
     // Executes the trading by
@@ -33,45 +95,830 @@ pub enum EscrowInstruction {
     //
     /// 0. `[signer]` The account of the person initializing the exchange
     /// 1. `[w]` Request's token account which has sent, to receive if fail
-    /// 2. `[w]` Request's token account to receive
-    /// 3. `[w]` The escrow temp account, would move all amount to 2
-    /// 4. `[w]` The initializer account, what for?
+    /// 2. `[w]` Request's token account to receive, owned by 0
+    /// 3. `[w]` The escrow temp account, would move the filled amount to 2
+    /// 4. `[w]` The initializer account, gets the temp account's lamports once the
+    ///    escrow closes. The maker's wallet, never the pda
     /// 5. `[w]` The initializer token account to receive
     /// 6. `[w]` The escrow account
     /// 7. `[]` token program
     /// 8. `[]` pda
-    Exchange { amount: u64 },
+    /// 9. `[]` The program's config, see `Admin`
+    /// 10. `[]` (strict mode) The mint of the escrow temp token
+    /// 11. `[]` (strict mode) The mint of the initializer token to receive
+    ///
+    /// The taker gets `min(amount, temp balance)` and pays the maker the same share
+    /// of the expected amount, rounded up. A fill short of the temp balance leaves
+    /// the escrow open for the rest at the same price.
+    ///
+    /// Asking for 0 fails with `ExpectedAmountMismatch`, an escrow with nothing
+    /// left with `StaleAmount`, a taker short of the maker's share with
+    /// `NotEnoughBalanceToSent` and a frozen account 2 or 5 with `AccountFrozen`.
+    /// An escrow can't be filled from its deadline on, or once it's
+    /// `MAX_ESCROW_AGE_SECONDS` old if it has none, failing with `EscrowExpired`.
+    /// The time is read off the clock sysvar if passed after the strict mode mints,
+    /// otherwise off the runtime's. Escrows that can't expire don't need a clock at all.
+    ///
+    /// If account 5 is the maker's associated token account and doesn't exist yet,
+    /// `create_maker_receive` has the taker create it, paying its rent. The mint the
+    /// taker pays in, the system program, the rent sysvar and the associated token
+    /// account program then follow the strict mode mints, in any order.
+    ///
+    /// The token accounts 1, 2, 3 and 5 have to be four different accounts, any two
+    /// the same fail with `InvalidAccountData`. Accounts 0 and 4 may be the same
+    /// wallet, a maker filling their own escrow.
+    ///
+    /// Account 1 may be owned by someone else who made a delegate of it, the delegate
+    /// then signs for the taker's payment, passed `[signer]` after the strict mode
+    /// mints. Without it account 0 has to own account 1.
+    ///
+    /// An escrow with a hook, see `SetHook`, needs the hook program passed after the
+    /// strict mode mints too. An escrow made by `InitEscrowSplit` needs all of its
+    /// split accounts but the first there, distinct from accounts 1, 2 and 3.
+    ///
+    /// An escrow kept as a record once emptied, see `SetAuditClose`, stamps the time
+    /// off the clock the same way.
+    ///
+    /// A `memo` is recorded through the SPL Memo program, signed by account 0, which
+    /// then follows the strict mode mints as well. Memos over `MAX_MEMO_LEN` bytes
+    /// fail with `MemoTooLong`.
+    ///
+    /// While the program is paused every exchange fails with `ProgramPaused`, as do
+    /// `BatchExchange` and `SimulateExchange`. Cancels go through regardless.
+    ///
+    /// A router chaining a quote into the fill sets `EXCHANGE_QUOTED_AMOUNT` and
+    /// names the quote program. The amount is then the little-endian `u64` of the
+    /// return data of the instruction before, which has to be set by that program,
+    /// otherwise the exchange fails with `InvalidQuote`.
+    ///
+    /// A taker paying a wrapped SOL escrow in SOL sets `EXCHANGE_WRAP_SOL`. Account 1
+    /// is then the taker's wrapped SOL associated token account and account 0 has
+    /// to be writable: the program wraps what account 1 is short of the maker's
+    /// share out of account 0's lamports and pays the maker. An account 1 the fill
+    /// created is closed back into account 0, unwrapping anything left in it, one
+    /// that existed before stays open. The system program follows the strict mode
+    /// mints, and if account 1 doesn't exist yet, so do the native mint, the rent
+    /// sysvar and the associated token account program to create it.
+    Exchange {
+        /// The most temp tokens the taker is willing to take, replaced by the quote
+        /// if there is one
+        amount: u64,
+        /// The least amount of temp tokens the taker accepts, optional and 0 by default
+        min_receive: u64,
+        /// Optional, follows `min_receive` as the `EXCHANGE_CREATE_MAKER_RECEIVE` bit
+        /// of the flags byte. False by default
+        create_maker_receive: bool,
+        /// The `EXCHANGE_WRAP_SOL` bit of the flags byte, see above. False by default
+        wrap_sol: bool,
+        /// With the `EXCHANGE_QUOTED_AMOUNT` bit, the program the amount is read
+        /// from, following the flags byte
+        quote_program: Option<Pubkey>,
+        /// Optional UTF-8, the rest of the data after the flags and quote program
+        memo: Option<String>,
+    },
 
     // Executes the cancel by
     //
     // Accounts expected:
     //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` Owner's return token acc, any of the owner's accounts in the temp token's mint
+    /// 2. `[w]` Escrow temp token acc
+    /// 3. `[w]` The escrow account
+    /// 4. `[]` token program
+    /// 5. `[]` pda
+    /// 6. `[]` (expired cancel) The clock sysvar
+    ///
+    /// Until the escrow expires, see `SetExpiry`, account 0 has to sign. Once it
+    /// has, anyone can cancel by passing account 0 unsigned and the clock: the
+    /// tokens and rent still go back to the initializer. `unwrap_sol` and
+    /// `keep_escrow` always need the signature.
+    ///
+    /// Account 1 in another mint fails with `RefundMintMismatch`, logging the mint
+    /// to retry with.
+    ///
+    /// A client confirming what it cancels passes the temp balance it last saw as
+    /// `confirm_amount`, the cancel fails with `StaleAmount` if a fill or top up
+    /// moved it since.
+    ///
+    /// A cancel sent again after the first closed the escrow succeeds and does
+    /// nothing, account 3 then has neither lamports nor data.
+    CancelEscrow {
+        /// Closes the refunded wrapped SOL account into the owner's lamports if it
+        /// was empty before, ignored for other mints. Optional and false by default
+        unwrap_sol: bool,
+        /// Leaves the escrow account open and empty for `Relist` instead of closing
+        /// it. Optional and false by default, after `unwrap_sol`
+        keep_escrow: bool,
+        /// The temp balance the cancel expects, optional after `keep_escrow`
+        confirm_amount: Option<u64>,
+    },
+
+    // Freezes or unfreezes the escrow, exchanges are rejected while frozen
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    SetFrozen { frozen: bool },
+
+    /// Same as `InitEscrow`, but the trade may be paid in any of the given mints
+    ///
+    /// The taker then pays into a token account of the initializer holding the
+    /// mint it pays with, in place of the one passed at init.
+    ///
+    /// Accounts expected: the same as `InitEscrow`, the mint of account 2 has
+    /// to be one of `mints`
+    InitEscrowMulti {
+        /// The amount party A expects to receive in any of the mints
+        amount: u64,
+        /// At most `MAX_ACCEPTED_MINTS` mints
+        mints: Vec<Pubkey>,
+    },
+
+    // Fills several escrows in one go, if any fill fails the whole transaction
+    // reverts
+    //
+    // Accounts expected:
+    //
     /// 0. `[signer]` The account of the person initializing the exchange
-    /// 1. `[w]` Owner's return token acc
+    /// 1. `[w]` Request's token account which has sent, shared by every fill
+    /// 2. `[w]` Request's token account to receive, shared by every fill
+    /// 3. `[]` token program
+    /// 4. `[]` pda
+    /// 5. `[]` The program's config, see `Admin`
+    ///
+    /// Then for each of `amounts`, in the same order:
+    ///
+    /// 6. `[w]` The escrow account
+    /// 7. `[w]` The escrow temp account
+    /// 8. `[w]` The initializer account
+    /// 9. `[w]` The initializer token account to receive
+    ///
+    /// The strict mode mints of every escrow, if any, follow the last fill.
+    BatchExchange {
+        /// The most temp tokens to take from each escrow, at most `MAX_BATCH_FILLS`
+        amounts: Vec<u64>,
+    },
+
+    /// Runs every check of `Exchange` and fails the same way, but moves no tokens
+    /// and leaves the escrow open
+    ///
+    /// Accounts expected: the same as `Exchange`
+    SimulateExchange { amount: u64, min_receive: u64 },
+
+    // Sets when the escrow expires, after which anyone can cancel it
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    SetExpiry {
+        /// Unix timestamp, 0 never expires
+        expires_at: i64,
+    },
+
+    // Adds tokens to the temp account of an escrow nobody has filled yet, the
+    // expected amount grows at the same price, rounded up
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The maker's token account to take the tokens from, in the temp token's mint
+    /// 2. `[w]` Escrow temp token acc
+    /// 3. `[w]` The escrow account
+    /// 4. `[]` token program
+    /// 5. `[]` The clock sysvar, expired escrows can't be topped up
+    TopUp {
+        /// The amount of temp tokens to add
+        amount: u64,
+    },
+
+    // Takes part of the temp tokens back and leaves the escrow open, the expected
+    // amount shrinks at the same price. Withdrawing everything is `CancelEscrow`
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` Owner's return token acc, any of the owner's accounts in the temp token's mint
     /// 2. `[w]` Escrow temp token acc
     /// 3. `[w]` The escrow account
     /// 4. `[]` token program
     /// 5. `[]` pda
-    CancelEscrow { amount: u64 },
+    WithdrawPartial {
+        /// Less than the temp balance, and leaving at least the escrow's `min_fill`
+        amount: u64,
+    },
+
+    // Grows an escrow packed in an earlier layout to the current one, the maker
+    // pays the extra rent
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer, w]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    /// 2. `[]` The rent sysvar
+    /// 3. `[]` The system program
+    Migrate,
+
+    // Sets the share of an escrow's rent paid to a fee account once an exchange
+    // completes it, closing it or leaving it for `FinalizeClosed`, the maker gets
+    // the rest. Those then pass the fee account after their other accounts, along
+    // with the config. Cancels pay no fee. Signed by the admin of an existing
+    // config, see `Admin`
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The admin
+    /// 1. `[w]` The config account
+    /// 2. `[]` The account paid the fee
+    SetRentFee {
+        /// Basis points of the rent, at most `MAX_BPS`
+        rent_fee_bps: u16,
+    },
+
+    // Hands the escrow to another maker without cancelling it, who then gets paid,
+    // refunded and signs in the old maker's place
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    /// 2. `[]` The maker's token account to receive, the one stored in the escrow
+    /// 3. `[]` The new maker's token account to receive, in the same mint
+    TransferMaker { new_maker: Pubkey },
+
+    // Lists a new trade in an escrow a `keep_escrow` cancel left open, saving the
+    // rent of closing and creating one. The maker, receive account and nonce stay,
+    // every other setting starts over as in `InitEscrow`
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` New temp token account, owned by the initializer
+    /// 2. `[w]` The escrow account
+    /// 3. `[]` The token program
+    /// 4. `[]` The clock sysvar
+    /// 5. `[]` The program's config, a relist fails with `ProgramPaused` while the
+    ///    admin has the program paused, see `Admin`
+    /// 6. `[]` The maker's token account to receive, the one stored in the escrow
+    ///
+    /// The new temp account is checked as in `InitEscrow`, without `allow_same_mint`
+    /// a relist in the mint to receive fails with `SameMint`.
+    Relist {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+    },
+
+    // Logs every field of the escrow, for reading its state off a simulated
+    // transaction. Changes nothing
+    //
+    // Accounts expected:
+    //
+    /// 0. `[]` The escrow account
+    LogState,
+
+    // Sets or clears the program invoked after every fill of the escrow, in the same
+    // transaction so a failing hook fails the exchange. It gets a `SettlementHook`
+    // as instruction data and the escrow and the taker as read-only accounts
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    SetHook {
+        /// Optional, no hook when left out
+        hook_program: Option<Pubkey>,
+    },
+
+    /// Same as `InitEscrow`, but priced per temp token rather than for the lot
+    ///
+    /// A fill of `n` temp tokens pays the maker `n * price_num / price_den`, rounded
+    /// up. The expected amount is that of the whole temp balance and follows it
+    /// through fills, `TopUp` and `WithdrawPartial`.
+    ///
+    /// Accounts expected: the same as `InitEscrow`
+    InitEscrowRatio {
+        /// Tokens Y the maker gets per `price_den` temp tokens, not 0
+        price_num: u64,
+        /// Not 0
+        price_den: u64,
+    },
+
+    // Cancels several escrows of the same maker in one go, if any cancel fails the
+    // whole transaction reverts
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrows
+    /// 1. `[]` The token program
+    /// 2. `[]` pda
+    ///
+    /// Then `CANCEL_ALL_ESCROW_ACCOUNTS` for each escrow:
+    ///
+    /// 0. `[w]` The escrow account
+    /// 1. `[w]` Its temp token account
+    /// 2. `[w]` The maker's token account refunded, in the temp token's mint
+    ///
+    /// The tokens and rent of each go back as with `CancelEscrow`.
+    CancelAll,
+
+    /// Same as `InitEscrowRatio`, but the ratio comes from a Pyth price feed, read
+    /// again on every exchange, see `validation::oracle_price`
+    ///
+    /// The feed is passed after the clock, ahead of any multisig, here and after the
+    /// strict mode mints on exchange. A feed out of `min_price..=max_price` or
+    /// older than `MAX_ORACLE_AGE_SLOTS` fails the exchange rather than trading at
+    /// its price. In between, `TopUp` and `WithdrawPartial` use the last price read.
+    ///
+    /// Accounts expected: the same as `InitEscrow`
+    InitEscrowOracle {
+        price_feed: Pubkey,
+        /// In the feed's units, not 0
+        min_price: u64,
+        /// In the feed's units, at least `min_price`
+        max_price: u64,
+    },
+
+    // Pauses or resumes opening and filling escrows across the program. The first
+    // call creates the config at `find_config_address` and makes its signer the
+    // admin, so it belongs in the deployment. Every later one has to be signed by
+    // that admin
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer, w]` The admin, pays for the config on the first call
+    /// 1. `[w]` The config account
+    /// 2. `[]` (first call) The system program
+    Admin { paused: bool },
+
+    /// Same as `InitEscrow`, but every payment is split across several of the
+    /// maker's token accounts
+    ///
+    /// Each account gets its basis points of the payment rounded down, the first
+    /// the remainder on top. The first has to be account 2, the others token
+    /// accounts in the same mint, passed after the strict mode mints on exchange.
+    ///
+    /// Accounts expected: the same as `InitEscrow`
+    InitEscrowSplit {
+        /// The amount party A expects to receive of token Y, all accounts together
+        amount: u64,
+        /// At most `MAX_RECEIVE_SPLITS` distinct accounts, each with a share over 0
+        /// and all adding up to `MAX_BPS`
+        splits: Vec<MakerReceiveSplit>,
+    },
+
+    /// Same as `InitEscrow`, with the expected amount in units of `10^scale` of
+    /// the token to receive, so a maker can ask for 5 tokens of a 6 decimals mint
+    /// as `amount` 5 and `scale` 6
+    ///
+    /// The program prices every exchange by `amount * 10^scale` base units. An
+    /// amount that doesn't fit a `u64` once scaled fails with `AmountOverflow`.
+    ///
+    /// Accounts expected: the same as `InitEscrow`
+    InitEscrowScaled {
+        /// The amount party A expects to receive of token Y, in units of `10^scale`
+        amount: u64,
+        scale: u8,
+    },
+
+    // Keeps the escrow as a record once an exchange empties it, with the taker in
+    // `completed_by` and the time in `completed_at`. Its rent stays in it until
+    // `FinalizeClosed`
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[w]` The escrow account
+    SetAuditClose { audit_close: bool },
+
+    // Closes an escrow an exchange left as a record, see `SetAuditClose`. The rent
+    // goes to the maker, less the share of `SetRentFee`
+    //
+    // Accounts expected:
+    //
+    /// 0. `[signer, w]` The account of the person initializing the escrow, gets the rent
+    /// 1. `[w]` The escrow account
+    /// 2. `[]` The config account
+    /// 3. `[w]` (rent fee set) The account paid the fee
+    FinalizeClosed,
+
+    /// Same as `Exchange`, but the taker asks for a share of the temp tokens left
+    /// rather than an amount, so a retried fill still takes the share it meant to
+    ///
+    /// The fill is `permille` thousandths of what's left, rounded down. A share
+    /// rounding to nothing fails with `ExpectedAmountMismatch`, and one under the
+    /// escrow's `min_fill` with `BelowMinimumFill` unless it empties the escrow.
+    ///
+    /// Accounts expected: the same as `Exchange`
+    PartialExchangePermille {
+        /// At most `MAX_PERMILLE`, which takes everything left
+        permille: u16,
+    },
+
+    // Logs which of a batch of escrows have expired, for keepers. With `reclaim` it
+    // cancels those as well, everything going back to their makers as with a
+    // `CancelEscrow` past the deadline. Any other account, or an escrow failing the
+    // cancel's checks, is logged and skipped rather than failing the batch. A
+    // cancel failing once it moves tokens or lamports fails the batch
+    //
+    // Accounts expected:
+    //
+    /// 0. `[]` The clock sysvar
+    /// 1. `[]` The token program
+    /// 2. `[]` pda
+    ///
+    /// Then each escrow account alone, or with `reclaim` these
+    /// `SCAN_EXPIRED_RECLAIM_ACCOUNTS` for each:
+    ///
+    /// 0. `[w]` The escrow account
+    /// 1. `[w]` Its temp token account
+    /// 2. `[w]` The maker's token account refunded, in the temp token's mint
+    /// 3. `[w]` The maker's account, gets the rent back
+    ScanExpired { reclaim: bool },
 }
 
+/// The first byte of each instruction's data, pinned so a new instruction can't
+/// shift the others
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscrowInstructionTag {
+    InitEscrow = 0,
+    Exchange = 1,
+    CancelEscrow = 2,
+    SetFrozen = 3,
+    InitEscrowMulti = 4,
+    BatchExchange = 5,
+    SimulateExchange = 6,
+    SetExpiry = 7,
+    TopUp = 8,
+    WithdrawPartial = 9,
+    Migrate = 10,
+    SetRentFee = 11,
+    TransferMaker = 12,
+    Relist = 13,
+    LogState = 14,
+    SetHook = 15,
+    InitEscrowRatio = 16,
+    CancelAll = 17,
+    InitEscrowOracle = 18,
+    Admin = 19,
+    InitEscrowSplit = 20,
+    InitEscrowScaled = 21,
+    SetAuditClose = 22,
+    FinalizeClosed = 23,
+    PartialExchangePermille = 24,
+    ScanExpired = 25,
+}
+
+impl TryFrom<u8> for EscrowInstructionTag {
+    type Error = ProgramError;
+
+    fn try_from(tag: u8) -> Result<Self, Self::Error> {
+        use EscrowInstructionTag::*;
+        Ok(match tag {
+            0 => InitEscrow,
+            1 => Exchange,
+            2 => CancelEscrow,
+            3 => SetFrozen,
+            4 => InitEscrowMulti,
+            5 => BatchExchange,
+            6 => SimulateExchange,
+            7 => SetExpiry,
+            8 => TopUp,
+            9 => WithdrawPartial,
+            10 => Migrate,
+            11 => SetRentFee,
+            12 => TransferMaker,
+            13 => Relist,
+            14 => LogState,
+            15 => SetHook,
+            16 => InitEscrowRatio,
+            17 => CancelAll,
+            18 => InitEscrowOracle,
+            19 => Admin,
+            20 => InitEscrowSplit,
+            21 => InitEscrowScaled,
+            22 => SetAuditClose,
+            23 => FinalizeClosed,
+            24 => PartialExchangePermille,
+            25 => ScanExpired,
+            _ => return Err(InvalidInstruction.into()),
+        })
+    }
+}
+
+/// Bits of the `Exchange` flags byte, following `min_receive`
+pub const EXCHANGE_CREATE_MAKER_RECEIVE: u8 = 1;
+pub const EXCHANGE_QUOTED_AMOUNT: u8 = 1 << 1;
+pub const EXCHANGE_WRAP_SOL: u8 = 1 << 2;
+
+/// The SPL Memo program `Exchange` records memos with
+pub const MEMO_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
 impl EscrowInstruction {
     // unpact byte buffer
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
 
-        Ok(match tag {
-            0 => Self::InitEscrow {
+        Ok(match EscrowInstructionTag::try_from(*tag)? {
+            EscrowInstructionTag::InitEscrow => {
+                let (nonce, allow_same_mint) =
+                    Self::unpack_nonce_and_flag(rest.get(16..).unwrap_or_default())?;
+                Self::InitEscrow {
+                    amount: Self::unpack_amount(rest)?,
+                    min_fill: Self::unpack_optional_amount(&rest[8..])?,
+                    nonce,
+                    allow_same_mint,
+                }
+            }
+            EscrowInstructionTag::Exchange => {
+                let tail = rest.get(16..).unwrap_or_default();
+                let (flags, tail) = match tail.split_first() {
+                    Some((flags, tail)) => (*flags, tail),
+                    None => (0, tail),
+                };
+                let known_flags =
+                    EXCHANGE_CREATE_MAKER_RECEIVE | EXCHANGE_QUOTED_AMOUNT | EXCHANGE_WRAP_SOL;
+                if flags & !known_flags != 0 {
+                    return Err(InvalidInstruction.into());
+                }
+                let (quote_program, memo) = if flags & EXCHANGE_QUOTED_AMOUNT != 0 {
+                    let quote_program = Self::unpack_pubkey(tail.get(..32).unwrap_or_default())?;
+                    (Some(quote_program), &tail[32..])
+                } else {
+                    (None, tail)
+                };
+                Self::Exchange {
+                    amount: Self::unpack_amount(rest)?,
+                    min_receive: Self::unpack_optional_amount(&rest[8..])?,
+                    create_maker_receive: flags & EXCHANGE_CREATE_MAKER_RECEIVE != 0,
+                    wrap_sol: flags & EXCHANGE_WRAP_SOL != 0,
+                    quote_program,
+                    memo: Self::unpack_memo(memo)?,
+                }
+            }
+            EscrowInstructionTag::CancelEscrow => Self::CancelEscrow {
+                unwrap_sol: Self::unpack_optional_bool(rest.get(..1).unwrap_or_default())?,
+                keep_escrow: Self::unpack_optional_bool(rest.get(1..2).unwrap_or_default())?,
+                confirm_amount: match rest.get(2..).unwrap_or_default() {
+                    [] => None,
+                    amount => Some(u64::from_le_bytes(
+                        amount.try_into().map_err(|_| InvalidInstruction)?,
+                    )),
+                },
+            },
+            EscrowInstructionTag::SetFrozen => Self::SetFrozen {
+                frozen: Self::unpack_bool(rest)?,
+            },
+            EscrowInstructionTag::InitEscrowMulti => Self::InitEscrowMulti {
+                amount: Self::unpack_amount(rest)?,
+                mints: Self::unpack_mints(&rest[8..])?,
+            },
+            EscrowInstructionTag::BatchExchange => Self::BatchExchange {
+                amounts: Self::unpack_amounts(rest)?,
+            },
+            EscrowInstructionTag::SimulateExchange => Self::SimulateExchange {
                 amount: Self::unpack_amount(rest)?,
+                min_receive: Self::unpack_optional_amount(&rest[8..])?,
+            },
+            EscrowInstructionTag::SetExpiry => Self::SetExpiry {
+                expires_at: Self::unpack_amount(rest)? as i64,
             },
-            1 => Self::Exchange {
+            EscrowInstructionTag::TopUp => Self::TopUp {
                 amount: Self::unpack_amount(rest)?,
             },
-            2 => Self::CancelEscrow { amount: 0 },
-            _ => return Err(InvalidInstruction.into()),
+            EscrowInstructionTag::WithdrawPartial => Self::WithdrawPartial {
+                amount: Self::unpack_amount(rest)?,
+            },
+            EscrowInstructionTag::Migrate => Self::Migrate,
+            EscrowInstructionTag::SetRentFee => Self::SetRentFee {
+                rent_fee_bps: Self::unpack_bps(rest)?,
+            },
+            EscrowInstructionTag::TransferMaker => Self::TransferMaker {
+                new_maker: Self::unpack_pubkey(rest)?,
+            },
+            EscrowInstructionTag::Relist => Self::Relist {
+                amount: Self::unpack_amount(rest)?,
+            },
+            EscrowInstructionTag::LogState => Self::LogState,
+            EscrowInstructionTag::SetHook => Self::SetHook {
+                hook_program: Self::unpack_optional_nonce(rest)?.map(Pubkey::new_from_array),
+            },
+            EscrowInstructionTag::InitEscrowRatio => {
+                let price_num = Self::unpack_amount(rest)?;
+                let price_den = Self::unpack_amount(&rest[8..])?;
+                if price_num == 0 || price_den == 0 {
+                    return Err(InvalidInstruction.into());
+                }
+                Self::InitEscrowRatio {
+                    price_num,
+                    price_den,
+                }
+            }
+            EscrowInstructionTag::CancelAll => Self::CancelAll,
+            EscrowInstructionTag::InitEscrowOracle => {
+                let price_feed = Self::unpack_pubkey(rest.get(..32).unwrap_or_default())?;
+                let min_price = Self::unpack_amount(&rest[32..])?;
+                let max_price = Self::unpack_amount(&rest[40..])?;
+                if min_price == 0 || min_price > max_price || rest.len() != 48 {
+                    return Err(InvalidInstruction.into());
+                }
+                Self::InitEscrowOracle {
+                    price_feed,
+                    min_price,
+                    max_price,
+                }
+            }
+            EscrowInstructionTag::Admin => Self::Admin {
+                paused: Self::unpack_bool(rest)?,
+            },
+            EscrowInstructionTag::InitEscrowSplit => Self::InitEscrowSplit {
+                amount: Self::unpack_amount(rest)?,
+                splits: Self::unpack_splits(&rest[8..])?,
+            },
+            EscrowInstructionTag::InitEscrowScaled => Self::InitEscrowScaled {
+                amount: Self::unpack_amount(rest)?,
+                scale: Self::unpack_scale(&rest[8..])?,
+            },
+            EscrowInstructionTag::SetAuditClose => Self::SetAuditClose {
+                audit_close: Self::unpack_bool(rest)?,
+            },
+            EscrowInstructionTag::FinalizeClosed => Self::FinalizeClosed,
+            EscrowInstructionTag::PartialExchangePermille => Self::PartialExchangePermille {
+                permille: Self::unpack_permille(rest)?,
+            },
+            EscrowInstructionTag::ScanExpired => Self::ScanExpired {
+                reclaim: Self::unpack_bool(rest)?,
+            },
         })
     }
 
+    /// Serializes the instruction into the data `unpack` reads it from, optional
+    /// trailing fields left out when they hold their default
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![self.tag() as u8];
+        match self {
+            Self::InitEscrow {
+                amount,
+                min_fill,
+                nonce,
+                allow_same_mint,
+            } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.extend_from_slice(&min_fill.to_le_bytes());
+                if let Some(nonce) = nonce {
+                    data.extend_from_slice(nonce);
+                }
+                if *allow_same_mint {
+                    data.push(1);
+                }
+            }
+            Self::Exchange {
+                amount,
+                min_receive,
+                create_maker_receive,
+                wrap_sol,
+                quote_program,
+                memo,
+            } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.extend_from_slice(&min_receive.to_le_bytes());
+                let mut flags = 0;
+                if *create_maker_receive {
+                    flags |= EXCHANGE_CREATE_MAKER_RECEIVE;
+                }
+                if quote_program.is_some() {
+                    flags |= EXCHANGE_QUOTED_AMOUNT;
+                }
+                if *wrap_sol {
+                    flags |= EXCHANGE_WRAP_SOL;
+                }
+                data.push(flags);
+                if let Some(quote_program) = quote_program {
+                    data.extend_from_slice(quote_program.as_ref());
+                }
+                if let Some(memo) = memo {
+                    data.extend_from_slice(memo.as_bytes());
+                }
+            }
+            Self::CancelEscrow {
+                unwrap_sol,
+                keep_escrow,
+                confirm_amount,
+            } => {
+                data.extend_from_slice(&[*unwrap_sol as u8, *keep_escrow as u8]);
+                if let Some(confirm_amount) = confirm_amount {
+                    data.extend_from_slice(&confirm_amount.to_le_bytes());
+                }
+            }
+            Self::SetFrozen { frozen } => data.push(*frozen as u8),
+            Self::InitEscrowMulti { amount, mints } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.push(mints.len() as u8);
+                for mint in mints {
+                    data.extend_from_slice(mint.as_ref());
+                }
+            }
+            Self::BatchExchange { amounts } => {
+                data.push(amounts.len() as u8);
+                for amount in amounts {
+                    data.extend_from_slice(&amount.to_le_bytes());
+                }
+            }
+            Self::SimulateExchange {
+                amount,
+                min_receive,
+            } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.extend_from_slice(&min_receive.to_le_bytes());
+            }
+            Self::SetExpiry { expires_at } => data.extend_from_slice(&expires_at.to_le_bytes()),
+            Self::TopUp { amount } | Self::WithdrawPartial { amount } | Self::Relist { amount } => {
+                data.extend_from_slice(&amount.to_le_bytes())
+            }
+            Self::SetRentFee { rent_fee_bps } => {
+                data.extend_from_slice(&rent_fee_bps.to_le_bytes())
+            }
+            Self::TransferMaker { new_maker } => data.extend_from_slice(new_maker.as_ref()),
+            Self::SetHook { hook_program } => {
+                if let Some(hook_program) = hook_program {
+                    data.extend_from_slice(hook_program.as_ref());
+                }
+            }
+            Self::InitEscrowRatio {
+                price_num,
+                price_den,
+            } => {
+                data.extend_from_slice(&price_num.to_le_bytes());
+                data.extend_from_slice(&price_den.to_le_bytes());
+            }
+            Self::InitEscrowOracle {
+                price_feed,
+                min_price,
+                max_price,
+            } => {
+                data.extend_from_slice(price_feed.as_ref());
+                data.extend_from_slice(&min_price.to_le_bytes());
+                data.extend_from_slice(&max_price.to_le_bytes());
+            }
+            Self::Admin { paused } => data.push(*paused as u8),
+            Self::SetAuditClose { audit_close } => data.push(*audit_close as u8),
+            Self::ScanExpired { reclaim } => data.push(*reclaim as u8),
+            Self::InitEscrowSplit { amount, splits } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.push(splits.len() as u8);
+                for split in splits {
+                    data.extend_from_slice(split.account.as_ref());
+                    data.extend_from_slice(&split.bps.to_le_bytes());
+                }
+            }
+            Self::InitEscrowScaled { amount, scale } => {
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.push(*scale);
+            }
+            Self::PartialExchangePermille { permille } => {
+                data.extend_from_slice(&permille.to_le_bytes())
+            }
+            Self::Migrate | Self::LogState | Self::CancelAll | Self::FinalizeClosed => {}
+        }
+        data
+    }
+
+    /// The tag `unpack` reads this instruction from
+    pub fn tag(&self) -> EscrowInstructionTag {
+        match self {
+            Self::InitEscrow { .. } => EscrowInstructionTag::InitEscrow,
+            Self::Exchange { .. } => EscrowInstructionTag::Exchange,
+            Self::CancelEscrow { .. } => EscrowInstructionTag::CancelEscrow,
+            Self::SetFrozen { .. } => EscrowInstructionTag::SetFrozen,
+            Self::InitEscrowMulti { .. } => EscrowInstructionTag::InitEscrowMulti,
+            Self::BatchExchange { .. } => EscrowInstructionTag::BatchExchange,
+            Self::SimulateExchange { .. } => EscrowInstructionTag::SimulateExchange,
+            Self::SetExpiry { .. } => EscrowInstructionTag::SetExpiry,
+            Self::TopUp { .. } => EscrowInstructionTag::TopUp,
+            Self::WithdrawPartial { .. } => EscrowInstructionTag::WithdrawPartial,
+            Self::Migrate => EscrowInstructionTag::Migrate,
+            Self::SetRentFee { .. } => EscrowInstructionTag::SetRentFee,
+            Self::TransferMaker { .. } => EscrowInstructionTag::TransferMaker,
+            Self::Relist { .. } => EscrowInstructionTag::Relist,
+            Self::LogState => EscrowInstructionTag::LogState,
+            Self::SetHook { .. } => EscrowInstructionTag::SetHook,
+            Self::InitEscrowRatio { .. } => EscrowInstructionTag::InitEscrowRatio,
+            Self::CancelAll => EscrowInstructionTag::CancelAll,
+            Self::InitEscrowOracle { .. } => EscrowInstructionTag::InitEscrowOracle,
+            Self::Admin { .. } => EscrowInstructionTag::Admin,
+            Self::InitEscrowSplit { .. } => EscrowInstructionTag::InitEscrowSplit,
+            Self::InitEscrowScaled { .. } => EscrowInstructionTag::InitEscrowScaled,
+            Self::SetAuditClose { .. } => EscrowInstructionTag::SetAuditClose,
+            Self::FinalizeClosed => EscrowInstructionTag::FinalizeClosed,
+            Self::PartialExchangePermille { .. } => EscrowInstructionTag::PartialExchangePermille,
+            Self::ScanExpired { .. } => EscrowInstructionTag::ScanExpired,
+        }
+    }
+
+    /// Reads the first 8 bytes as a little-endian `u64`, whatever the host's byte order
     fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
         let amount = input
             .get(..8)
@@ -80,4 +927,1187 @@ impl EscrowInstruction {
             .ok_or(InvalidInstruction)?;
         Ok(amount)
     }
+
+    fn unpack_optional_amount(input: &[u8]) -> Result<u64, ProgramError> {
+        if input.is_empty() {
+            return Ok(0);
+        }
+        Self::unpack_amount(input)
+    }
+
+    fn unpack_optional_nonce(input: &[u8]) -> Result<Option<[u8; 32]>, ProgramError> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let nonce = input.try_into().map_err(|_| InvalidInstruction)?;
+        Ok(Some(nonce))
+    }
+
+    /// Both optional, the nonce takes 32 bytes and the flag after it 1, so either
+    /// can come alone
+    fn unpack_nonce_and_flag(input: &[u8]) -> Result<(Option<[u8; 32]>, bool), ProgramError> {
+        let (nonce, flag) = match input.len() {
+            0 | 1 => (&[][..], input),
+            len => input.split_at(len.min(32)),
+        };
+        Ok((
+            Self::unpack_optional_nonce(nonce)?,
+            Self::unpack_optional_bool(flag)?,
+        ))
+    }
+
+    fn unpack_mints(input: &[u8]) -> Result<Vec<Pubkey>, ProgramError> {
+        let (len, rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let len = *len as usize;
+        if len == 0 || len > MAX_ACCEPTED_MINTS || rest.len() != len * 32 {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(rest.chunks_exact(32).map(Pubkey::new).collect())
+    }
+
+    fn unpack_splits(input: &[u8]) -> Result<Vec<MakerReceiveSplit>, ProgramError> {
+        let (len, rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let len = *len as usize;
+        if len == 0 || len > MAX_RECEIVE_SPLITS || rest.len() != len * 34 {
+            return Err(InvalidInstruction.into());
+        }
+        let splits: Vec<_> = rest
+            .chunks_exact(34)
+            .map(|split| MakerReceiveSplit {
+                account: Pubkey::new(&split[..32]),
+                bps: u16::from_le_bytes([split[32], split[33]]),
+            })
+            .collect();
+        let total: u32 = splits.iter().map(|split| split.bps as u32).sum();
+        if splits.iter().any(|split| split.bps == 0) || total != MAX_BPS as u32 {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(splits)
+    }
+
+    fn unpack_amounts(input: &[u8]) -> Result<Vec<u64>, ProgramError> {
+        let (len, rest) = input.split_first().ok_or(InvalidInstruction)?;
+        let len = *len as usize;
+        if len == 0 || len > MAX_BATCH_FILLS || rest.len() != len * 8 {
+            return Err(InvalidInstruction.into());
+        }
+        rest.chunks_exact(8).map(Self::unpack_amount).collect()
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<Pubkey, ProgramError> {
+        if input.len() != 32 {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(Pubkey::new(input))
+    }
+
+    fn unpack_bps(input: &[u8]) -> Result<u16, ProgramError> {
+        let bps = input
+            .try_into()
+            .map(u16::from_le_bytes)
+            .map_err(|_| InvalidInstruction)?;
+        if bps > MAX_BPS {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(bps)
+    }
+
+    fn unpack_permille(input: &[u8]) -> Result<u16, ProgramError> {
+        let permille = input
+            .try_into()
+            .map(u16::from_le_bytes)
+            .map_err(|_| InvalidInstruction)?;
+        if permille > MAX_PERMILLE {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(permille)
+    }
+
+    fn unpack_scale(input: &[u8]) -> Result<u8, ProgramError> {
+        match input {
+            [scale] => Ok(*scale),
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_optional_bool(input: &[u8]) -> Result<bool, ProgramError> {
+        match input {
+            [] => Ok(false),
+            [_] => Self::unpack_bool(input),
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_memo(input: &[u8]) -> Result<Option<String>, ProgramError> {
+        if input.len() > MAX_MEMO_LEN {
+            return Err(EscrowError::MemoTooLong.into());
+        }
+        match std::str::from_utf8(input) {
+            Ok("") => Ok(None),
+            Ok(memo) => Ok(Some(memo.to_string())),
+            Err(_) => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_bool(input: &[u8]) -> Result<bool, ProgramError> {
+        match input.first() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+}
+
+/// The keys and amounts `build_init_escrow_transaction` opens an escrow with
+pub struct InitEscrowTransaction {
+    pub maker: Pubkey,
+    /// A new keypair's address, the temp token account is created there
+    pub temp_token_account: Pubkey,
+    /// The maker's token account the deposit comes from
+    pub maker_source: Pubkey,
+    /// The mint of the deposit
+    pub mint: Pubkey,
+    pub maker_receive: Pubkey,
+    pub deposit: u64,
+    pub expected_amount: u64,
+    /// Picks the escrow's address, see `find_escrow_address`
+    pub nonce: [u8; 32],
+}
+
+/// Assembles everything opening an escrow takes, in order: creating the temp token
+/// account, initializing it, moving the deposit in and `InitEscrow`.
+///
+/// The transaction has to be signed by the maker, who pays for both accounts, and
+/// by the temp token account's keypair. The program creates the escrow at the
+/// address derived from the nonce, returned along with the instructions.
+pub fn build_init_escrow_transaction(
+    program_id: &Pubkey,
+    init: &InitEscrowTransaction,
+) -> Result<(Vec<Instruction>, Pubkey), ProgramError> {
+    let (escrow, _bump_seed) = find_escrow_address(program_id, &init.maker, &init.nonce);
+    let temp_len = spl_token::state::Account::LEN;
+
+    let data = EscrowInstruction::InitEscrow {
+        amount: init.expected_amount,
+        min_fill: 0,
+        nonce: Some(init.nonce),
+        allow_same_mint: false,
+    }
+    .pack();
+    let init_escrow = Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(init.maker, true),
+            AccountMeta::new(init.temp_token_account, false),
+            AccountMeta::new_readonly(init.maker_receive, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    };
+
+    let instructions = vec![
+        system_instruction::create_account(
+            &init.maker,
+            &init.temp_token_account,
+            Rent::default().minimum_balance(temp_len),
+            temp_len as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &init.temp_token_account,
+            &init.mint,
+            &init.maker,
+        )?,
+        spl_token::instruction::transfer(
+            &spl_token::id(),
+            &init.maker_source,
+            &init.temp_token_account,
+            &init.maker,
+            &[],
+            init.deposit,
+        )?,
+        init_escrow,
+    ];
+    Ok((instructions, escrow))
+}
+
+/// The accounts `build_exchange_instruction` fills an escrow with
+pub struct ExchangeKeys {
+    pub taker: Pubkey,
+    /// The taker's token account paying the maker
+    pub taker_token_sent: Pubkey,
+    /// The taker's token account getting the temp tokens
+    pub taker_token_to_receive: Pubkey,
+    pub temp_token_account: Pubkey,
+    pub maker: Pubkey,
+    pub maker_receive: Pubkey,
+    pub escrow: Pubkey,
+}
+
+/// An `Exchange` of up to `amount` temp tokens, with the accounts in the order the
+/// program reads them
+pub fn build_exchange_instruction(
+    program_id: &Pubkey,
+    keys: &ExchangeKeys,
+    amount: u64,
+    min_receive: u64,
+) -> Instruction {
+    let data = EscrowInstruction::Exchange {
+        amount,
+        min_receive,
+        create_maker_receive: false,
+        wrap_sol: false,
+        quote_program: None,
+        memo: None,
+    }
+    .pack();
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(keys.taker, true),
+            AccountMeta::new(keys.taker_token_sent, false),
+            AccountMeta::new(keys.taker_token_to_receive, false),
+            AccountMeta::new(keys.temp_token_account, false),
+            AccountMeta::new(keys.maker, false),
+            AccountMeta::new(keys.maker_receive, false),
+            AccountMeta::new(keys.escrow, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(find_escrow_authority(program_id).0, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+        data,
+    }
+}
+
+/// The accounts `build_cancel_instruction` cancels an escrow with
+pub struct CancelKeys {
+    pub maker: Pubkey,
+    /// Any of the maker's token accounts in the temp token's mint
+    pub maker_refund: Pubkey,
+    pub temp_token_account: Pubkey,
+    pub escrow: Pubkey,
+}
+
+/// A `CancelEscrow` signed by the maker, refunding the temp tokens and closing
+/// the escrow
+pub fn build_cancel_instruction(program_id: &Pubkey, keys: &CancelKeys) -> Instruction {
+    let data = EscrowInstruction::CancelEscrow {
+        unwrap_sol: false,
+        keep_escrow: false,
+        confirm_amount: None,
+    }
+    .pack();
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(keys.maker, true),
+            AccountMeta::new(keys.maker_refund, false),
+            AccountMeta::new(keys.temp_token_account, false),
+            AccountMeta::new(keys.escrow, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(find_escrow_authority(program_id).0, false),
+        ],
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unpack_amount_little_endian() {
+        assert_eq!(
+            EscrowInstruction::unpack_amount(&[1, 0, 0, 0, 0, 0, 0, 0]),
+            Ok(1)
+        );
+        assert_eq!(
+            EscrowInstruction::unpack_amount(&[0, 0, 0, 0, 0, 0, 0, 1]),
+            Ok(1 << 56)
+        );
+        assert_eq!(
+            EscrowInstruction::unpack_amount(&[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]),
+            Ok(0x0102_0304_0506_0708)
+        );
+
+        for amount in [0, 1, u64::MAX] {
+            let data = [&[8][..], &amount.to_le_bytes()].concat();
+            assert_eq!(
+                EscrowInstruction::unpack(&data).unwrap(),
+                EscrowInstruction::TopUp { amount }
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_exchange_min_receive() {
+        let mut data = vec![1];
+        data.extend_from_slice(&100u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 0,
+                create_maker_receive: false,
+                wrap_sol: false,
+                quote_program: None,
+                memo: None
+            }
+        );
+
+        data.extend_from_slice(&90u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 90,
+                create_maker_receive: false,
+                wrap_sol: false,
+                quote_program: None,
+                memo: None
+            }
+        );
+
+        data.push(1);
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 90,
+                create_maker_receive: true,
+                wrap_sol: false,
+                quote_program: None,
+                memo: None
+            }
+        );
+
+        data.extend_from_slice(b"order 42");
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 90,
+                create_maker_receive: true,
+                wrap_sol: false,
+                quote_program: None,
+                memo: Some("order 42".to_string())
+            }
+        );
+        data.push(0xff);
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+        data.truncate(18);
+        data.extend_from_slice(&[b'a'; MAX_MEMO_LEN + 1]);
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(EscrowError::MemoTooLong.into())
+        );
+
+        // flags the instruction doesn't know
+        data.truncate(17);
+        data.push(EXCHANGE_WRAP_SOL << 1);
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+
+        // a cut off min_receive
+        data.truncate(16);
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_exchange_quoted_amount() {
+        let mut data = vec![1];
+        data.extend_from_slice(&100u64.to_le_bytes());
+        data.extend_from_slice(&90u64.to_le_bytes());
+        data.push(EXCHANGE_QUOTED_AMOUNT | EXCHANGE_CREATE_MAKER_RECEIVE);
+        data.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 90,
+                create_maker_receive: true,
+                wrap_sol: false,
+                quote_program: Some(Pubkey::new_from_array([7; 32])),
+                memo: None
+            }
+        );
+
+        data.extend_from_slice(b"order 42");
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 90,
+                create_maker_receive: true,
+                wrap_sol: false,
+                quote_program: Some(Pubkey::new_from_array([7; 32])),
+                memo: Some("order 42".to_string())
+            }
+        );
+
+        // a cut off quote program
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..17 + 1 + 31]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let key = Pubkey::new_from_array([7; 32]);
+        let mut instructions = Vec::new();
+        for amount in [0, 1, u64::MAX] {
+            instructions.extend([
+                EscrowInstruction::InitEscrow {
+                    amount,
+                    min_fill: amount,
+                    nonce: None,
+                    allow_same_mint: false,
+                },
+                EscrowInstruction::InitEscrow {
+                    amount,
+                    min_fill: 0,
+                    nonce: Some([7; 32]),
+                    allow_same_mint: true,
+                },
+                EscrowInstruction::InitEscrow {
+                    amount,
+                    min_fill: 0,
+                    nonce: None,
+                    allow_same_mint: true,
+                },
+                EscrowInstruction::Exchange {
+                    amount,
+                    min_receive: amount,
+                    create_maker_receive: false,
+                    wrap_sol: false,
+                    quote_program: None,
+                    memo: None,
+                },
+                EscrowInstruction::Exchange {
+                    amount,
+                    min_receive: 0,
+                    create_maker_receive: true,
+                    wrap_sol: true,
+                    quote_program: Some(key),
+                    memo: Some("order 42".to_string()),
+                },
+                EscrowInstruction::Exchange {
+                    amount,
+                    min_receive: 0,
+                    create_maker_receive: false,
+                    wrap_sol: false,
+                    quote_program: None,
+                    memo: Some("order 42".to_string()),
+                },
+                EscrowInstruction::InitEscrowMulti {
+                    amount,
+                    mints: vec![key; MAX_ACCEPTED_MINTS],
+                },
+                EscrowInstruction::BatchExchange {
+                    amounts: vec![amount; MAX_BATCH_FILLS],
+                },
+                EscrowInstruction::SimulateExchange {
+                    amount,
+                    min_receive: amount,
+                },
+                EscrowInstruction::SetExpiry {
+                    expires_at: amount as i64,
+                },
+                EscrowInstruction::TopUp { amount },
+                EscrowInstruction::WithdrawPartial { amount },
+                EscrowInstruction::Relist { amount },
+                EscrowInstruction::InitEscrowRatio {
+                    price_num: amount.max(1),
+                    price_den: amount.max(1),
+                },
+                EscrowInstruction::InitEscrowOracle {
+                    price_feed: key,
+                    min_price: amount.max(1),
+                    max_price: amount.max(1),
+                },
+                EscrowInstruction::InitEscrowSplit {
+                    amount,
+                    splits: vec![
+                        MakerReceiveSplit {
+                            account: key,
+                            bps: 7_000,
+                        },
+                        MakerReceiveSplit {
+                            account: Pubkey::new_unique(),
+                            bps: 3_000,
+                        },
+                    ],
+                },
+                EscrowInstruction::InitEscrowScaled {
+                    amount,
+                    scale: amount as u8,
+                },
+            ]);
+        }
+        for flag in [false, true] {
+            instructions.extend([
+                EscrowInstruction::CancelEscrow {
+                    unwrap_sol: flag,
+                    keep_escrow: !flag,
+                    confirm_amount: flag.then_some(100),
+                },
+                EscrowInstruction::SetFrozen { frozen: flag },
+                EscrowInstruction::Admin { paused: flag },
+                EscrowInstruction::SetAuditClose { audit_close: flag },
+                EscrowInstruction::ScanExpired { reclaim: flag },
+            ]);
+        }
+        instructions.extend([
+            EscrowInstruction::Migrate,
+            EscrowInstruction::SetRentFee { rent_fee_bps: 0 },
+            EscrowInstruction::SetRentFee {
+                rent_fee_bps: MAX_BPS,
+            },
+            EscrowInstruction::TransferMaker { new_maker: key },
+            EscrowInstruction::LogState,
+            EscrowInstruction::SetHook { hook_program: None },
+            EscrowInstruction::SetHook {
+                hook_program: Some(key),
+            },
+            EscrowInstruction::CancelAll,
+            EscrowInstruction::FinalizeClosed,
+            EscrowInstruction::PartialExchangePermille { permille: 0 },
+            EscrowInstruction::PartialExchangePermille {
+                permille: MAX_PERMILLE,
+            },
+        ]);
+
+        let mut tags: Vec<_> = instructions.iter().map(|ix| ix.tag() as u8).collect();
+        tags.sort_unstable();
+        tags.dedup();
+        assert_eq!(tags, (0..26).collect::<Vec<_>>(), "every variant");
+        for instruction in instructions {
+            assert_eq!(
+                EscrowInstruction::unpack(&instruction.pack()).as_ref(),
+                Ok(&instruction)
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_init_escrow_split() {
+        let split = |bps: u16| [&[7; 32][..], &bps.to_le_bytes()].concat();
+        let data = |splits: &[Vec<u8>]| {
+            [
+                &[20][..],
+                &500u64.to_le_bytes(),
+                &[splits.len() as u8],
+                &splits.concat(),
+            ]
+            .concat()
+        };
+        assert_eq!(
+            EscrowInstruction::unpack(&data(&[split(7_000), split(3_000)])).unwrap(),
+            EscrowInstruction::InitEscrowSplit {
+                amount: 500,
+                splits: vec![
+                    MakerReceiveSplit {
+                        account: Pubkey::new_from_array([7; 32]),
+                        bps: 7_000
+                    },
+                    MakerReceiveSplit {
+                        account: Pubkey::new_from_array([7; 32]),
+                        bps: 3_000
+                    },
+                ]
+            }
+        );
+
+        // the shares have to add up to the whole, none of them 0
+        for splits in [
+            vec![split(7_000), split(2_999)],
+            vec![split(7_000), split(3_001)],
+            vec![split(MAX_BPS), split(0)],
+            vec![],
+            vec![split(2_000); MAX_RECEIVE_SPLITS + 1],
+        ] {
+            assert_eq!(
+                EscrowInstruction::unpack(&data(&splits)),
+                Err(InvalidInstruction.into())
+            );
+        }
+        let mut cut_off = data(&[split(MAX_BPS)]);
+        cut_off.pop();
+        assert_eq!(
+            EscrowInstruction::unpack(&cut_off),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_cancel() {
+        assert_eq!(
+            EscrowInstruction::unpack(&[2]).unwrap(),
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: false,
+                keep_escrow: false,
+                confirm_amount: None
+            }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[2, 1]).unwrap(),
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: true,
+                keep_escrow: false,
+                confirm_amount: None
+            }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[2, 0, 1]).unwrap(),
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: false,
+                keep_escrow: true,
+                confirm_amount: None
+            }
+        );
+        let mut data = vec![2, 0, 1];
+        data.extend_from_slice(&100u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: false,
+                keep_escrow: true,
+                confirm_amount: Some(100)
+            }
+        );
+        for data in [&[2, 2][..], &[2, 0, 2], &[2, 0, 0, 0], &data[..10]] {
+            assert_eq!(
+                EscrowInstruction::unpack(data),
+                Err(InvalidInstruction.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_init_escrow_multi() {
+        let mints = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut data = vec![4];
+        data.extend_from_slice(&500u64.to_le_bytes());
+        data.push(2);
+        data.extend_from_slice(mints[0].as_ref());
+        data.extend_from_slice(mints[1].as_ref());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrowMulti { amount: 500, mints }
+        );
+
+        data[9] = 3;
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_batch_exchange() {
+        let mut data = vec![5, 2];
+        data.extend_from_slice(&100u64.to_le_bytes());
+        data.extend_from_slice(&200u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::BatchExchange {
+                amounts: vec![100, 200]
+            }
+        );
+
+        data.pop();
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[5, 0]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_simulate_exchange() {
+        assert_eq!(
+            EscrowInstruction::unpack(&[6, 100, 0, 0, 0, 0, 0, 0, 0]).unwrap(),
+            EscrowInstruction::SimulateExchange {
+                amount: 100,
+                min_receive: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_unpack_init_escrow_min_fill() {
+        let mut data = vec![0];
+        data.extend_from_slice(&500u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 0,
+                nonce: None,
+                allow_same_mint: false
+            }
+        );
+
+        data.extend_from_slice(&50u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 50,
+                nonce: None,
+                allow_same_mint: false
+            }
+        );
+
+        data.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 50,
+                nonce: Some([7; 32]),
+                allow_same_mint: false
+            }
+        );
+
+        data.push(1);
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 50,
+                nonce: Some([7; 32]),
+                allow_same_mint: true
+            }
+        );
+        // the flag alone, without a nonce
+        let mut flag_only = data[..17].to_vec();
+        flag_only.push(1);
+        assert_eq!(
+            EscrowInstruction::unpack(&flag_only).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 50,
+                nonce: None,
+                allow_same_mint: true
+            }
+        );
+
+        data.truncate(48);
+        data.pop();
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        let amount = [1; 8];
+        let payloads: [&[u8]; 26] = [
+            &amount,
+            &amount,
+            &[],
+            &[1],
+            &[&amount[..], &[1], &[7; 32]].concat(),
+            &[&[1], &amount[..]].concat(),
+            &amount,
+            &amount,
+            &amount,
+            &amount,
+            &[],
+            &[0, 0],
+            &[7; 32],
+            &amount,
+            &[],
+            &[],
+            &[amount, amount].concat(),
+            &[],
+            &[&[7; 32][..], &amount, &amount].concat(),
+            &[1],
+            &[&amount[..], &[1], &[7; 32], &MAX_BPS.to_le_bytes()].concat(),
+            &[&amount[..], &[6]].concat(),
+            &[1],
+            &[],
+            &500u16.to_le_bytes(),
+            &[1],
+        ];
+        for (tag, payload) in payloads.iter().enumerate() {
+            let data = [&[tag as u8], *payload].concat();
+            let instruction = EscrowInstruction::unpack(&data).unwrap();
+            assert_eq!(instruction.tag() as u8, data[0]);
+            assert_eq!(
+                EscrowInstructionTag::try_from(data[0]),
+                Ok(instruction.tag())
+            );
+        }
+        assert_eq!(
+            EscrowInstructionTag::try_from(payloads.len() as u8),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_build_init_escrow_transaction() {
+        let program_id = Pubkey::new_unique();
+        let init = InitEscrowTransaction {
+            maker: Pubkey::new_unique(),
+            temp_token_account: Pubkey::new_unique(),
+            maker_source: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            maker_receive: Pubkey::new_unique(),
+            deposit: 100,
+            expected_amount: 500,
+            nonce: [7; 32],
+        };
+        let (instructions, escrow) = build_init_escrow_transaction(&program_id, &init).unwrap();
+        assert_eq!(
+            escrow,
+            find_escrow_address(&program_id, &init.maker, &init.nonce).0
+        );
+
+        let program_ids: Vec<_> = instructions.iter().map(|ix| ix.program_id).collect();
+        assert_eq!(
+            program_ids,
+            [
+                system_program::id(),
+                spl_token::id(),
+                spl_token::id(),
+                program_id
+            ]
+        );
+        // the temp account's keypair signs its creation, the maker everything else
+        assert!(instructions[0].accounts[1].is_signer);
+        assert!(instructions[1..]
+            .iter()
+            .flat_map(|ix| &ix.accounts)
+            .filter(|meta| meta.is_signer)
+            .all(|meta| meta.pubkey == init.maker));
+        assert!(instructions[3].accounts[0].is_signer);
+
+        assert_eq!(
+            EscrowInstruction::unpack(&instructions[3].data).unwrap(),
+            EscrowInstruction::InitEscrow {
+                amount: 500,
+                min_fill: 0,
+                nonce: Some([7; 32]),
+                allow_same_mint: false
+            }
+        );
+        assert_eq!(instructions[3].accounts.len(), INIT_ESCROW_ACCOUNTS + 1);
+        assert_eq!(instructions[3].accounts[3].pubkey, escrow);
+        assert_eq!(
+            instructions[3].accounts[6].pubkey,
+            find_config_address(&program_id).0
+        );
+    }
+
+    /// The processor reads accounts by position, the order is part of the
+    /// interface. `processor::test::test_built_exchange_and_cancel` runs these
+    #[test]
+    fn test_account_order() {
+        let program_id = Pubkey::new_unique();
+        let key = |_| Pubkey::new_unique();
+        let keys = |instruction: &Instruction| -> Vec<_> {
+            instruction
+                .accounts
+                .iter()
+                .map(|meta| meta.pubkey)
+                .collect()
+        };
+        let pda = find_escrow_authority(&program_id).0;
+        let config = find_config_address(&program_id).0;
+
+        let init = InitEscrowTransaction {
+            maker: key(0),
+            temp_token_account: key(1),
+            maker_source: key(2),
+            mint: key(3),
+            maker_receive: key(4),
+            deposit: 100,
+            expected_amount: 500,
+            nonce: [7; 32],
+        };
+        let (instructions, escrow) = build_init_escrow_transaction(&program_id, &init).unwrap();
+        assert_eq!(
+            keys(&instructions[3]),
+            [
+                init.maker,
+                init.temp_token_account,
+                init.maker_receive,
+                escrow,
+                spl_token::id(),
+                sysvar::clock::id(),
+                config,
+                system_program::id(),
+            ]
+        );
+
+        let exchange = ExchangeKeys {
+            taker: key(0),
+            taker_token_sent: key(1),
+            taker_token_to_receive: key(2),
+            temp_token_account: key(3),
+            maker: key(4),
+            maker_receive: key(5),
+            escrow: key(6),
+        };
+        let instruction = build_exchange_instruction(&program_id, &exchange, 100, 0);
+        assert_eq!(
+            keys(&instruction),
+            [
+                exchange.taker,
+                exchange.taker_token_sent,
+                exchange.taker_token_to_receive,
+                exchange.temp_token_account,
+                exchange.maker,
+                exchange.maker_receive,
+                exchange.escrow,
+                spl_token::id(),
+                pda,
+                config,
+            ]
+        );
+        assert_eq!(instruction.accounts.len(), EXCHANGE_ACCOUNTS);
+        assert!(instruction.accounts[0].is_signer);
+
+        let cancel = CancelKeys {
+            maker: key(0),
+            maker_refund: key(1),
+            temp_token_account: key(2),
+            escrow: key(3),
+        };
+        let instruction = build_cancel_instruction(&program_id, &cancel);
+        assert_eq!(
+            keys(&instruction),
+            [
+                cancel.maker,
+                cancel.maker_refund,
+                cancel.temp_token_account,
+                cancel.escrow,
+                spl_token::id(),
+                pda,
+            ]
+        );
+        assert_eq!(instruction.accounts.len(), CANCEL_ESCROW_ACCOUNTS);
+        assert!(instruction.accounts[0].is_signer);
+    }
+
+    #[test]
+    fn test_unpack_admin() {
+        assert_eq!(
+            EscrowInstruction::unpack(&[19, 1]).unwrap(),
+            EscrowInstruction::Admin { paused: true }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[19, 0]).unwrap(),
+            EscrowInstruction::Admin { paused: false }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[19]),
+            Err(InvalidInstruction.into())
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[19, 2]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_init_escrow_oracle() {
+        let mut data = vec![18];
+        data.extend_from_slice(&[7; 32]);
+        data.extend_from_slice(&90u64.to_le_bytes());
+        data.extend_from_slice(&110u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrowOracle {
+                price_feed: Pubkey::new_from_array([7; 32]),
+                min_price: 90,
+                max_price: 110
+            }
+        );
+
+        // bounds the wrong way round
+        data[33..41].copy_from_slice(&120u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+        data[33..41].copy_from_slice(&0u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(InvalidInstruction.into())
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..40]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_set_expiry() {
+        let mut data = vec![7];
+        data.extend_from_slice(&(-5i64).to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::SetExpiry { expires_at: -5 }
+        );
+
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..8]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_top_up() {
+        let mut data = vec![8];
+        data.extend_from_slice(&50u64.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::TopUp { amount: 50 }
+        );
+
+        data[0] = 9;
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::WithdrawPartial { amount: 50 }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..4]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_migrate() {
+        assert_eq!(
+            EscrowInstruction::unpack(&[10]).unwrap(),
+            EscrowInstruction::Migrate
+        );
+    }
+
+    #[test]
+    fn test_unpack_set_rent_fee() {
+        let mut data = vec![11];
+        data.extend_from_slice(&250u16.to_le_bytes());
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::SetRentFee { rent_fee_bps: 250 }
+        );
+
+        let over = [&[11][..], &(MAX_BPS + 1).to_le_bytes()].concat();
+        for data in [&data[..2], &[11, 250, 0, 0], &over[..]] {
+            assert_eq!(
+                EscrowInstruction::unpack(data),
+                Err(InvalidInstruction.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_partial_exchange_permille() {
+        let data = [&[24][..], &500u16.to_le_bytes()].concat();
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::PartialExchangePermille { permille: 500 }
+        );
+
+        let over = [&[24][..], &(MAX_PERMILLE + 1).to_le_bytes()].concat();
+        for data in [&data[..2], &[24, 244, 1, 0], &over[..]] {
+            assert_eq!(
+                EscrowInstruction::unpack(data),
+                Err(InvalidInstruction.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_transfer_maker() {
+        let new_maker = Pubkey::new_unique();
+        let data = [&[12][..], new_maker.as_ref()].concat();
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::TransferMaker { new_maker }
+        );
+
+        for data in [&data[..32], &[&data[..], &[0]].concat()] {
+            assert_eq!(
+                EscrowInstruction::unpack(data),
+                Err(InvalidInstruction.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_relist() {
+        let data = [&[13][..], &100u64.to_le_bytes()].concat();
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::Relist { amount: 100 }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..8]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_set_hook() {
+        let hook_program = Pubkey::new_unique();
+        let data = [&[15][..], hook_program.as_ref()].concat();
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::SetHook {
+                hook_program: Some(hook_program)
+            }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&[15]).unwrap(),
+            EscrowInstruction::SetHook { hook_program: None }
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&data[..32]),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_init_ratio() {
+        let data = [&[16][..], &3u64.to_le_bytes(), &2u64.to_le_bytes()].concat();
+        assert_eq!(
+            EscrowInstruction::unpack(&data).unwrap(),
+            EscrowInstruction::InitEscrowRatio {
+                price_num: 3,
+                price_den: 2
+            }
+        );
+
+        let zero_den = [&[16][..], &3u64.to_le_bytes(), &0u64.to_le_bytes()].concat();
+        let zero_num = [&[16][..], &0u64.to_le_bytes(), &2u64.to_le_bytes()].concat();
+        for data in [&data[..12], &zero_den, &zero_num] {
+            assert_eq!(
+                EscrowInstruction::unpack(data),
+                Err(InvalidInstruction.into())
+            );
+        }
+    }
 }
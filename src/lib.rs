@@ -1,5 +1,43 @@
+// Client crates only need the types, `no-entrypoint` leaves the program out
+pub mod config;
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
-mod error;
-mod instruction;
+pub mod error;
+pub mod event;
+pub mod instruction;
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod processor;
-mod state;
+pub mod state;
+// Off by default, tests outside the crate turn it on to reach the processor's checks
+#[cfg(feature = "test-internals")]
+pub mod validation;
+#[cfg(not(any(feature = "test-internals", feature = "no-entrypoint")))]
+mod validation;
+
+/// Run with `cargo test --features no-entrypoint` to check the client-only build
+#[cfg(all(test, feature = "no-entrypoint"))]
+mod test {
+    use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+    use solana_program::program_pack::Pack;
+
+    #[test]
+    fn test_client_types_without_program() {
+        assert_eq!(
+            EscrowInstruction::unpack(&[2]).unwrap(),
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: false,
+                keep_escrow: false,
+                confirm_amount: None
+            }
+        );
+        assert!(
+            !Escrow::unpack_unchecked(&[0; Escrow::LEN])
+                .unwrap()
+                .is_initialized
+        );
+        assert_eq!(
+            EscrowError::from_u32(0),
+            Some(EscrowError::InvalidInstruction)
+        );
+    }
+}
@@ -1,21 +1,118 @@
 use solana_program::account_info::next_account_info;
-use solana_program::program::invoke_signed;
+use solana_program::program::{get_return_data, invoke_signed};
 use solana_program::program_error::ProgramError;
 use solana_program::{
     account_info::AccountInfo,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
     program::invoke,
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction, system_program,
+    sysvar::{self, clock::Clock, rent::Rent, Sysvar},
 };
-use spl_token::state::Account;
+use spl_associated_token_account::{create_associated_token_account, get_associated_token_address};
+use spl_token::state::{Account, Mint};
+use std::{cmp::Ordering, convert::TryInto};
 
-use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+use crate::{
+    config::{
+        ADMIN_ACCOUNTS, BATCH_EXCHANGE_ACCOUNTS, BATCH_FILL_ACCOUNTS, CANCEL_ALL_ACCOUNTS,
+        CANCEL_ALL_ESCROW_ACCOUNTS, CANCEL_ESCROW_ACCOUNTS, CONFIG_SEED, ESCROW_SEED,
+        EXCHANGE_ACCOUNTS, FINALIZE_CLOSED_ACCOUNTS, INIT_ESCROW_ACCOUNTS, LOG_STATE_ACCOUNTS,
+        MAX_ESCROW_AMOUNT, MIGRATE_ACCOUNTS, RELIST_ACCOUNTS, SCAN_EXPIRED_ACCOUNTS,
+        SCAN_EXPIRED_RECLAIM_ACCOUNTS, SET_AUDIT_CLOSE_ACCOUNTS, SET_EXPIRY_ACCOUNTS,
+        SET_FROZEN_ACCOUNTS, SET_HOOK_ACCOUNTS, SET_RENT_FEE_ACCOUNTS, TOP_UP_ACCOUNTS,
+        TRANSFER_MAKER_ACCOUNTS, WITHDRAW_PARTIAL_ACCOUNTS,
+    },
+    error::{EscrowError, EscrowResult},
+    event::{EscrowEvent, ExchangeReceipt, SettlementHook},
+    instruction::{EscrowInstruction, MEMO_PROGRAM_ID},
+    state::{
+        find_config_address, find_escrow_address, find_escrow_authority, Config, Escrow,
+        MakerReceiveAccount, MakerReceiveSplit, TempTokenAccount,
+    },
+    validation::{
+        check_amounts_within, check_maker_mint, check_temp_token, fill_amounts, maker_share,
+        oracle_price, permille_share, ratio_fill_amounts, ratio_share, rent_split, scaled_amount,
+        split_payouts, topped_up_expected,
+    },
+};
 
 pub struct Processor;
 
+/// The escrow state together with what every handler derives from it
+struct LoadedEscrow {
+    escrow: Escrow,
+    temp_token: Account,
+    pda: Pubkey,
+    bump_seed: u8,
+}
+
+/// A cancel past all its checks, from `validate_cancel` for `execute_cancel`
+struct CancelPlan<'a, 'b> {
+    owner: &'b AccountInfo<'a>,
+    refund_acc: &'b AccountInfo<'a>,
+    temp_acc: &'b AccountInfo<'a>,
+    escrow_acc: &'b AccountInfo<'a>,
+    token_program: &'b AccountInfo<'a>,
+    pda_acc: &'b AccountInfo<'a>,
+    refund: Account,
+    loaded: LoadedEscrow,
+    unwrap_sol: bool,
+    keep_escrow: bool,
+}
+
+/// What an escrow is set up with, shared by the `InitEscrow` variants
+#[derive(Default)]
+struct InitTerms<'m> {
+    amount: u64,
+    min_fill: u64,
+    nonce: Option<[u8; 32]>,
+    accepted_mints: &'m [Pubkey],
+    // Prices by ratio, `amount` is left out then
+    price: Option<(u64, u64)>,
+    // The price feed and its bounds, the ratio comes from the feed then
+    oracle: Option<(Pubkey, u64, u64)>,
+    // Shares of the maker's payment, the first is the token account to receive
+    receive_splits: &'m [MakerReceiveSplit],
+    // `amount` is in units of `10^scale`
+    scale: u8,
+    allow_same_mint: bool,
+}
+
+/// How the taker fills, shared by the exchange instructions
+#[derive(Default)]
+struct ExchangeTerms<'m> {
+    amount_expected: u64,
+    min_receive: u64,
+    create_maker_receive: bool,
+    // The taker pays in SOL, wrapped for the fill, see `EXCHANGE_WRAP_SOL`
+    wrap_sol: bool,
+    memo: Option<&'m str>,
+    // Stops before any transfer, see `fill_escrow`
+    simulate: bool,
+    // Takes a share of the temp tokens left instead of `amount_expected`, see
+    // `PartialExchangePermille`
+    permille: Option<u16>,
+}
+
+/// The accounts one exchange touches, shared by `Exchange` and `BatchExchange`
+struct ExchangeAccounts<'a, 'b> {
+    taker: &'b AccountInfo<'a>,
+    taker_token_sent_acc: &'b AccountInfo<'a>,
+    taker_token_to_receive_acc: &'b AccountInfo<'a>,
+    escrow_temp_token_acc: &'b AccountInfo<'a>,
+    escrow_maker_acc: &'b AccountInfo<'a>,
+    escrow_maker_to_receive_acc: &'b AccountInfo<'a>,
+    escrow_acc: &'b AccountInfo<'a>,
+    token_program: &'b AccountInfo<'a>,
+    pda_acc: &'b AccountInfo<'a>,
+    config_acc: &'b AccountInfo<'a>,
+}
+
 impl Processor {
     pub fn process(
         program_id: &Pubkey,
@@ -23,318 +120,7411 @@ impl Processor {
         instruction_data: &[u8],
     ) -> ProgramResult {
         let instruction = EscrowInstruction::unpack(instruction_data)?;
+        check_account_roles(&instruction, accounts, program_id)?;
+        check_writable(&instruction, accounts)?;
 
         // use instruction to dispatch procedure
-        match instruction {
-            EscrowInstruction::InitEscrow { amount } => {
+        let result = match instruction {
+            EscrowInstruction::InitEscrow {
+                amount,
+                min_fill,
+                nonce,
+                allow_same_mint,
+            } => {
                 msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(accounts, amount, program_id)
+                let terms = InitTerms {
+                    amount,
+                    min_fill,
+                    nonce,
+                    allow_same_mint,
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
+            }
+            EscrowInstruction::InitEscrowMulti { amount, mints } => {
+                msg!("Instruction: InitEscrowMulti");
+                let terms = InitTerms {
+                    amount,
+                    accepted_mints: &mints,
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
             }
-            EscrowInstruction::Exchange { amount } => {
+            EscrowInstruction::InitEscrowRatio {
+                price_num,
+                price_den,
+            } => {
+                msg!("Instruction: InitEscrowRatio");
+                let terms = InitTerms {
+                    price: Some((price_num, price_den)),
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
+            }
+            EscrowInstruction::InitEscrowOracle {
+                price_feed,
+                min_price,
+                max_price,
+            } => {
+                msg!("Instruction: InitEscrowOracle");
+                let terms = InitTerms {
+                    oracle: Some((price_feed, min_price, max_price)),
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
+            }
+            EscrowInstruction::Exchange {
+                amount,
+                min_receive,
+                create_maker_receive,
+                wrap_sol,
+                quote_program,
+                memo,
+            } => {
                 msg!("Instruction: Exchange");
-                Self::process_exchange(accounts, amount, program_id)
+                let amount = match quote_program {
+                    Some(quote_program) => quoted_amount(&quote_program)?,
+                    None => amount,
+                };
+                let terms = ExchangeTerms {
+                    amount_expected: amount,
+                    min_receive,
+                    create_maker_receive,
+                    wrap_sol,
+                    memo: memo.as_deref(),
+                    ..ExchangeTerms::default()
+                };
+                Self::process_exchange(accounts, &terms, program_id)
+            }
+            EscrowInstruction::SimulateExchange {
+                amount,
+                min_receive,
+            } => {
+                msg!("Instruction: SimulateExchange");
+                let terms = ExchangeTerms {
+                    amount_expected: amount,
+                    min_receive,
+                    simulate: true,
+                    ..ExchangeTerms::default()
+                };
+                Self::process_exchange(accounts, &terms, program_id)
+            }
+            EscrowInstruction::PartialExchangePermille { permille } => {
+                msg!("Instruction: PartialExchangePermille");
+                let terms = ExchangeTerms {
+                    permille: Some(permille),
+                    ..ExchangeTerms::default()
+                };
+                Self::process_exchange(accounts, &terms, program_id)
             }
-            EscrowInstruction::CancelEscrow { amount: _ } => {
+            EscrowInstruction::BatchExchange { amounts } => {
+                msg!("Instruction: BatchExchange");
+                Self::process_batch_exchange(accounts, &amounts, program_id)
+            }
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol,
+                keep_escrow,
+                confirm_amount,
+            } => {
                 msg!("Instruction: Cancel");
-                Self::process_cancel(accounts, program_id)
+                Self::process_cancel(
+                    accounts,
+                    unwrap_sol,
+                    keep_escrow,
+                    confirm_amount,
+                    program_id,
+                )
+            }
+            EscrowInstruction::SetFrozen { frozen } => {
+                msg!("Instruction: SetFrozen");
+                Self::process_set_frozen(accounts, frozen)
+            }
+            EscrowInstruction::SetExpiry { expires_at } => {
+                msg!("Instruction: SetExpiry");
+                Self::process_set_expiry(accounts, expires_at)
+            }
+            EscrowInstruction::TopUp { amount } => {
+                msg!("Instruction: TopUp");
+                Self::process_top_up(accounts, amount, program_id)
+            }
+            EscrowInstruction::WithdrawPartial { amount } => {
+                msg!("Instruction: WithdrawPartial");
+                Self::process_withdraw_partial(accounts, amount, program_id)
+            }
+            EscrowInstruction::Migrate => {
+                msg!("Instruction: Migrate");
+                Self::process_migrate(accounts, program_id)
+            }
+            EscrowInstruction::SetRentFee { rent_fee_bps } => {
+                msg!("Instruction: SetRentFee");
+                Self::process_set_rent_fee(accounts, rent_fee_bps, program_id)
+            }
+            EscrowInstruction::TransferMaker { new_maker } => {
+                msg!("Instruction: TransferMaker");
+                Self::process_transfer_maker(accounts, &new_maker, program_id)
+            }
+            EscrowInstruction::Relist { amount } => {
+                msg!("Instruction: Relist");
+                Self::process_relist(accounts, amount, program_id)
+            }
+            EscrowInstruction::LogState => {
+                msg!("Instruction: LogState");
+                Self::process_log_state(accounts, program_id)
+            }
+            EscrowInstruction::CancelAll => {
+                msg!("Instruction: CancelAll");
+                Self::process_cancel_all(accounts, program_id)
+            }
+            EscrowInstruction::SetHook { hook_program } => {
+                msg!("Instruction: SetHook");
+                Self::process_set_hook(accounts, hook_program)
+            }
+            EscrowInstruction::InitEscrowSplit { amount, splits } => {
+                msg!("Instruction: InitEscrowSplit");
+                let terms = InitTerms {
+                    amount,
+                    receive_splits: &splits,
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
+            }
+            EscrowInstruction::InitEscrowScaled { amount, scale } => {
+                msg!("Instruction: InitEscrowScaled");
+                let terms = InitTerms {
+                    amount,
+                    scale,
+                    ..InitTerms::default()
+                };
+                Self::process_init_escrow(accounts, terms, program_id)
+            }
+            EscrowInstruction::Admin { paused } => {
+                msg!("Instruction: Admin");
+                Self::process_admin(accounts, paused, program_id)
+            }
+            EscrowInstruction::SetAuditClose { audit_close } => {
+                msg!("Instruction: SetAuditClose");
+                Self::process_set_audit_close(accounts, audit_close)
             }
+            EscrowInstruction::FinalizeClosed => {
+                msg!("Instruction: FinalizeClosed");
+                Self::process_finalize_closed(accounts, program_id)
+            }
+            EscrowInstruction::ScanExpired { reclaim } => {
+                msg!("Instruction: ScanExpired");
+                Self::process_scan_expired(accounts, reclaim, program_id)
+            }
+        };
+
+        if let Err(e) = &result {
+            msg!("Escrow error: {:?}", e);
         }
+        result
+    }
+
+    fn process_set_frozen(accounts: &[AccountInfo], frozen: bool) -> EscrowResult<()> {
+        check_account_count(accounts, SET_FROZEN_ACCOUNTS)?;
+
+        Self::update_as_maker(accounts, |escrow| escrow.frozen = frozen)
+    }
+
+    fn process_set_expiry(accounts: &[AccountInfo], expires_at: i64) -> EscrowResult<()> {
+        check_account_count(accounts, SET_EXPIRY_ACCOUNTS)?;
+
+        Self::update_as_maker(accounts, |escrow| escrow.expires_at = expires_at)
     }
 
-    fn process_cancel(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    /// Sets the rent fee every escrow closing from then on pays, signed by the admin
+    fn process_set_rent_fee(
+        accounts: &[AccountInfo],
+        rent_fee_bps: u16,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, SET_RENT_FEE_ACCOUNTS)?;
+
         let acc_iter = &mut accounts.iter();
 
-        let owner = next_account_info(acc_iter)?;
+        let admin = next_account_info(acc_iter)?;
 
-        if !owner.is_signer {
+        if !admin.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
-        let owner_token_to_receive_acc = next_account_info(acc_iter)?;
-        let owner_token_to_receive_acc_info =
-            Account::unpack(&owner_token_to_receive_acc.try_borrow_data()?)?;
+        let config_acc = next_account_info(acc_iter)?;
+        if *config_acc.key != find_config_address(program_id).0 {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+        // Created by the admin's first `Admin` call
+        if config_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut config = Config::unpack(&config_acc.try_borrow_data()?)?;
+        if config.admin != *admin.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
 
-        let escrow_temp_token_acc = next_account_info(acc_iter)?;
-        let escrow_temp_token_acc_info =
-            Account::unpack(&escrow_temp_token_acc.try_borrow_data()?)?;
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        config.rent_fee_bps = rent_fee_bps;
+        config.rent_fee_account = *next_account_info(acc_iter)?.key;
+        Config::pack(config, &mut config_acc.try_borrow_mut_data()?)?;
 
-        let escrow_acc = next_account_info(acc_iter)?;
-        let escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
+        Ok(())
+    }
 
-        let token_program = next_account_info(acc_iter)?;
-        let pda_acc = next_account_info(acc_iter)?;
+    fn process_set_hook(
+        accounts: &[AccountInfo],
+        hook_program: Option<Pubkey>,
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, SET_HOOK_ACCOUNTS)?;
 
-        if owner_token_to_receive_acc_info.mint != escrow_temp_token_acc_info.mint {
-            return Err(EscrowError::ExpectedMintMismatch.into());
+        Self::update_as_maker(accounts, |escrow| escrow.hook_program = hook_program)
+    }
+
+    fn process_set_audit_close(accounts: &[AccountInfo], audit_close: bool) -> EscrowResult<()> {
+        check_account_count(accounts, SET_AUDIT_CLOSE_ACCOUNTS)?;
+
+        Self::update_as_maker(accounts, |escrow| escrow.audit_close = audit_close)
+    }
+
+    /// Closes an escrow an exchange kept as a record, the rent split as the exchange
+    /// would have
+    fn process_finalize_closed(accounts: &[AccountInfo], program_id: &Pubkey) -> EscrowResult<()> {
+        if accounts.len() < FINALIZE_CLOSED_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
         }
 
-        let tx_to_owner_ix = spl_token::instruction::transfer(
-            token_program.key,
-            escrow_temp_token_acc.key,
-            owner_token_to_receive_acc.key,
-            &pda,
-            &[&pda],
-            escrow_temp_token_acc_info.amount,
-        )?;
+        let acc_iter = &mut accounts.iter();
 
-        msg!("Calling the token program to return tokens to the escrow's owner.");
-        invoke_signed(
-            &tx_to_owner_ix,
-            &[
-                escrow_temp_token_acc.clone(),
-                owner_token_to_receive_acc.clone(),
-                pda_acc.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
+        let owner = next_account_info(acc_iter)?;
 
-        let close_temp_ix = spl_token::instruction::close_account(
-            token_program.key,
-            escrow_temp_token_acc.key,
-            owner.key,
-            &pda,
-            &[&pda],
-        )?;
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        msg!("Calling the token program close temp.");
-        invoke_signed(
-            &close_temp_ix,
-            &[
-                escrow_temp_token_acc.clone(),
-                owner.clone(),
-                pda_acc.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        if escrow_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
 
-        msg!("Closing the escrow account...");
-        **owner.lamports.borrow_mut() = owner
-            .lamports()
-            .checked_add(escrow_acc.lamports())
-            .ok_or(EscrowError::AmountOverflow)?;
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
 
-        **escrow_acc.lamports.borrow_mut() = 0;
-        // Setting it to empty fields
+        // Still trading, or kept by a cancel for `Relist`
+        if !escrow_acc_info.is_completed() {
+            return Err(EscrowError::EscrowActive.into());
+        }
+
+        let config = load_config(program_id, next_account_info(acc_iter)?)?;
+        let rent_fee = rent_fee(config.as_ref(), acc_iter.as_slice())?;
+        pay_out_rent(escrow_acc, owner, rent_fee)?;
         *escrow_acc.try_borrow_mut_data()? = &mut [];
 
         Ok(())
     }
 
-    fn process_exchange(
+    /// Pauses or resumes the program, creating the config with the signer as admin
+    /// on the first call
+    fn process_admin(
         accounts: &[AccountInfo],
-        amount_expected: u64,
+        paused: bool,
         program_id: &Pubkey,
-    ) -> ProgramResult {
+    ) -> EscrowResult<()> {
+        if accounts.len() < ADMIN_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
         let acc_iter = &mut accounts.iter();
 
-        let taker = next_account_info(acc_iter)?;
+        let admin = next_account_info(acc_iter)?;
 
-        if !taker.is_signer {
+        if !admin.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
-        let taker_token_sent_acc = next_account_info(acc_iter)?;
-        let taker_token_sent_acc_info = Account::unpack(&taker_token_sent_acc.try_borrow_data()?)?;
+        let config_acc = next_account_info(acc_iter)?;
+        let (config_address, bump_seed) = find_config_address(program_id);
+        if config_address != *config_acc.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
 
-        let taker_token_to_receive_acc = next_account_info(acc_iter)?;
-        let taker_token_to_receive_acc_info =
-            Account::unpack(&taker_token_to_receive_acc.try_borrow_data()?)?;
+        if config_acc.owner != program_id {
+            let system_program = find_account(acc_iter.as_slice(), &system_program::id())
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let create_ix = system_instruction::create_account(
+                admin.key,
+                config_acc.key,
+                Rent::get()?.minimum_balance(Config::LEN),
+                Config::LEN as u64,
+                program_id,
+            );
 
-        let escrow_temp_token_acc = next_account_info(acc_iter)?;
-        let escrow_temp_token_acc_info =
-            Account::unpack(&escrow_temp_token_acc.try_borrow_data()?)?;
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+            msg!("Calling the system program to create the config account...");
+            invoke_signed(
+                &create_ix,
+                &[admin.clone(), config_acc.clone(), system_program.clone()],
+                &[&[CONFIG_SEED, &[bump_seed]]],
+            )?;
+        }
 
-        let escrow_maker_acc = next_account_info(acc_iter)?;
+        let mut config = Config::unpack_unchecked(&config_acc.try_borrow_data()?)?;
+        if !config.is_initialized() {
+            config.is_initialized = true;
+            config.admin = *admin.key;
+        } else if config.admin != *admin.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
 
-        let escrow_maker_to_receive_acc = next_account_info(acc_iter)?;
-        let escrow_maker_to_receive_acc_info =
-            Account::unpack(&escrow_maker_to_receive_acc.try_borrow_data()?)?;
+        config.paused = paused;
+        Config::pack(config, &mut config_acc.try_borrow_mut_data()?)?;
 
-        let escrow_acc = next_account_info(acc_iter)?;
-        let escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
+        Ok(())
+    }
 
-        let token_program = next_account_info(acc_iter)?;
-        let pda_acc = next_account_info(acc_iter)?;
+    fn process_transfer_maker(
+        accounts: &[AccountInfo],
+        new_maker: &Pubkey,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, TRANSFER_MAKER_ACCOUNTS)?;
 
-        if taker_token_sent_acc_info.mint != escrow_maker_to_receive_acc_info.mint {
-            return Err(EscrowError::ExpectedMintMismatch.into());
-        }
-        if taker_token_to_receive_acc_info.mint != escrow_temp_token_acc_info.mint {
-            return Err(EscrowError::ExpectedMintMismatch.into());
-        }
+        let acc_iter = &mut accounts.iter();
 
-        // Now the exchange tokens are matched
+        let owner = next_account_info(acc_iter)?;
 
-        if amount_expected != escrow_temp_token_acc_info.amount {
-            return Err(EscrowError::ExpectedAmountMismatch.into());
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
         }
 
-        if taker_token_sent_acc_info.amount <= escrow_acc_info.expected_amount {
-            return Err(EscrowError::NotEnoughBalanceToSent.into());
-        }
+        let escrow_acc = next_account_info(acc_iter)?;
+        let owner_token_to_receive_acc = next_account_info(acc_iter)?;
+        let new_owner_token_to_receive_acc = next_account_info(acc_iter)?;
 
-        // Why check the data here because you couldn't trust the data sent by client?
-        // Then why not
-        // - Read onchain data here
-        // - Use a hash
+        if escrow_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
 
-        if escrow_acc_info.temp_token_account_pubkey != *escrow_temp_token_acc.key {
+        if escrow_acc_info.initializer_pubkey != *owner.key
+            || !escrow_acc_info
+                .initializer_token_to_receive_account_pubkey
+                .matches(owner_token_to_receive_acc.key)
+        {
             return Err(EscrowError::InvalidAccountData.into());
         }
 
-        if escrow_acc_info.initializer_pubkey != *escrow_maker_acc.key {
-            return Err(EscrowError::InvalidAccountData.into());
+        // Mid exchange the maker's accounts are already picked
+        if escrow_acc_info.locked {
+            return Err(EscrowError::EscrowBusy.into());
         }
 
-        if escrow_acc_info.initializer_token_to_receive_account_pubkey
-            != *escrow_maker_to_receive_acc.key
+        if *owner_token_to_receive_acc.owner != spl_token::id()
+            || *new_owner_token_to_receive_acc.owner != spl_token::id()
         {
-            return Err(EscrowError::InvalidAccountData.into());
+            return Err(ProgramError::IncorrectProgramId);
         }
+        let owner_token_to_receive_acc_info =
+            Account::unpack(&owner_token_to_receive_acc.try_borrow_data()?)?;
+        let new_owner_token_to_receive_acc_info =
+            Account::unpack(&new_owner_token_to_receive_acc.try_borrow_data()?)?;
 
-        let tx_to_taker_ix = spl_token::instruction::transfer(
-            token_program.key,
-            escrow_temp_token_acc.key,
-            taker_token_to_receive_acc.key,
-            &pda,
-            &[&pda],
-            amount_expected,
-        )?;
-
-        msg!("Calling the token program to transfer tokens to the escrow's taker.");
-        invoke_signed(
-            &tx_to_taker_ix,
-            &[
-                escrow_temp_token_acc.clone(),
-                taker_token_to_receive_acc.clone(),
-                pda_acc.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
-
-        let tx_to_maker_ix = spl_token::instruction::transfer(
-            token_program.key,
-            taker_token_sent_acc.key,
-            escrow_maker_to_receive_acc.key,
-            taker.key,
-            &[&taker.key],
-            escrow_acc_info.expected_amount,
-        )?;
-
-        msg!("Calling the token program to transfer tokens to the escrow's maker.");
-        invoke(
-            &tx_to_maker_ix,
-            &[
-                taker_token_sent_acc.clone(),
-                escrow_maker_to_receive_acc.clone(),
-                taker.clone(),
-                token_program.clone(),
-            ],
-        )?;
-
-        let close_temp_ix = spl_token::instruction::close_account(
-            token_program.key,
-            escrow_temp_token_acc.key,
-            escrow_maker_acc.key,
-            &pda,
-            &[&pda],
-        )?;
-
-        msg!("Calling the token program close temp.");
-        invoke_signed(
-            &close_temp_ix,
-            &[
-                escrow_temp_token_acc.clone(),
-                escrow_maker_acc.clone(),
-                pda_acc.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
-
-        msg!("Closing the escrow account...");
-        **escrow_maker_acc.lamports.borrow_mut() = escrow_maker_acc
-            .lamports()
-            .checked_add(escrow_acc.lamports())
-            .ok_or(EscrowError::AmountOverflow)?;
+        if new_owner_token_to_receive_acc_info.mint != owner_token_to_receive_acc_info.mint {
+            return Err(EscrowError::ExpectedMintMismatch.into());
+        }
+        if new_owner_token_to_receive_acc_info.owner != *new_maker {
+            return Err(EscrowError::ReceiveAccountNotOwned.into());
+        }
 
-        **escrow_acc.lamports.borrow_mut() = 0;
-        // Setting it to empty fields
-        *escrow_acc.try_borrow_mut_data()? = &mut [];
+        escrow_acc_info.initializer_pubkey = *new_maker;
+        escrow_acc_info.initializer_token_to_receive_account_pubkey =
+            MakerReceiveAccount(*new_owner_token_to_receive_acc.key);
+        // The new maker takes over the first share, the others keep theirs
+        if escrow_acc_info.receive_splits_len > 0 {
+            escrow_acc_info.receive_splits[0].account = *new_owner_token_to_receive_acc.key;
+        }
+        Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
 
         Ok(())
     }
 
-    fn process_init_escrow(
+    fn process_relist(
         accounts: &[AccountInfo],
         amount: u64,
         program_id: &Pubkey,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let initializer = next_account_info(account_info_iter)?;
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, RELIST_ACCOUNTS)?;
 
-        if !initializer.is_signer {
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
-        // TODO: how and should I check this is writable
-        let temp_token_account = next_account_info(account_info_iter)?;
+        let temp_token_acc = next_account_info(acc_iter)?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let clock = Clock::from_account_info(next_account_info(acc_iter)?)?;
+        check_not_paused(load_config(program_id, next_account_info(acc_iter)?)?.as_ref())?;
+        let token_to_receive_acc = next_account_info(acc_iter)?;
 
-        let token_to_receive_account = next_account_info(account_info_iter)?;
-        if *token_to_receive_account.owner != spl_token::id() {
+        if escrow_acc.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
+        let escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key
+            || !escrow_acc_info
+                .initializer_token_to_receive_account_pubkey
+                .matches(token_to_receive_acc.key)
+        {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
 
-        let escrow_account = next_account_info(account_info_iter)?;
+        // Only a cancel keeping the escrow leaves it without a temp account, while
+        // it still has one the trade can be filled
+        if !escrow_acc_info.is_empty() {
+            return Err(EscrowError::EscrowActive.into());
+        }
 
-        let sysvar_rent = next_account_info(account_info_iter)?;
-        let rent = &Rent::from_account_info(sysvar_rent)?;
+        if amount == 0 {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
 
-        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+        let rent = Rent::get()?;
+        if !rent.is_exempt(temp_token_acc.lamports(), temp_token_acc.data_len()) {
             return Err(ProgramError::AccountNotRentExempt);
         }
 
-        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
-        if escrow_info.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
+        let temp_token_acc_info = Account::unpack(&temp_token_acc.try_borrow_data()?)?;
+        if temp_token_acc_info.owner != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
         }
+        check_no_delegate(&temp_token_acc_info)?;
 
-        escrow_info.is_initialized = true;
-        escrow_info.initializer_pubkey = *initializer.key;
-        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
-        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
-        escrow_info.expected_amount = amount;
-
-        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+        // The receive account may still be the maker's associated token account
+        // that the first exchange creates, in the temp mint only if it derives
+        // from it
+        let same_mint = if *token_to_receive_acc.owner == spl_token::id() {
+            Account::unpack(&token_to_receive_acc.try_borrow_data()?)?.mint
+                == temp_token_acc_info.mint
+        } else {
+            get_associated_token_address(owner.key, &temp_token_acc_info.mint)
+                == *token_to_receive_acc.key
+        };
+        check_listing(
+            temp_token_acc.key,
+            token_to_receive_acc.key,
+            same_mint,
+            &[amount, temp_token_acc_info.amount],
+        )?;
 
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let relisted = Escrow {
+            is_initialized: true,
+            initializer_pubkey: escrow_acc_info.initializer_pubkey,
+            temp_token_account_pubkey: TempTokenAccount(*temp_token_acc.key),
+            initializer_token_to_receive_account_pubkey: escrow_acc_info
+                .initializer_token_to_receive_account_pubkey,
+            expected_amount: amount,
+            nonce: escrow_acc_info.nonce,
+            created_at: clock.unix_timestamp,
+            ..Escrow::unpack_unchecked(&[0; Escrow::LEN])?
+        };
+        Escrow::pack(relisted, &mut escrow_acc.try_borrow_mut_data()?)?;
 
-        let token_program = next_account_info(account_info_iter)?;
-        // spl instruction to change authority
+        let (pda, _bump_seed) = find_escrow_authority(program_id);
         let owner_change_ix = spl_token::instruction::set_authority(
             token_program.key,
-            temp_token_account.key,
+            temp_token_acc.key,
             Some(&pda),
             spl_token::instruction::AuthorityType::AccountOwner,
-            initializer.key,
-            &[initializer.key], // signer, support multi-sig
+            owner.key,
+            &[owner.key],
         )?;
 
         msg!("Calling the token program to transfer token account ownership...");
-        // CPI interface
-        // i.e. the signature is extended to the CPIs.
         invoke(
             &owner_change_ix,
+            &[temp_token_acc.clone(), owner.clone(), token_program.clone()],
+        )?;
+
+        EscrowEvent::Initialized {
+            escrow: *escrow_acc.key,
+            initializer: *owner.key,
+            temp_token_account: *temp_token_acc.key,
+            expected_amount: amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_log_state(accounts: &[AccountInfo], program_id: &Pubkey) -> EscrowResult<()> {
+        check_account_count(accounts, LOG_STATE_ACCOUNTS)?;
+
+        let escrow_acc = &accounts[0];
+        if escrow_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
+
+        msg!("escrow: {}", escrow_acc.key);
+        msg!("initializer: {}", escrow.initializer_pubkey);
+        msg!("temp token account: {}", escrow.temp_token_account_pubkey.0);
+        msg!(
+            "token to receive account: {}",
+            escrow.initializer_token_to_receive_account_pubkey.0
+        );
+        msg!("expected amount: {}", escrow.expected_amount);
+        msg!("check decimals: {}", escrow.check_decimals);
+        msg!("temp token decimals: {}", escrow.temp_token_decimals);
+        msg!(
+            "token to receive decimals: {}",
+            escrow.token_to_receive_decimals
+        );
+        msg!("frozen: {}", escrow.frozen);
+        msg!("accepted mints: {:?}", escrow.accepted_mints());
+        msg!("min fill: {}", escrow.min_fill);
+        msg!("locked: {}", escrow.locked);
+        msg!("nonce: {:?}", escrow.nonce);
+        msg!("expires at: {}", escrow.expires_at);
+        msg!("partially filled: {}", escrow.partially_filled);
+        msg!("created at: {}", escrow.created_at);
+        msg!("hook program: {:?}", escrow.hook_program);
+        msg!("price: {:?}", escrow.price());
+        msg!("price feed: {:?}", escrow.price_feed);
+        msg!("min price: {}", escrow.min_price);
+        msg!("max price: {}", escrow.max_price);
+        msg!("receive splits: {:?}", escrow.receive_splits());
+        msg!("scale: {}", escrow.scale);
+        msg!("audit close: {}", escrow.audit_close);
+        if escrow.is_completed() {
+            msg!("completed by: {}", escrow.completed_by);
+            msg!("completed at: {}", escrow.completed_at);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a settings change signed by the escrow's maker
+    fn update_as_maker(
+        accounts: &[AccountInfo],
+        update: impl FnOnce(&mut Escrow),
+    ) -> EscrowResult<()> {
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_acc = next_account_info(acc_iter)?;
+        let mut escrow_acc_info = Escrow::unpack(&escrow_acc.try_borrow_data()?)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        update(&mut escrow_acc_info);
+        Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_cancel(
+        accounts: &[AccountInfo],
+        unwrap_sol: bool,
+        keep_escrow: bool,
+        confirm_amount: Option<u64>,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        let plan = Self::validate_cancel(
+            accounts,
+            unwrap_sol,
+            keep_escrow,
+            confirm_amount,
+            program_id,
+        )?;
+        match plan {
+            Some(plan) => Self::execute_cancel(plan),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs every check of a cancel without moving anything, `None` for an escrow
+    /// an earlier cancel closed
+    fn validate_cancel<'a, 'b>(
+        accounts: &'b [AccountInfo<'a>],
+        unwrap_sol: bool,
+        keep_escrow: bool,
+        confirm_amount: Option<u64>,
+        program_id: &Pubkey,
+    ) -> EscrowResult<Option<CancelPlan<'a, 'b>>> {
+        if accounts.len() < CANCEL_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // In the order of the `CancelEscrow` docs, pinned by `build_cancel_instruction`
+        // and its tests
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+        let owner_token_to_receive_acc = next_account_info(acc_iter)?;
+        let escrow_temp_token_acc = next_account_info(acc_iter)?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+
+        // A retried cancel finds the escrow the first one closed, see `is_closed`.
+        // Nothing is left to refund, so it succeeds without doing anything
+        if is_closed(escrow_acc) {
+            msg!("Escrow {} is already closed", escrow_acc.key);
+            return Ok(None);
+        }
+
+        check_distinct(&[
+            owner,
+            owner_token_to_receive_acc,
+            escrow_temp_token_acc,
+            escrow_acc,
+        ])?;
+        let escrow_acc_info = Self::unpack_escrow(program_id, escrow_acc, escrow_temp_token_acc)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        // Before its deadline only the maker can cancel. Past it anyone can, passing
+        // the clock, and everything still goes back to the maker. Unwrapping closes
+        // the maker's refund account and keeping the escrow holds on to its rent,
+        // both always take the maker's signature.
+        if !owner.is_signer {
+            let expired = escrow_acc_info.expires_at != 0 && {
+                let clock = Clock::from_account_info(next_account_info(acc_iter)?)?;
+                escrow_acc_info.is_expired(clock.unix_timestamp)
+            };
+            if !expired || unwrap_sol || keep_escrow {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+        }
+
+        // The refund goes back in the temp token's mint, so unlike exchange there is no
+        // stored account to compare `owner_token_to_receive_acc` with, any account of
+        // the maker will do
+        let owner_token_to_receive_acc_info = Self::load_token_account(
+            owner_token_to_receive_acc,
+            None,
+            Some((owner.key, EscrowError::RefundAccountNotOwned)),
+        )?;
+
+        let loaded = Self::load_and_validate_escrow(
+            program_id,
+            escrow_acc_info,
+            escrow_temp_token_acc,
+            &owner_token_to_receive_acc_info,
+            EscrowError::RefundMintMismatch,
+        )?;
+
+        // The client saw another balance than it would cancel
+        if confirm_amount.is_some_and(|amount| amount != loaded.temp_token.amount) {
+            return Err(EscrowError::StaleAmount.into());
+        }
+
+        Ok(Some(CancelPlan {
+            owner,
+            refund_acc: owner_token_to_receive_acc,
+            temp_acc: escrow_temp_token_acc,
+            escrow_acc,
+            token_program,
+            pda_acc,
+            refund: owner_token_to_receive_acc_info,
+            loaded,
+            unwrap_sol,
+            keep_escrow,
+        }))
+    }
+
+    /// Refunds and closes what `validate_cancel` checked, failing only where the
+    /// runtime or the token program would
+    fn execute_cancel(plan: CancelPlan) -> EscrowResult<()> {
+        let CancelPlan {
+            owner,
+            refund_acc: owner_token_to_receive_acc,
+            temp_acc: escrow_temp_token_acc,
+            escrow_acc,
+            token_program,
+            pda_acc,
+            refund: owner_token_to_receive_acc_info,
+            loaded:
+                LoadedEscrow {
+                    escrow: mut escrow_acc_info,
+                    temp_token: escrow_temp_token_acc_info,
+                    pda,
+                    bump_seed,
+                },
+            unwrap_sol,
+            keep_escrow,
+        } = plan;
+
+        let tx_to_owner_ix = spl_token::instruction::transfer(
+            token_program.key,
+            escrow_temp_token_acc.key,
+            owner_token_to_receive_acc.key,
+            &pda,
+            &[&pda],
+            escrow_temp_token_acc_info.amount,
+        )?;
+
+        msg!("Calling the token program to return tokens to the escrow's owner.");
+        invoke_signed(
+            &tx_to_owner_ix,
             &[
-                temp_token_account.clone(),
-                initializer.clone(),
+                escrow_temp_token_acc.clone(),
+                owner_token_to_receive_acc.clone(),
+                pda_acc.clone(),
                 token_program.clone(),
             ],
-        )
+            &[&[ESCROW_SEED, &[bump_seed]]],
+        )?;
+
+        let close_temp_ix = spl_token::instruction::close_account(
+            token_program.key,
+            escrow_temp_token_acc.key,
+            owner.key,
+            &pda,
+            &[&pda],
+        )?;
+
+        msg!("Calling the token program close temp.");
+        invoke_signed(
+            &close_temp_ix,
+            &[
+                escrow_temp_token_acc.clone(),
+                owner.clone(),
+                pda_acc.clone(),
+                token_program.clone(),
+            ],
+            &[&[ESCROW_SEED, &[bump_seed]]],
+        )?;
+
+        // Only a wSOL refund account opened for this cancel gets closed, one the maker
+        // already kept wrapped SOL in stays open with the refund added
+        let close_refund = unwrap_sol
+            && escrow_temp_token_acc_info.mint == spl_token::native_mint::id()
+            && owner_token_to_receive_acc_info.is_native()
+            && owner_token_to_receive_acc_info.amount == 0
+            && matches!(
+                owner_token_to_receive_acc_info.close_authority,
+                COption::None
+            );
+        if close_refund {
+            let close_refund_ix = spl_token::instruction::close_account(
+                token_program.key,
+                owner_token_to_receive_acc.key,
+                owner.key,
+                owner.key,
+                &[owner.key],
+            )?;
+
+            msg!("Calling the token program to unwrap the refunded SOL.");
+            invoke(
+                &close_refund_ix,
+                &[
+                    owner_token_to_receive_acc.clone(),
+                    owner.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        if keep_escrow {
+            msg!("Emptying the escrow account for a relist...");
+            escrow_acc_info.temp_token_account_pubkey = TempTokenAccount::default();
+            escrow_acc_info.expected_amount = 0;
+            Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+        } else {
+            msg!("Closing the escrow account...");
+            credit_lamports(escrow_acc, owner)?;
+            // Setting it to empty fields
+            *escrow_acc.try_borrow_mut_data()? = &mut [];
+        }
+
+        EscrowEvent::Cancelled {
+            escrow: *escrow_acc.key,
+            initializer: *owner.key,
+            amount_refunded: escrow_temp_token_acc_info.amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_cancel_all(accounts: &[AccountInfo], program_id: &Pubkey) -> EscrowResult<()> {
+        if accounts.len() < CANCEL_ALL_ACCOUNTS + CANCEL_ALL_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+
+        let escrow_accs = acc_iter.as_slice().chunks_exact(CANCEL_ALL_ESCROW_ACCOUNTS);
+        if !escrow_accs.remainder().is_empty() {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // Each is cancelled on its own, checking the maker owns it, and any error
+        // reverts the cancels already done along with the transaction
+        for escrow in escrow_accs {
+            let cancel_accs = [
+                owner.clone(),
+                escrow[2].clone(),
+                escrow[1].clone(),
+                escrow[0].clone(),
+                token_program.clone(),
+                pda_acc.clone(),
+            ];
+            Self::process_cancel(&cancel_accs, false, false, None, program_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_scan_expired(
+        accounts: &[AccountInfo],
+        reclaim: bool,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        let escrow_len = if reclaim {
+            SCAN_EXPIRED_RECLAIM_ACCOUNTS
+        } else {
+            1
+        };
+        if accounts.len() < SCAN_EXPIRED_ACCOUNTS + escrow_len {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let acc_iter = &mut accounts.iter();
+
+        let clock_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+        let now = Clock::from_account_info(clock_acc)?.unix_timestamp;
+
+        let escrow_accs = acc_iter.as_slice().chunks_exact(escrow_len);
+        if !escrow_accs.remainder().is_empty() {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // A keeper's batch shouldn't fail over one escrow filled or cancelled since
+        // it was put together, only expired escrows are touched
+        for escrow in escrow_accs {
+            let escrow_acc = &escrow[0];
+            let escrow_acc_info = if escrow_acc.owner == program_id {
+                Escrow::unpack(&escrow_acc.try_borrow_data()?).ok()
+            } else {
+                None
+            };
+            let expires_at = match escrow_acc_info {
+                Some(escrow_acc_info) if escrow_acc_info.is_expired(now) => {
+                    escrow_acc_info.expires_at
+                }
+                Some(_) => {
+                    msg!("Escrow {}: live", escrow_acc.key);
+                    continue;
+                }
+                None => {
+                    msg!("Escrow {}: skipped, not an open escrow", escrow_acc.key);
+                    continue;
+                }
+            };
+            msg!("Escrow {}: expired at {}", escrow_acc.key, expires_at);
+            if !reclaim {
+                continue;
+            }
+
+            let cancel_accs = [
+                escrow[3].clone(),
+                escrow[2].clone(),
+                escrow[1].clone(),
+                escrow_acc.clone(),
+                token_program.clone(),
+                pda_acc.clone(),
+                clock_acc.clone(),
+            ];
+            // Only an escrow failing the cancel's checks is skipped. Once the cancel
+            // moves tokens or lamports an error fails the batch, it may have been
+            // left halfway
+            match Self::validate_cancel(&cancel_accs, false, false, None, program_id) {
+                Ok(Some(plan)) => {
+                    Self::execute_cancel(plan)?;
+                    msg!("Escrow {}: reclaimed", escrow_acc.key);
+                }
+                Ok(None) => {}
+                Err(error) => msg!("Escrow {}: not reclaimed, {}", escrow_acc.key, error),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_top_up(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, TOP_UP_ACCOUNTS)?;
+
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let owner_token_source_acc = next_account_info(acc_iter)?;
+        let escrow_temp_token_acc = next_account_info(acc_iter)?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let clock = Clock::from_account_info(next_account_info(acc_iter)?)?;
+
+        check_distinct(&[
+            owner,
+            owner_token_source_acc,
+            escrow_temp_token_acc,
+            escrow_acc,
+        ])?;
+        let escrow_acc_info = Self::unpack_escrow(program_id, escrow_acc, escrow_temp_token_acc)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+        if escrow_acc_info.is_expired(clock.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+        // A fill already fixed the price of what was taken, growing the rest would
+        // blur what was on offer
+        if escrow_acc_info.partially_filled {
+            return Err(EscrowError::PartiallyFilled.into());
+        }
+        if escrow_acc_info.locked {
+            return Err(EscrowError::EscrowBusy.into());
+        }
+
+        let owner_token_source_acc_info =
+            Account::unpack(&owner_token_source_acc.try_borrow_data()?)?;
+        let LoadedEscrow {
+            escrow: mut escrow_acc_info,
+            temp_token: escrow_temp_token_acc_info,
+            ..
+        } = Self::load_and_validate_escrow(
+            program_id,
+            escrow_acc_info,
+            escrow_temp_token_acc,
+            &owner_token_source_acc_info,
+            EscrowError::ExpectedMintMismatch,
+        )?;
+
+        escrow_acc_info.expected_amount = match escrow_acc_info.price() {
+            Some((price_num, price_den)) => {
+                let temp_amount = escrow_temp_token_acc_info
+                    .amount
+                    .checked_add(amount)
+                    .ok_or(EscrowError::AmountOverflow)?;
+                ratio_share(price_num, price_den, temp_amount)?
+            }
+            None => topped_up_expected(
+                escrow_acc_info.expected_amount,
+                escrow_temp_token_acc_info.amount,
+                amount,
+            )?,
+        };
+
+        let tx_to_temp_ix = spl_token::instruction::transfer(
+            token_program.key,
+            owner_token_source_acc.key,
+            escrow_temp_token_acc.key,
+            owner.key,
+            &[owner.key],
+            amount,
+        )?;
+
+        msg!("Calling the token program to top up the escrow.");
+        invoke(
+            &tx_to_temp_ix,
+            &[
+                owner_token_source_acc.clone(),
+                escrow_temp_token_acc.clone(),
+                owner.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_withdraw_partial(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        check_account_count(accounts, WITHDRAW_PARTIAL_ACCOUNTS)?;
+
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let owner_token_to_receive_acc = next_account_info(acc_iter)?;
+        let escrow_temp_token_acc = next_account_info(acc_iter)?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+
+        check_distinct(&[
+            owner,
+            owner_token_to_receive_acc,
+            escrow_temp_token_acc,
+            escrow_acc,
+        ])?;
+        let escrow_acc_info = Self::unpack_escrow(program_id, escrow_acc, escrow_temp_token_acc)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+        if escrow_acc_info.locked {
+            return Err(EscrowError::EscrowBusy.into());
+        }
+
+        let owner_token_to_receive_acc_info =
+            Account::unpack(&owner_token_to_receive_acc.try_borrow_data()?)?;
+
+        if owner_token_to_receive_acc_info.owner != *owner.key {
+            return Err(EscrowError::RefundAccountNotOwned.into());
+        }
+
+        let LoadedEscrow {
+            escrow: mut escrow_acc_info,
+            temp_token: escrow_temp_token_acc_info,
+            pda,
+            bump_seed,
+        } = Self::load_and_validate_escrow(
+            program_id,
+            escrow_acc_info,
+            escrow_temp_token_acc,
+            &owner_token_to_receive_acc_info,
+            EscrowError::RefundMintMismatch,
+        )?;
+
+        if amount == 0 || amount >= escrow_temp_token_acc_info.amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+        // What stays has to be fillable on its own
+        let remaining = escrow_temp_token_acc_info.amount - amount;
+        if remaining < escrow_acc_info.min_fill {
+            return Err(EscrowError::BelowMinimumFill.into());
+        }
+        escrow_acc_info.expected_amount = match escrow_acc_info.price() {
+            Some((price_num, price_den)) => ratio_share(price_num, price_den, remaining)?,
+            None => maker_share(
+                escrow_acc_info.expected_amount,
+                remaining,
+                escrow_temp_token_acc_info.amount,
+            ),
+        };
+
+        let tx_to_owner_ix = spl_token::instruction::transfer(
+            token_program.key,
+            escrow_temp_token_acc.key,
+            owner_token_to_receive_acc.key,
+            &pda,
+            &[&pda],
+            amount,
+        )?;
+
+        msg!("Calling the token program to return tokens to the escrow's owner.");
+        invoke_signed(
+            &tx_to_owner_ix,
+            &[
+                escrow_temp_token_acc.clone(),
+                owner_token_to_receive_acc.clone(),
+                pda_acc.clone(),
+                token_program.clone(),
+            ],
+            &[&[ESCROW_SEED, &[bump_seed]]],
+        )?;
+
+        Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_migrate(accounts: &[AccountInfo], program_id: &Pubkey) -> EscrowResult<()> {
+        check_account_count(accounts, MIGRATE_ACCOUNTS)?;
+
+        let acc_iter = &mut accounts.iter();
+
+        let owner = next_account_info(acc_iter)?;
+
+        if !owner.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_acc = next_account_info(acc_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(acc_iter)?)?;
+        let system_program_acc = next_account_info(acc_iter)?;
+
+        if escrow_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if escrow_acc.data_len() == Escrow::LEN {
+            return Err(EscrowError::AlreadyMigrated.into());
+        }
+
+        let escrow_acc_info = Escrow::unpack_legacy(&escrow_acc.try_borrow_data()?)?;
+
+        if escrow_acc_info.initializer_pubkey != *owner.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        let shortfall = rent
+            .minimum_balance(Escrow::LEN)
+            .saturating_sub(escrow_acc.lamports());
+        if shortfall > 0 {
+            let fund_ix = system_instruction::transfer(owner.key, escrow_acc.key, shortfall);
+
+            msg!("Calling the system program to fund the larger escrow.");
+            invoke(
+                &fund_ix,
+                &[
+                    owner.clone(),
+                    escrow_acc.clone(),
+                    system_program_acc.clone(),
+                ],
+            )?;
+        }
+
+        escrow_acc.realloc(Escrow::LEN, true)?;
+        Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_exchange(
+        accounts: &[AccountInfo],
+        terms: &ExchangeTerms,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        if accounts.len() < EXCHANGE_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // In the order of the `Exchange` docs, pinned by `build_exchange_instruction`
+        // and its tests
+        let acc_iter = &mut accounts.iter();
+
+        let taker = next_account_info(acc_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_token_sent_acc = next_account_info(acc_iter)?;
+        let taker_token_to_receive_acc = next_account_info(acc_iter)?;
+        let escrow_temp_token_acc = next_account_info(acc_iter)?;
+        let escrow_maker_acc = next_account_info(acc_iter)?;
+        let escrow_maker_to_receive_acc = next_account_info(acc_iter)?;
+        let escrow_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+        let config_acc = next_account_info(acc_iter)?;
+
+        let accs = ExchangeAccounts {
+            taker,
+            taker_token_sent_acc,
+            taker_token_to_receive_acc,
+            escrow_temp_token_acc,
+            escrow_maker_acc,
+            escrow_maker_to_receive_acc,
+            escrow_acc,
+            token_program,
+            pda_acc,
+            config_acc,
+        };
+        if terms.create_maker_receive {
+            Self::create_maker_receive_account(&accs, acc_iter.as_slice())?;
+        }
+        let wsol_created =
+            terms.wrap_sol && Self::create_taker_wsol_account(&accs, acc_iter.as_slice())?;
+
+        let receipt = Self::fill_escrow(program_id, &accs, acc_iter.as_slice(), terms)?;
+
+        // Whatever the fill left wrapped goes back to the taker with the rent. An
+        // account the taker had before is theirs to keep
+        if wsol_created {
+            let close_ix = spl_token::instruction::close_account(
+                token_program.key,
+                taker_token_sent_acc.key,
+                taker.key,
+                taker.key,
+                &[taker.key],
+            )?;
+
+            msg!("Calling the token program to unwrap the taker's SOL.");
+            invoke(
+                &close_ix,
+                &[
+                    taker_token_sent_acc.clone(),
+                    taker.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        if let Some(memo) = terms.memo {
+            let memo_program = find_account(acc_iter.as_slice(), &MEMO_PROGRAM_ID)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let memo_ix = Instruction {
+                program_id: MEMO_PROGRAM_ID,
+                accounts: vec![AccountMeta::new_readonly(*taker.key, true)],
+                data: memo.as_bytes().to_vec(),
+            };
+
+            log_compute("the memo");
+            msg!("Calling the memo program to record the exchange's memo");
+            invoke(&memo_ix, &[taker.clone(), memo_program.clone()])?;
+        }
+
+        receipt.set();
+
+        Ok(())
+    }
+
+    /// The clock sysvar passed among `trailing_accs`, the runtime's otherwise
+    fn trailing_clock(trailing_accs: &[AccountInfo]) -> EscrowResult<Clock> {
+        match find_account(trailing_accs, &sysvar::clock::id()) {
+            Some(clock_acc) => Ok(Clock::from_account_info(clock_acc)?),
+            None => Clock::get().map_err(|_| {
+                msg!("This escrow needs the time, pass the clock sysvar");
+                ProgramError::NotEnoughAccountKeys
+            }),
+        }
+    }
+
+    /// Creates the maker's associated token account in the mint the taker pays in,
+    /// unless it exists already. The taker pays its rent.
+    fn create_maker_receive_account<'a>(
+        accs: &ExchangeAccounts<'a, '_>,
+        trailing_accs: &[AccountInfo<'a>],
+    ) -> EscrowResult<()> {
+        let ExchangeAccounts {
+            taker,
+            taker_token_sent_acc,
+            escrow_maker_acc,
+            escrow_maker_to_receive_acc,
+            token_program,
+            ..
+        } = *accs;
+
+        if *escrow_maker_to_receive_acc.owner != system_program::id() {
+            return Ok(());
+        }
+
+        let taker_token_sent_acc_info = Account::unpack(&taker_token_sent_acc.try_borrow_data()?)?;
+        if get_associated_token_address(escrow_maker_acc.key, &taker_token_sent_acc_info.mint)
+            != *escrow_maker_to_receive_acc.key
+        {
+            return Err(EscrowError::MakerMintMismatch.into());
+        }
+
+        let find = |key: Pubkey| {
+            find_account(trailing_accs, &key).ok_or(ProgramError::NotEnoughAccountKeys)
+        };
+        let mint = find(taker_token_sent_acc_info.mint)?;
+        let system_program_acc = find(system_program::id())?;
+        let sysvar_rent = find(sysvar::rent::id())?;
+        let ata_program = find(spl_associated_token_account::id())?;
+
+        let create_ix = create_associated_token_account(taker.key, escrow_maker_acc.key, mint.key);
+
+        msg!("Calling the associated token account program to create the maker's account.");
+        invoke(
+            &create_ix,
+            &[
+                taker.clone(),
+                escrow_maker_to_receive_acc.clone(),
+                escrow_maker_acc.clone(),
+                mint.clone(),
+                system_program_acc.clone(),
+                token_program.clone(),
+                sysvar_rent.clone(),
+                ata_program.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates the taker's wrapped SOL associated token account an `EXCHANGE_WRAP_SOL`
+    /// fill pays from, unless it exists already. The taker pays its rent, which
+    /// closing it after the fill gives back. Returns whether it created the account.
+    fn create_taker_wsol_account<'a>(
+        accs: &ExchangeAccounts<'a, '_>,
+        trailing_accs: &[AccountInfo<'a>],
+    ) -> EscrowResult<bool> {
+        let ExchangeAccounts {
+            taker,
+            taker_token_sent_acc,
+            token_program,
+            ..
+        } = *accs;
+
+        if *taker_token_sent_acc.owner != system_program::id() {
+            return Ok(false);
+        }
+
+        let native_mint = spl_token::native_mint::id();
+        if get_associated_token_address(taker.key, &native_mint) != *taker_token_sent_acc.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        let find = |key: Pubkey| {
+            find_account(trailing_accs, &key).ok_or(ProgramError::NotEnoughAccountKeys)
+        };
+        let mint = find(native_mint)?;
+        let system_program_acc = find(system_program::id())?;
+        let sysvar_rent = find(sysvar::rent::id())?;
+        let ata_program = find(spl_associated_token_account::id())?;
+
+        let create_ix = create_associated_token_account(taker.key, taker.key, mint.key);
+
+        msg!("Calling the associated token account program to create the taker's wSOL account.");
+        invoke(
+            &create_ix,
+            &[
+                taker.clone(),
+                taker_token_sent_acc.clone(),
+                taker.clone(),
+                mint.clone(),
+                system_program_acc.clone(),
+                token_program.clone(),
+                sysvar_rent.clone(),
+                ata_program.clone(),
+            ],
+        )?;
+
+        Ok(true)
+    }
+
+    fn process_batch_exchange(
+        accounts: &[AccountInfo],
+        amounts: &[u64],
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        if accounts.len() < BATCH_EXCHANGE_ACCOUNTS + amounts.len() * BATCH_FILL_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let acc_iter = &mut accounts.iter();
+
+        let taker = next_account_info(acc_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_token_sent_acc = next_account_info(acc_iter)?;
+        let taker_token_to_receive_acc = next_account_info(acc_iter)?;
+        let token_program = next_account_info(acc_iter)?;
+        let pda_acc = next_account_info(acc_iter)?;
+        let config_acc = next_account_info(acc_iter)?;
+
+        let (fill_accs, trailing_accs) = acc_iter
+            .as_slice()
+            .split_at(amounts.len() * BATCH_FILL_ACCOUNTS);
+
+        // Every fill sees the balances the previous ones left behind, and any
+        // error reverts the fills already done along with the transaction
+        for (fill, amount_expected) in fill_accs.chunks_exact(BATCH_FILL_ACCOUNTS).zip(amounts) {
+            let accs = ExchangeAccounts {
+                taker,
+                taker_token_sent_acc,
+                taker_token_to_receive_acc,
+                escrow_acc: &fill[0],
+                escrow_temp_token_acc: &fill[1],
+                escrow_maker_acc: &fill[2],
+                escrow_maker_to_receive_acc: &fill[3],
+                token_program,
+                pda_acc,
+                config_acc,
+            };
+            let terms = ExchangeTerms {
+                amount_expected: *amount_expected,
+                ..ExchangeTerms::default()
+            };
+            Self::fill_escrow(program_id, &accs, trailing_accs, &terms)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs one exchange against one escrow, `trailing_accs` holds the strict mode mints,
+    /// the rent fee account, the delegate paying for the taker, the hook program and
+    /// the clock.
+    ///
+    /// With `simulate` it stops once every check passed, before any transfer, and
+    /// returns what the exchange would have done. With `wrap_sol` it wraps what the
+    /// taker's account is short of the maker's share before paying.
+    fn fill_escrow<'a>(
+        program_id: &Pubkey,
+        accs: &ExchangeAccounts<'a, '_>,
+        trailing_accs: &[AccountInfo<'a>],
+        terms: &ExchangeTerms,
+    ) -> EscrowResult<ExchangeReceipt> {
+        let &ExchangeTerms {
+            amount_expected,
+            min_receive,
+            wrap_sol,
+            simulate,
+            permille,
+            ..
+        } = terms;
+        let ExchangeAccounts {
+            taker,
+            taker_token_sent_acc,
+            taker_token_to_receive_acc,
+            escrow_temp_token_acc,
+            escrow_maker_acc,
+            escrow_maker_to_receive_acc,
+            escrow_acc,
+            token_program,
+            pda_acc,
+            config_acc,
+        } = *accs;
+
+        let config = load_config(program_id, config_acc)?;
+        check_not_paused(config.as_ref())?;
+
+        // Why check the data here because you couldn't trust the data sent by client?
+        // Then why not
+        // - Read onchain data here
+        // - Use a hash
+        //
+        // The pubkey comparisons are cheap, run them before unpacking any token account.
+        // No token account may stand in for another: a taker receive account that is
+        // also the maker's would be paid and credited in the same transfer, and one
+        // that is the temp account would pay the taker out of their own fill. The
+        // wallets may alias, a maker filling their own escrow only moves tokens
+        // between their accounts
+        check_distinct(&[
+            escrow_acc,
+            escrow_temp_token_acc,
+            escrow_maker_acc,
+            escrow_maker_to_receive_acc,
+            taker_token_sent_acc,
+            taker_token_to_receive_acc,
+        ])?;
+        let escrow_acc_info = Self::unpack_escrow(program_id, escrow_acc, escrow_temp_token_acc)?;
+
+        if escrow_acc_info.initializer_pubkey != *escrow_maker_acc.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        // A multi-mint escrow gets paid into whichever maker account holds the taker's mint
+        if escrow_acc_info.accepted_mints().is_empty()
+            && !escrow_acc_info
+                .initializer_token_to_receive_account_pubkey
+                .matches(escrow_maker_to_receive_acc.key)
+        {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        if escrow_acc_info.frozen {
+            return Err(EscrowError::EscrowFrozen.into());
+        }
+
+        if escrow_acc_info.locked {
+            return Err(EscrowError::EscrowBusy.into());
+        }
+
+        // An escrow closes to takers at the maker's deadline, and the price of one left
+        // open without a deadline goes stale. Only an escrow that can expire needs the
+        // clock, older clients don't pass it
+        if escrow_acc_info.expires_at != 0 || escrow_acc_info.created_at != 0 {
+            let now = Self::trailing_clock(trailing_accs)?.unix_timestamp;
+            if escrow_acc_info.is_expired(now) || escrow_acc_info.is_too_old(now) {
+                return Err(EscrowError::EscrowExpired.into());
+            }
+        }
+
+        // Guards against a client sending the temp tokens to a stranger
+        let taker_token_to_receive_acc_info = Self::load_token_account(
+            taker_token_to_receive_acc,
+            None,
+            Some((taker.key, EscrowError::ReceiveAccountNotOwned)),
+        )?;
+
+        // A temp account closed under a lingering escrow is as empty as a drained one,
+        // see `fill_amounts`
+        if escrow_temp_token_acc.data_is_empty() {
+            return Err(EscrowError::StaleAmount.into());
+        }
+
+        let LoadedEscrow {
+            escrow: mut escrow_acc_info,
+            temp_token: escrow_temp_token_acc_info,
+            pda,
+            bump_seed,
+        } = Self::load_and_validate_escrow(
+            program_id,
+            escrow_acc_info,
+            escrow_temp_token_acc,
+            &taker_token_to_receive_acc_info,
+            EscrowError::TakerMintMismatch,
+        )?;
+
+        // Left by an init that didn't check the amount, or worn down by earlier
+        // fills rounding their payments up
+        if escrow_acc_info.expected_amount == 0 {
+            return Err(EscrowError::ZeroAmount.into());
+        }
+
+        // Closing the temp account into the PDA would strand its lamports, no
+        // instruction of this program ever moves them out again
+        if *escrow_maker_acc.key == pda {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        // A taker paying in SOL wraps it into their own native account
+        let native_mint = spl_token::native_mint::id();
+        let taker_token_sent_acc_info = Self::load_token_account(
+            taker_token_sent_acc,
+            wrap_sol.then_some((&native_mint, EscrowError::InvalidAccountData)),
+            wrap_sol.then_some((taker.key, EscrowError::InvalidAccountData)),
+        )?;
+        let escrow_maker_to_receive_acc_info =
+            Self::load_token_account(escrow_maker_to_receive_acc, None, None)?;
+
+        check_maker_mint(
+            &escrow_acc_info,
+            &taker_token_sent_acc_info.mint,
+            &escrow_maker_to_receive_acc_info,
+        )?;
+
+        // Now the exchange tokens are matched
+
+        // The taker receive account is in the temp token's mint, checked above, so the
+        // temp token's decimals stand for it
+        if escrow_acc_info.check_decimals {
+            let mints = [
+                (
+                    "temp token",
+                    &escrow_temp_token_acc_info.mint,
+                    escrow_acc_info.temp_token_decimals,
+                ),
+                (
+                    "token to receive",
+                    &escrow_maker_to_receive_acc_info.mint,
+                    escrow_acc_info.token_to_receive_decimals,
+                ),
+            ];
+            for (role, mint, recorded) in mints {
+                let decimals = Self::unpack_mint(
+                    find_account(trailing_accs, mint).ok_or(ProgramError::NotEnoughAccountKeys)?,
+                )?
+                .decimals;
+                if decimals != recorded {
+                    msg!(
+                        "The {} mint {} has {} decimals, the escrow recorded {}",
+                        role,
+                        mint,
+                        decimals,
+                        recorded
+                    );
+                    return Err(EscrowError::DecimalsMismatch.into());
+                }
+            }
+        }
+
+        // An oracle escrow trades at the feed's price of the moment, kept for the
+        // rest of the escrow until the next exchange
+        if let Some(price_feed) = escrow_acc_info.price_feed {
+            let feed = find_account(trailing_accs, &price_feed)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let (price_num, price_den) = oracle_price(
+                &feed.try_borrow_data()?,
+                Self::trailing_clock(trailing_accs)?.slot,
+                escrow_acc_info.min_price,
+                escrow_acc_info.max_price,
+            )?;
+            escrow_acc_info.price_num = price_num;
+            escrow_acc_info.price_den = price_den;
+        }
+
+        let amount_expected = match permille {
+            Some(permille) => permille_share(escrow_temp_token_acc_info.amount, permille)?,
+            None => amount_expected,
+        };
+        let (fill, maker_amount) = match escrow_acc_info.price() {
+            Some(price) => {
+                ratio_fill_amounts(price, escrow_temp_token_acc_info.amount, amount_expected)?
+            }
+            None => fill_amounts(
+                escrow_acc_info.expected_amount,
+                escrow_temp_token_acc_info.amount,
+                amount_expected,
+            )?,
+        };
+        check_amounts_within(&[fill, maker_amount], MAX_ESCROW_AMOUNT)?;
+        // Shares round up and the expected amount is nonzero, only a feed priced at
+        // zero leaves the maker unpaid
+        if maker_amount == 0 {
+            return Err(EscrowError::ZeroMakerPayment.into());
+        }
+        let closes_escrow = fill == escrow_temp_token_acc_info.amount;
+
+        if fill < min_receive {
+            return Err(EscrowError::SlippageExceeded.into());
+        }
+
+        // Dust protection, a fill emptying the escrow is always allowed
+        if fill < escrow_acc_info.min_fill && !closes_escrow {
+            return Err(EscrowError::BelowMinimumFill.into());
+        }
+
+        // A taker paying in SOL only needs the lamports their account is short of
+        let wrap_lamports = if wrap_sol {
+            let wrap_lamports = maker_amount.saturating_sub(taker_token_sent_acc_info.amount);
+            if taker.lamports() < wrap_lamports {
+                return Err(EscrowError::NotEnoughBalanceToSent.into());
+            }
+            wrap_lamports
+        } else if taker_token_sent_acc_info.amount < maker_amount {
+            return Err(EscrowError::NotEnoughBalanceToSent.into());
+        } else {
+            0
+        };
+        let system_program_acc = if wrap_lamports > 0 {
+            Some(
+                find_account(trailing_accs, &system_program::id())
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            )
+        } else {
+            None
+        };
+
+        // The delegate of a sent account the taker doesn't own pays in the taker's
+        // place, if it's passed after the other accounts
+        let payer = match taker_token_sent_acc_info.delegate {
+            COption::Some(delegate) if taker_token_sent_acc_info.owner != *taker.key => {
+                find_account(trailing_accs, &delegate)
+            }
+            _ => None,
+        };
+        let payer = match payer {
+            Some(delegate) => {
+                if !delegate.is_signer {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+                if taker_token_sent_acc_info.delegated_amount < maker_amount {
+                    return Err(EscrowError::NotEnoughBalanceToSent.into());
+                }
+                delegate
+            }
+            None => taker,
+        };
+
+        // The maker's token account to receive is paid the first share, the other
+        // shares go to accounts passed after the others
+        let payouts = split_payouts(maker_amount, escrow_acc_info.receive_splits())?;
+        let mut payees = vec![escrow_maker_to_receive_acc];
+        for split in escrow_acc_info.receive_splits().iter().skip(1) {
+            payees.push(
+                find_account(trailing_accs, &split.account)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            );
+        }
+        if payees.len() > 1 {
+            let mut payees_and_taker = payees.clone();
+            payees_and_taker.extend([
+                escrow_temp_token_acc,
+                taker_token_sent_acc,
+                taker_token_to_receive_acc,
+            ]);
+            check_distinct(&payees_and_taker)?;
+        }
+
+        // A frozen account takes no credit, find out before the first transfer
+        if escrow_maker_to_receive_acc_info.is_frozen()
+            || taker_token_to_receive_acc_info.is_frozen()
+        {
+            return Err(EscrowError::AccountFrozen.into());
+        }
+
+        // Both credits have to fit the receiving accounts before anything moves
+        escrow_maker_to_receive_acc_info
+            .amount
+            .checked_add(payouts[0])
+            .ok_or(EscrowError::AmountOverflow)?;
+        taker_token_to_receive_acc_info
+            .amount
+            .checked_add(fill)
+            .ok_or(EscrowError::AmountOverflow)?;
+
+        // An escrow kept as a record pays its rent out on `FinalizeClosed` instead
+        let audit_close = closes_escrow && escrow_acc_info.audit_close;
+        let rent_fee = if closes_escrow && !audit_close {
+            rent_fee(config.as_ref(), trailing_accs)?
+        } else {
+            None
+        };
+        let completed_at = if audit_close {
+            Some(Self::trailing_clock(trailing_accs)?.unix_timestamp)
+        } else {
+            None
+        };
+
+        let hook_program = match escrow_acc_info.hook_program {
+            Some(hook_program) => Some(
+                find_account(trailing_accs, &hook_program)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            ),
+            None => None,
+        };
+
+        let receipt = ExchangeReceipt {
+            amount_in: maker_amount,
+            amount_out: fill,
+            closed: closes_escrow,
+        };
+        if simulate {
+            return Ok(receipt);
+        }
+
+        // Stored before any CPI so a nested exchange of this escrow sees it, it's
+        // cleared again at the end unless the escrow gets closed
+        escrow_acc_info.locked = true;
+        Escrow::pack(
+            escrow_acc_info.clone(),
+            &mut escrow_acc.try_borrow_mut_data()?,
+        )?;
+
+        if let Some(system_program_acc) = system_program_acc {
+            let wrap_ix =
+                system_instruction::transfer(taker.key, taker_token_sent_acc.key, wrap_lamports);
+            msg!("Calling the system program to wrap the taker's SOL.");
+            invoke(
+                &wrap_ix,
+                &[
+                    taker.clone(),
+                    taker_token_sent_acc.clone(),
+                    system_program_acc.clone(),
+                ],
+            )?;
+
+            let sync_ix =
+                spl_token::instruction::sync_native(token_program.key, taker_token_sent_acc.key)?;
+            invoke(
+                &sync_ix,
+                &[taker_token_sent_acc.clone(), token_program.clone()],
+            )?;
+        }
+
+        // The taker pays first, nothing leaves the temp account until the maker got
+        // their share
+        for (payee, payout) in payees.into_iter().zip(payouts) {
+            let tx_to_maker_ix = spl_token::instruction::transfer(
+                token_program.key,
+                taker_token_sent_acc.key,
+                payee.key,
+                payer.key,
+                &[payer.key],
+                payout,
+            )?;
+
+            log_compute("the maker's payment");
+            msg!("Calling the token program to transfer tokens to the escrow's maker.");
+            invoke(
+                &tx_to_maker_ix,
+                &[
+                    taker_token_sent_acc.clone(),
+                    payee.clone(),
+                    payer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        let tx_to_taker_ix = spl_token::instruction::transfer(
+            token_program.key,
+            escrow_temp_token_acc.key,
+            taker_token_to_receive_acc.key,
+            &pda,
+            &[&pda],
+            fill,
+        )?;
+
+        log_compute("the taker's fill");
+        msg!("Calling the token program to transfer tokens to the escrow's taker.");
+        invoke_signed(
+            &tx_to_taker_ix,
+            &[
+                escrow_temp_token_acc.clone(),
+                taker_token_to_receive_acc.clone(),
+                pda_acc.clone(),
+                token_program.clone(),
+            ],
+            &[&[ESCROW_SEED, &[bump_seed]]],
+        )?;
+
+        EscrowEvent::Exchanged {
+            escrow: *escrow_acc.key,
+            initializer: *escrow_maker_acc.key,
+            taker: *taker.key,
+            amount_sent: maker_amount,
+            amount_received: fill,
+        }
+        .emit();
+
+        // Still locked, the hook can't exchange against this escrow again
+        if let Some(hook_program) = hook_program {
+            let hook_ix = Instruction::new_with_borsh(
+                *hook_program.key,
+                &SettlementHook {
+                    escrow: *escrow_acc.key,
+                    taker: *taker.key,
+                    amount: fill,
+                },
+                vec![
+                    AccountMeta::new_readonly(*escrow_acc.key, false),
+                    AccountMeta::new_readonly(*taker.key, false),
+                ],
+            );
+
+            log_compute("the hook");
+            msg!("Calling the escrow's hook program.");
+            invoke(
+                &hook_ix,
+                &[escrow_acc.clone(), taker.clone(), hook_program.clone()],
+            )?;
+        }
+
+        if !closes_escrow {
+            // The rest stays on offer at the same price
+            escrow_acc_info.expected_amount = match escrow_acc_info.price() {
+                Some((price_num, price_den)) => ratio_share(
+                    price_num,
+                    price_den,
+                    escrow_temp_token_acc_info.amount - fill,
+                )?,
+                None => escrow_acc_info.expected_amount - maker_amount,
+            };
+            escrow_acc_info.partially_filled = true;
+            escrow_acc_info.locked = false;
+            Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+            return Ok(receipt);
+        }
+
+        let close_temp_ix = spl_token::instruction::close_account(
+            token_program.key,
+            escrow_temp_token_acc.key,
+            escrow_maker_acc.key,
+            &pda,
+            &[&pda],
+        )?;
+
+        log_compute("closing the escrow");
+        msg!("Calling the token program close temp.");
+        invoke_signed(
+            &close_temp_ix,
+            &[
+                escrow_temp_token_acc.clone(),
+                escrow_maker_acc.clone(),
+                pda_acc.clone(),
+                token_program.clone(),
+            ],
+            &[&[ESCROW_SEED, &[bump_seed]]],
+        )?;
+
+        if let Some(completed_at) = completed_at {
+            msg!("Keeping the escrow as a record...");
+            escrow_acc_info.temp_token_account_pubkey = TempTokenAccount::default();
+            escrow_acc_info.completed_by = *taker.key;
+            escrow_acc_info.completed_at = completed_at;
+            escrow_acc_info.locked = false;
+            Escrow::pack(escrow_acc_info, &mut escrow_acc.try_borrow_mut_data()?)?;
+            return Ok(receipt);
+        }
+
+        msg!("Closing the escrow account...");
+        pay_out_rent(escrow_acc, escrow_maker_acc, rent_fee)?;
+        // Setting it to empty fields
+        *escrow_acc.try_borrow_mut_data()? = &mut [];
+
+        Ok(receipt)
+    }
+
+    fn process_init_escrow(
+        accounts: &[AccountInfo],
+        terms: InitTerms,
+        program_id: &Pubkey,
+    ) -> EscrowResult<()> {
+        if accounts.len() < INIT_ESCROW_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let InitTerms {
+            amount,
+            min_fill,
+            nonce,
+            accepted_mints,
+            price,
+            oracle,
+            allow_same_mint,
+            receive_splits,
+            scale,
+        } = terms;
+
+        // In the order of the `InitEscrow` docs, pinned by `build_init_escrow_transaction`
+        // and its tests
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let temp_token_account = next_account_info(account_info_iter)?;
+
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        if *token_to_receive_account.owner != spl_token::id()
+            && *token_to_receive_account.owner != system_program::id()
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        // Clients may leave the rent sysvar out, the runtime has it too. The token
+        // program moves up then, anything else there was meant as the rent sysvar
+        let rent = &match account_info_iter.as_slice().first() {
+            Some(acc) if *acc.key == sysvar::rent::id() => {
+                Rent::from_account_info(next_account_info(account_info_iter)?)?
+            }
+            Some(acc) if *acc.key != spl_token::id() => {
+                msg!(
+                    "Account 4 ({}) is neither the rent sysvar nor the token program",
+                    acc.key
+                );
+                return Err(ProgramError::InvalidArgument);
+            }
+            _ => Rent::get()?,
+        };
+
+        let token_program = next_account_info(account_info_iter)?;
+        let clock = Clock::from_account_info(next_account_info(account_info_iter)?)?;
+        check_not_paused(load_config(program_id, next_account_info(account_info_iter)?)?.as_ref())?;
+        let trailing_accs = account_info_iter.as_slice();
+
+        // Among others the maker can't be paid into the temp account, which the
+        // closing exchange empties and closes
+        check_distinct(&[
+            initializer,
+            temp_token_account,
+            token_to_receive_account,
+            escrow_account,
+        ])?;
+
+        if let Some(nonce) = &nonce {
+            Self::create_escrow_account(
+                program_id,
+                initializer,
+                escrow_account,
+                nonce,
+                rent,
+                trailing_accs,
+            )?;
+        }
+
+        // Spelled out rather than `Rent::is_exempt`, a client funding the escrow
+        // wrong gets told by how much
+        let min_lamports = rent.minimum_balance(escrow_account.data_len().max(Escrow::LEN));
+        if escrow_account.lamports() < min_lamports {
+            msg!(
+                "The escrow account holds {} lamports, it needs at least {}",
+                escrow_account.lamports(),
+                min_lamports
+            );
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+
+        // A reaped temp account would strand the escrow
+        if !rent.is_exempt(temp_token_account.lamports(), temp_token_account.data_len()) {
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+
+        // Checked ahead of the temp account, which a replayed init has already handed over
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        // Optional strict mode, enabled by passing both mints after the token program
+        let temp_token_account_info = Account::unpack(&temp_token_account.try_borrow_data()?)?;
+        check_no_delegate(&temp_token_account_info)?;
+        // An associated token account that doesn't exist yet is told apart by the
+        // mint it derives from, which the maker passes along
+        let (to_receive_mint, to_receive_created) =
+            if *token_to_receive_account.owner == spl_token::id() {
+                let token_to_receive_account_info =
+                    Account::unpack(&token_to_receive_account.try_borrow_data()?)?;
+                (token_to_receive_account_info.mint, true)
+            } else {
+                let mint = trailing_accs
+                    .iter()
+                    .find(|acc| {
+                        get_associated_token_address(initializer.key, acc.key)
+                            == *token_to_receive_account.key
+                    })
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                (*mint.key, false)
+            };
+        let decimals = match (
+            find_account(trailing_accs, &temp_token_account_info.mint),
+            find_account(trailing_accs, &to_receive_mint),
+        ) {
+            (Some(temp_mint), Some(to_receive_mint)) => Some((
+                Self::unpack_mint(temp_mint)?.decimals,
+                Self::unpack_mint(to_receive_mint)?.decimals,
+            )),
+            (None, Some(_)) if !to_receive_created => None,
+            (None, None) => None,
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+
+        // The maker's token account to receive takes the first share, and no
+        // account is paid twice
+        if let Some(first) = receive_splits.first() {
+            let mut split_keys: Vec<_> = receive_splits.iter().map(|split| split.account).collect();
+            split_keys.sort_unstable();
+            split_keys.dedup();
+            if first.account != *token_to_receive_account.key
+                || split_keys.len() != receive_splits.len()
+            {
+                return Err(EscrowError::InvalidAccountData.into());
+            }
+        }
+
+        if !accepted_mints.is_empty() && !accepted_mints.contains(&to_receive_mint) {
+            return Err(EscrowError::ExpectedMintMismatch.into());
+        }
+
+        // A multisig maker passes the multisig owning the temp account, followed by
+        // the signers that approve handing it over
+        let (temp_owner, multisig_signers) = if temp_token_account_info.owner == *initializer.key {
+            (initializer, &[][..])
+        } else {
+            let multisig_pos = trailing_accs
+                .iter()
+                .position(|acc| *acc.key == temp_token_account_info.owner)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let signers = &trailing_accs[multisig_pos + 1..];
+            if signers.is_empty() || signers.iter().any(|signer| !signer.is_signer) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            (&trailing_accs[multisig_pos], signers)
+        };
+
+        // An oracle escrow starts out at the feed's current price
+        let price = match oracle {
+            Some((price_feed, min_price, max_price)) => {
+                let feed = find_account(trailing_accs, &price_feed)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                escrow_info.price_feed = Some(price_feed);
+                escrow_info.min_price = min_price;
+                escrow_info.max_price = max_price;
+                Some(oracle_price(
+                    &feed.try_borrow_data()?,
+                    clock.slot,
+                    min_price,
+                    max_price,
+                )?)
+            }
+            None => price,
+        };
+
+        // A ratio prices the whole temp balance, `amount` is left out then
+        let amount = match price {
+            Some((price_num, price_den)) => {
+                escrow_info.price_num = price_num;
+                escrow_info.price_den = price_den;
+                ratio_share(price_num, price_den, temp_token_account_info.amount)?
+            }
+            None => amount,
+        };
+        check_listing(
+            temp_token_account.key,
+            token_to_receive_account.key,
+            !allow_same_mint && temp_token_account_info.mint == to_receive_mint,
+            &[
+                scaled_amount(amount, scale)?,
+                temp_token_account_info.amount,
+            ],
+        )?;
+
+        escrow_info.is_initialized = true;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.temp_token_account_pubkey = TempTokenAccount(*temp_token_account.key);
+        escrow_info.initializer_token_to_receive_account_pubkey =
+            MakerReceiveAccount(*token_to_receive_account.key);
+        escrow_info.expected_amount = amount;
+        escrow_info.scale = scale;
+        escrow_info.min_fill = min_fill;
+        escrow_info.nonce = nonce;
+        escrow_info.created_at = clock.unix_timestamp;
+        if let Some((temp_token_decimals, token_to_receive_decimals)) = decimals {
+            escrow_info.check_decimals = true;
+            escrow_info.temp_token_decimals = temp_token_decimals;
+            escrow_info.token_to_receive_decimals = token_to_receive_decimals;
+        }
+        escrow_info.accepted_mints_len = accepted_mints.len() as u8;
+        escrow_info.accepted_mints[..accepted_mints.len()].copy_from_slice(accepted_mints);
+        escrow_info.receive_splits_len = receive_splits.len() as u8;
+        escrow_info.receive_splits[..receive_splits.len()].copy_from_slice(receive_splits);
+
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        let (pda, _bump_seed) = find_escrow_authority(program_id);
+
+        // spl instruction to change authority
+        let signer_keys = if multisig_signers.is_empty() {
+            vec![initializer.key]
+        } else {
+            multisig_signers.iter().map(|signer| signer.key).collect()
+        };
+        let owner_change_ix = spl_token::instruction::set_authority(
+            token_program.key,
+            temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            temp_owner.key,
+            &signer_keys,
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        // CPI interface
+        // i.e. the signature is extended to the CPIs.
+        let mut owner_change_accs = vec![
+            temp_token_account.clone(),
+            temp_owner.clone(),
+            token_program.clone(),
+        ];
+        owner_change_accs.extend(multisig_signers.iter().cloned());
+        invoke(&owner_change_ix, &owner_change_accs)?;
+
+        EscrowEvent::Initialized {
+            escrow: *escrow_account.key,
+            initializer: *initializer.key,
+            temp_token_account: *temp_token_account.key,
+            expected_amount: amount,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Creates the escrow at the address derived from `nonce`. A replayed init finds
+    /// it already created and fails as already initialized.
+    fn create_escrow_account<'a>(
+        program_id: &Pubkey,
+        initializer: &AccountInfo<'a>,
+        escrow_account: &AccountInfo<'a>,
+        nonce: &[u8; 32],
+        rent: &Rent,
+        trailing_accs: &[AccountInfo<'a>],
+    ) -> EscrowResult<()> {
+        let (escrow_address, bump_seed) = find_escrow_address(program_id, initializer.key, nonce);
+        if escrow_address != *escrow_account.key {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        if escrow_account.owner == program_id {
+            return Ok(());
+        }
+
+        let system_program = find_account(trailing_accs, &system_program::id())
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let create_ix = system_instruction::create_account(
+            initializer.key,
+            escrow_account.key,
+            rent.minimum_balance(Escrow::LEN),
+            Escrow::LEN as u64,
+            program_id,
+        );
+
+        msg!("Calling the system program to create the escrow account...");
+        invoke_signed(
+            &create_ix,
+            &[
+                initializer.clone(),
+                escrow_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[ESCROW_SEED, initializer.key.as_ref(), nonce, &[bump_seed]]],
+        )
+    }
+
+    /// Unpacks the escrow this program owns and checks the temp token account passed is
+    /// the stored one
+    fn unpack_escrow(
+        program_id: &Pubkey,
+        escrow_acc: &AccountInfo,
+        escrow_temp_token_acc: &AccountInfo,
+    ) -> EscrowResult<Escrow> {
+        if escrow_acc.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Checked here rather than left to `Pack::unpack`, an account created for an
+        // escrow but never initialized holds no trade
+        let escrow = Escrow::unpack_unchecked(&escrow_acc.try_borrow_data()?)?;
+        if !escrow.is_initialized() {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        if !escrow
+            .temp_token_account_pubkey
+            .matches(escrow_temp_token_acc.key)
+        {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+
+        Ok(escrow)
+    }
+
+    /// Unpacks the escrow's temp token account, checks the account about to receive
+    /// the temp tokens holds the same mint (failing with `mint_mismatch`) and derives
+    /// the PDA, which has to still be the temp account's only authority.
+    fn load_and_validate_escrow(
+        program_id: &Pubkey,
+        escrow: Escrow,
+        escrow_temp_token_acc: &AccountInfo,
+        temp_token_receiver_acc_info: &Account,
+        mint_mismatch: EscrowError,
+    ) -> EscrowResult<LoadedEscrow> {
+        let temp_token = Self::load_token_account(escrow_temp_token_acc, None, None)?;
+        let (pda, bump_seed) = find_escrow_authority(program_id);
+        // Tell the client which account to retry with
+        if temp_token_receiver_acc_info.mint != temp_token.mint {
+            msg!("Expected a token account of mint {}", temp_token.mint);
+        }
+        check_temp_token(
+            &temp_token,
+            &temp_token_receiver_acc_info.mint,
+            mint_mismatch,
+            &pda,
+        )?;
+
+        // Handlers only see base units, the escrow is stored back unscaled
+        let escrow = Escrow {
+            expected_amount: scaled_amount(escrow.expected_amount, escrow.scale)?,
+            scale: 0,
+            ..escrow
+        };
+
+        Ok(LoadedEscrow {
+            escrow,
+            temp_token,
+            pda,
+            bump_seed,
+        })
+    }
+
+    fn unpack_mint(mint_acc: &AccountInfo) -> EscrowResult<Mint> {
+        if *mint_acc.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Mint::unpack(&mint_acc.try_borrow_data()?)
+    }
+
+    /// Unpacks a token account and checks its mint and owner against the ones
+    /// given, each failing with the error paired with it.
+    fn load_token_account(
+        token_acc: &AccountInfo,
+        expected_mint: Option<(&Pubkey, EscrowError)>,
+        expected_owner: Option<(&Pubkey, EscrowError)>,
+    ) -> EscrowResult<Account> {
+        let token = Account::unpack(&token_acc.try_borrow_data()?)?;
+        if let Some((mint, mint_mismatch)) = expected_mint {
+            if token.mint != *mint {
+                return Err(mint_mismatch.into());
+            }
+        }
+        if let Some((owner, owner_mismatch)) = expected_owner {
+            if token.owner != *owner {
+                return Err(owner_mismatch.into());
+            }
+        }
+        Ok(token)
+    }
+}
+
+/// What an account passed at a fixed position has to be
+#[derive(Clone, Copy, Debug)]
+enum Role {
+    Escrow,
+    // An escrow, or what is left of one once closed
+    EscrowOrClosed,
+    TokenAccount,
+    TokenProgram,
+}
+
+/// The roles of the accounts an instruction always takes at the same position,
+/// optional or possibly uncreated ones left out
+fn account_roles(instruction: &EscrowInstruction) -> &'static [(usize, Role)] {
+    use Role::*;
+    match instruction {
+        EscrowInstruction::InitEscrow { .. }
+        | EscrowInstruction::InitEscrowMulti { .. }
+        | EscrowInstruction::InitEscrowRatio { .. }
+        | EscrowInstruction::InitEscrowOracle { .. }
+        | EscrowInstruction::InitEscrowSplit { .. }
+        | EscrowInstruction::InitEscrowScaled { .. } => &[(1, TokenAccount)],
+        // The taker's wrapped SOL account may only be created by the fill
+        EscrowInstruction::Exchange { wrap_sol: true, .. } => {
+            &[(2, TokenAccount), (6, Escrow), (7, TokenProgram)]
+        }
+        EscrowInstruction::Exchange { .. }
+        | EscrowInstruction::SimulateExchange { .. }
+        | EscrowInstruction::PartialExchangePermille { .. } => &[
+            (1, TokenAccount),
+            (2, TokenAccount),
+            (6, Escrow),
+            (7, TokenProgram),
+        ],
+        EscrowInstruction::CancelAll | EscrowInstruction::ScanExpired { .. } => {
+            &[(1, TokenProgram)]
+        }
+        EscrowInstruction::BatchExchange { .. } => {
+            &[(1, TokenAccount), (2, TokenAccount), (3, TokenProgram)]
+        }
+        // A retried cancel passes the escrow the first one closed
+        EscrowInstruction::CancelEscrow { .. } => {
+            &[(1, TokenAccount), (3, EscrowOrClosed), (4, TokenProgram)]
+        }
+        EscrowInstruction::WithdrawPartial { .. } => {
+            &[(1, TokenAccount), (3, Escrow), (4, TokenProgram)]
+        }
+        EscrowInstruction::TopUp { .. } => &[(1, TokenAccount), (3, Escrow), (4, TokenProgram)],
+        EscrowInstruction::TransferMaker { .. } => {
+            &[(1, Escrow), (2, TokenAccount), (3, TokenAccount)]
+        }
+        EscrowInstruction::LogState => &[(0, Escrow)],
+        EscrowInstruction::Relist { .. } => &[(1, TokenAccount), (2, Escrow), (3, TokenProgram)],
+        EscrowInstruction::SetFrozen { .. }
+        | EscrowInstruction::SetExpiry { .. }
+        | EscrowInstruction::SetHook { .. }
+        | EscrowInstruction::SetAuditClose { .. }
+        | EscrowInstruction::FinalizeClosed
+        | EscrowInstruction::Migrate => &[(1, Escrow)],
+        // The config doesn't exist before the first `Admin`, the handlers check it
+        EscrowInstruction::Admin { .. } | EscrowInstruction::SetRentFee { .. } => &[],
+    }
+}
+
+/// Fails early on accounts passed for another instruction, which the handlers
+/// would only trip over somewhere down the line
+fn check_account_roles(
+    instruction: &EscrowInstruction,
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+) -> EscrowResult<()> {
+    for (position, role) in account_roles(instruction) {
+        // Too few accounts is the handler's to report
+        let acc = match accounts.get(*position) {
+            Some(acc) => acc,
+            None => continue,
+        };
+        let fits = match role {
+            Role::Escrow => acc.owner == program_id,
+            Role::EscrowOrClosed => acc.owner == program_id || is_closed(acc),
+            Role::TokenAccount => *acc.owner == spl_token::id(),
+            Role::TokenProgram => *acc.key == spl_token::id(),
+        };
+        if !fits {
+            msg!(
+                "Account {} ({}) isn't the {:?} this instruction expects there",
+                position,
+                acc.key,
+                role
+            );
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+    }
+    Ok(())
+}
+
+/// The positions of the accounts an instruction writes to, as marked `[w]` in its
+/// docs. Accounts after the fixed ones are left to the handlers
+fn writable_accounts(instruction: &EscrowInstruction) -> &'static [usize] {
+    match instruction {
+        EscrowInstruction::InitEscrow { .. }
+        | EscrowInstruction::InitEscrowMulti { .. }
+        | EscrowInstruction::InitEscrowRatio { .. }
+        | EscrowInstruction::InitEscrowOracle { .. }
+        | EscrowInstruction::InitEscrowSplit { .. }
+        | EscrowInstruction::InitEscrowScaled { .. } => &[1, 3],
+        EscrowInstruction::Exchange { .. } | EscrowInstruction::PartialExchangePermille { .. } => {
+            &[1, 2, 3, 4, 5, 6]
+        }
+        EscrowInstruction::BatchExchange { .. } => &[1, 2],
+        EscrowInstruction::CancelEscrow { .. }
+        | EscrowInstruction::WithdrawPartial { .. }
+        | EscrowInstruction::TopUp { .. } => &[1, 2, 3],
+        EscrowInstruction::Relist { .. } => &[1, 2],
+        EscrowInstruction::SetFrozen { .. }
+        | EscrowInstruction::SetExpiry { .. }
+        | EscrowInstruction::SetRentFee { .. }
+        | EscrowInstruction::SetHook { .. }
+        | EscrowInstruction::SetAuditClose { .. }
+        | EscrowInstruction::TransferMaker { .. } => &[1],
+        EscrowInstruction::Migrate
+        | EscrowInstruction::Admin { .. }
+        | EscrowInstruction::FinalizeClosed => &[0, 1],
+        // A simulation writes nothing
+        EscrowInstruction::SimulateExchange { .. }
+        | EscrowInstruction::CancelAll
+        | EscrowInstruction::ScanExpired { .. }
+        | EscrowInstruction::LogState => &[],
+    }
+}
+
+/// Fails on a read-only account where the instruction writes, before a CPI or the
+/// runtime rejects the write with a less telling error
+fn check_writable(instruction: &EscrowInstruction, accounts: &[AccountInfo]) -> EscrowResult<()> {
+    for position in writable_accounts(instruction) {
+        match accounts.get(*position) {
+            Some(acc) if !acc.is_writable => {
+                msg!("Account {} ({}) has to be writable", position, acc.key);
+                return Err(ProgramError::InvalidArgument);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `acc` has neither lamports nor data, as an escrow does once closed. The
+/// runtime hands it back to the system program after the transaction, so an
+/// address never used looks the same. An escrow created but never initialized
+/// still holds its rent and data
+fn is_closed(acc: &AccountInfo) -> bool {
+    acc.lamports() == 0 && acc.data_is_empty()
+}
+
+/// Fails if a delegate can still spend from the account about to become a temp account
+fn check_no_delegate(temp_token: &Account) -> EscrowResult<()> {
+    if temp_token.delegate.is_some() && temp_token.delegated_amount > 0 {
+        return Err(EscrowError::TempAccountHasDelegate.into());
+    }
+    Ok(())
+}
+
+/// Logs the compute units left ahead of `step`, with the `debug-compute` feature only
+#[cfg(feature = "debug-compute")]
+fn log_compute(step: &str) {
+    msg!("Compute units before {}:", step);
+    solana_program::log::sol_log_compute_units();
+}
+
+#[cfg(not(feature = "debug-compute"))]
+fn log_compute(_step: &str) {}
+
+/// Moves `amount` lamports from `from` to `to`, failing with `AmountOverflow` rather
+/// than minting or burning any
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    **from.lamports.borrow_mut() = from_lamports;
+    **to.lamports.borrow_mut() = to_lamports;
+    Ok(())
+}
+
+/// Drains all of `from`'s lamports into `to`, closing `from` once its data is cleared
+fn credit_lamports(from: &AccountInfo, to: &AccountInfo) -> ProgramResult {
+    move_lamports(from, to, from.lamports())
+}
+
+/// The amount `quote_program` left as return data for the exchange to fill
+fn quoted_amount(quote_program: &Pubkey) -> EscrowResult<u64> {
+    match get_return_data() {
+        Some((program_id, data)) if program_id == *quote_program => data
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| EscrowError::InvalidQuote.into()),
+        _ => Err(EscrowError::InvalidQuote.into()),
+    }
+}
+
+/// The program's config, `None` before the admin's first call creates it. Any other
+/// account than the one at `find_config_address` fails
+fn load_config(program_id: &Pubkey, config_acc: &AccountInfo) -> EscrowResult<Option<Config>> {
+    if *config_acc.key != find_config_address(program_id).0 {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+    if config_acc.owner != program_id {
+        return Ok(None);
+    }
+    Ok(Some(Config::unpack(&config_acc.try_borrow_data()?)?))
+}
+
+/// Fails with `ProgramPaused` while the admin has the program paused
+fn check_not_paused(config: Option<&Config>) -> EscrowResult<()> {
+    if config.is_some_and(|config| config.paused) {
+        return Err(EscrowError::ProgramPaused.into());
+    }
+    Ok(())
+}
+
+/// The admin's rent fee and the account it's paid to, `None` while there is none.
+/// The fee account has to be among `trailing_accs` once there is one
+fn rent_fee<'a, 'b>(
+    config: Option<&Config>,
+    trailing_accs: &'b [AccountInfo<'a>],
+) -> EscrowResult<Option<(u16, &'b AccountInfo<'a>)>> {
+    let config = match config {
+        Some(config) if config.rent_fee_bps > 0 => config,
+        _ => return Ok(None),
+    };
+    let rent_fee_acc = find_account(trailing_accs, &config.rent_fee_account)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    Ok(Some((config.rent_fee_bps, rent_fee_acc)))
+}
+
+/// Drains the escrow's rent into `maker`, less the `rent_fee` share
+fn pay_out_rent(
+    escrow_acc: &AccountInfo,
+    maker: &AccountInfo,
+    rent_fee: Option<(u16, &AccountInfo)>,
+) -> ProgramResult {
+    if let Some((rent_fee_bps, rent_fee_acc)) = rent_fee {
+        let (_maker_lamports, fee_lamports) = rent_split(escrow_acc.lamports(), rent_fee_bps)?;
+        move_lamports(escrow_acc, rent_fee_acc, fee_lamports)?;
+    }
+    credit_lamports(escrow_acc, maker)
+}
+
+/// The checks `InitEscrow` and `Relist` share on the trade they list
+fn check_listing(
+    temp_key: &Pubkey,
+    receive_key: &Pubkey,
+    same_mint: bool,
+    amounts: &[u64],
+) -> EscrowResult<()> {
+    // The closing exchange empties and closes the temp account, the maker can't
+    // be paid into it
+    if temp_key == receive_key {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+    // Trading a token for itself is almost always a mistake
+    if same_mint {
+        return Err(EscrowError::SameMint.into());
+    }
+    check_amounts_within(amounts, MAX_ESCROW_AMOUNT)
+}
+
+/// Fails if two of `accounts` are the same, they hold roles that must not alias
+fn check_distinct(accounts: &[&AccountInfo]) -> EscrowResult<()> {
+    for (i, acc) in accounts.iter().enumerate() {
+        if accounts[i + 1..].iter().any(|other| other.key == acc.key) {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+    }
+    Ok(())
+}
+
+/// For instructions without optional trailing accounts, fails on any account
+/// past the `expected` they take
+fn check_account_count(accounts: &[AccountInfo], expected: usize) -> ProgramResult {
+    match accounts.len().cmp(&expected) {
+        Ordering::Less => Err(ProgramError::NotEnoughAccountKeys),
+        Ordering::Greater => Err(EscrowError::TooManyAccounts.into()),
+        Ordering::Equal => Ok(()),
+    }
+}
+
+/// Looks up an optional trailing account by its key
+fn find_account<'a, 'b>(
+    accounts: &'b [AccountInfo<'a>],
+    key: &Pubkey,
+) -> Option<&'b AccountInfo<'a>> {
+    accounts.iter().find(|acc| acc.key == key)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            config::{MAX_BPS, MAX_ESCROW_AGE_SECONDS, MAX_MEMO_LEN, MAX_ORACLE_AGE_SLOTS},
+            event::{parse_exchange_receipt, parse_settlement_hook},
+            instruction::{
+                build_cancel_instruction, build_exchange_instruction,
+                build_init_escrow_transaction, CancelKeys, ExchangeKeys, InitEscrowTransaction,
+                EXCHANGE_QUOTED_AMOUNT,
+            },
+            state::ESCROW_LEGACY_LENS,
+        },
+        solana_program::{
+            instruction::Instruction,
+            program::get_return_data,
+            program_stubs::{set_syscall_stubs, SyscallStubs},
+            system_program,
+            sysvar::{self, SysvarId},
+        },
+        spl_token::state::{AccountState, Multisig},
+        std::{cell::RefCell, convert::TryInto, sync::Once},
+    };
+
+    thread_local! {
+        static CALLER: RefCell<Pubkey> = RefCell::new(Pubkey::default());
+        static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+        // Calls to any program the tests don't run, hooks and the memo program
+        static HOOK_CALLS: RefCell<Vec<Instruction>> = const { RefCell::new(Vec::new()) };
+        // The seeds of every `invoke_signed`, one list per signer
+        static SIGNER_SEEDS: RefCell<Vec<Vec<Vec<u8>>>> = const { RefCell::new(Vec::new()) };
+        // The runtime's clock, `None` for a runtime without one
+        static NOW: RefCell<Option<i64>> = const { RefCell::new(Some(FIXTURE_NOW)) };
+    }
+
+    /// Settlement hooks, any program the stubs don't know records its calls and
+    /// this one fails them as well
+    const RECORDING_HOOK: Pubkey = Pubkey::new_from_array([1; 32]);
+    const FAILING_HOOK: Pubkey = Pubkey::new_from_array([2; 32]);
+
+    /// Routes CPIs into the real token program, granting the caller's PDA
+    /// signatures the way the runtime would.
+    struct TestSyscallStubs;
+
+    impl SyscallStubs for TestSyscallStubs {
+        fn sol_log(&self, message: &str) {
+            LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            let caller = CALLER.with(|caller| *caller.borrow());
+            RETURN_DATA
+                .with(|return_data| *return_data.borrow_mut() = Some((caller, data.to_vec())));
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            RETURN_DATA.with(|return_data| return_data.borrow().clone())
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let unix_timestamp = match NOW.with(|now| *now.borrow()) {
+                Some(now) => now,
+                None => return solana_program::program_error::UNSUPPORTED_SYSVAR,
+            };
+            let clock = Clock {
+                unix_timestamp,
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let caller = CALLER.with(|caller| *caller.borrow());
+            SIGNER_SEEDS.with(|recorded| {
+                let seeds = signers_seeds
+                    .iter()
+                    .map(|seeds| seeds.iter().map(|seed| seed.to_vec()).collect());
+                recorded.borrow_mut().extend(seeds)
+            });
+            let signers = signers_seeds
+                .iter()
+                .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut infos = Vec::with_capacity(instruction.accounts.len());
+            for meta in &instruction.accounts {
+                let mut info = account_infos
+                    .iter()
+                    .find(|info| *info.key == meta.pubkey)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?
+                    .clone();
+                info.is_signer = info.is_signer || signers.contains(info.key);
+                infos.push(info);
+            }
+
+            if instruction.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &infos,
+                    &instruction.data,
+                )
+            } else if instruction.program_id == system_program::id() {
+                process_system(&infos, &instruction.data)
+            } else if instruction.program_id == spl_associated_token_account::id() {
+                // The nested CPIs are signed for by the associated token program
+                CALLER.with(|caller| *caller.borrow_mut() = instruction.program_id);
+                let result = spl_associated_token_account::processor::process_instruction(
+                    &instruction.program_id,
+                    &infos,
+                    &instruction.data,
+                );
+                CALLER.with(|callee| *callee.borrow_mut() = caller);
+                result
+            } else {
+                HOOK_CALLS.with(|calls| calls.borrow_mut().push(instruction.clone()));
+                if instruction.program_id == FAILING_HOOK {
+                    return Err(ProgramError::Custom(u32::MAX));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The parts of the system program the tests rely on, accounts' data is
+    /// allocated up front by the test
+    fn process_system(infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        let (tag, rest) = data.split_at(4);
+        let amount = || u64::from_le_bytes(rest[..8].try_into().unwrap());
+        if !infos[0].is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        match u32::from_le_bytes(tag.try_into().unwrap()) {
+            0 => create_account(infos, data),
+            1 => {
+                infos[0].assign(&Pubkey::new(&rest[..32]));
+                Ok(())
+            }
+            2 => {
+                **infos[0].lamports.borrow_mut() -= amount();
+                **infos[1].lamports.borrow_mut() += amount();
+                Ok(())
+            }
+            8 if infos[0].data_len() as u64 == amount() => Ok(()),
+            8 => Err(ProgramError::InvalidArgument),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    /// `CreateAccount`, the new account's data is allocated up front by the test
+    fn create_account(infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        let (from, to) = (&infos[0], &infos[1]);
+        assert_eq!(data[..4], 0u32.to_le_bytes());
+        let lamports = u64::from_le_bytes(data[4..12].try_into().unwrap());
+        let space = u64::from_le_bytes(data[12..20].try_into().unwrap());
+        if !from.is_signer || !to.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if to.lamports() != 0 || to.data_len() as u64 != space {
+            return Err(ProgramError::InvalidArgument);
+        }
+        **from.lamports.borrow_mut() -= lamports;
+        **to.lamports.borrow_mut() = lamports;
+        to.assign(&Pubkey::new(&data[20..52]));
+        Ok(())
+    }
+
+    fn process(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(TestSyscallStubs));
+        });
+        CALLER.with(|caller| *caller.borrow_mut() = *program_id);
+        Processor::process(program_id, accounts, data)
+    }
+
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+    }
+
+    impl TestAccount {
+        fn new(owner: &Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key: Pubkey::new_unique(),
+                owner: *owner,
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                is_signer: false,
+            }
+        }
+
+        fn wallet() -> Self {
+            Self {
+                is_signer: true,
+                ..Self::new(&system_program::id(), vec![])
+            }
+        }
+
+        fn mint(decimals: u8) -> Self {
+            let mut data = vec![0; Mint::LEN];
+            let mint = Mint {
+                mint_authority: COption::None,
+                supply: u64::MAX,
+                decimals,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            };
+            Mint::pack(mint, &mut data).unwrap();
+            Self::new(&spl_token::id(), data)
+        }
+
+        fn token(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Self {
+            let mut data = vec![0; Account::LEN];
+            let account = Account {
+                mint: *mint,
+                owner: *owner,
+                amount,
+                state: AccountState::Initialized,
+                ..Account::default()
+            };
+            Account::pack(account, &mut data).unwrap();
+            Self::new(&spl_token::id(), data)
+        }
+
+        fn native_token(owner: &Pubkey, amount: u64) -> Self {
+            let rent_reserve = Rent::default().minimum_balance(Account::LEN);
+            let mut data = vec![0; Account::LEN];
+            let account = Account {
+                mint: spl_token::native_mint::id(),
+                owner: *owner,
+                amount,
+                state: AccountState::Initialized,
+                is_native: COption::Some(rent_reserve),
+                ..Account::default()
+            };
+            Account::pack(account, &mut data).unwrap();
+            Self {
+                lamports: rent_reserve + amount,
+                ..Self::new(&spl_token::id(), data)
+            }
+        }
+
+        fn sysvar<S: Sysvar + SysvarId>(sysvar: &S) -> Self {
+            let mut account = Self {
+                key: S::id(),
+                ..Self::new(&sysvar::id(), vec![0; S::size_of()])
+            };
+            sysvar.to_account_info(&mut account.info()).unwrap();
+            account
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    /// The unix timestamp of the fixture's clock
+    const FIXTURE_NOW: i64 = 1_650_000_000;
+
+    /// Every account taking part in one escrow's lifecycle.
+    struct Fixture {
+        program_id: Pubkey,
+        maker: TestAccount,
+        temp: TestAccount,
+        maker_receive: TestAccount,
+        maker_refund: TestAccount,
+        escrow: TestAccount,
+        taker: TestAccount,
+        taker_sent: TestAccount,
+        taker_receive: TestAccount,
+        temp_mint: TestAccount,
+        receive_mint: TestAccount,
+        rent: TestAccount,
+        clock: TestAccount,
+        token_program: TestAccount,
+        pda: TestAccount,
+        config: TestAccount,
+    }
+
+    impl Fixture {
+        fn new(temp_amount: u64, taker_amount: u64) -> Self {
+            let program_id = Pubkey::new_unique();
+            let maker = TestAccount::wallet();
+            let taker = TestAccount::wallet();
+            let temp_mint = TestAccount::mint(6);
+            let receive_mint = TestAccount::mint(9);
+            let (pda, _bump_seed) = find_escrow_authority(&program_id);
+            let (config, _bump_seed) = find_config_address(&program_id);
+
+            Self {
+                temp: TestAccount::token(&temp_mint.key, &maker.key, temp_amount),
+                maker_receive: TestAccount::token(&receive_mint.key, &maker.key, 0),
+                maker_refund: TestAccount::token(&temp_mint.key, &maker.key, 0),
+                escrow: TestAccount::new(&program_id, vec![0; Escrow::LEN]),
+                taker_sent: TestAccount::token(&receive_mint.key, &taker.key, taker_amount),
+                taker_receive: TestAccount::token(&temp_mint.key, &taker.key, 0),
+                rent: TestAccount::sysvar(&Rent::default()),
+                clock: TestAccount::sysvar(&Clock {
+                    unix_timestamp: FIXTURE_NOW,
+                    ..Clock::default()
+                }),
+                token_program: TestAccount {
+                    key: spl_token::id(),
+                    ..TestAccount::new(&Pubkey::default(), vec![])
+                },
+                pda: TestAccount {
+                    key: pda,
+                    ..TestAccount::new(&system_program::id(), vec![])
+                },
+                // Not created until a test pauses the program
+                config: TestAccount {
+                    key: config,
+                    lamports: 0,
+                    ..TestAccount::new(&system_program::id(), vec![0; Config::LEN])
+                },
+                program_id,
+                maker,
+                taker,
+                temp_mint,
+                receive_mint,
+            }
+        }
+
+        fn infos(&mut self) -> Infos<'_> {
+            Infos {
+                maker: self.maker.info(),
+                temp: self.temp.info(),
+                maker_receive: self.maker_receive.info(),
+                maker_refund: self.maker_refund.info(),
+                escrow: self.escrow.info(),
+                taker: self.taker.info(),
+                taker_sent: self.taker_sent.info(),
+                taker_receive: self.taker_receive.info(),
+                temp_mint: self.temp_mint.info(),
+                receive_mint: self.receive_mint.info(),
+                rent: self.rent.info(),
+                clock: self.clock.info(),
+                token_program: self.token_program.info(),
+                pda: self.pda.info(),
+                config: self.config.info(),
+            }
+        }
+
+        /// The accounts after a plain `InitEscrow` of the temp tokens for 500, the
+        /// state most tests start from
+        fn initialized(&mut self) -> (Pubkey, Infos<'_>) {
+            let program_id = self.program_id;
+            let infos = self.infos();
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500),
+            )
+            .unwrap();
+            (program_id, infos)
+        }
+    }
+
+    struct Infos<'a> {
+        maker: AccountInfo<'a>,
+        temp: AccountInfo<'a>,
+        maker_receive: AccountInfo<'a>,
+        maker_refund: AccountInfo<'a>,
+        escrow: AccountInfo<'a>,
+        taker: AccountInfo<'a>,
+        taker_sent: AccountInfo<'a>,
+        taker_receive: AccountInfo<'a>,
+        temp_mint: AccountInfo<'a>,
+        receive_mint: AccountInfo<'a>,
+        rent: AccountInfo<'a>,
+        clock: AccountInfo<'a>,
+        token_program: AccountInfo<'a>,
+        pda: AccountInfo<'a>,
+        config: AccountInfo<'a>,
+    }
+
+    impl<'a> Infos<'a> {
+        fn init_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.maker.clone(),
+                self.temp.clone(),
+                self.maker_receive.clone(),
+                self.escrow.clone(),
+                self.rent.clone(),
+                self.token_program.clone(),
+                self.clock.clone(),
+                self.config.clone(),
+            ]
+        }
+
+        fn exchange_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.taker.clone(),
+                self.taker_sent.clone(),
+                self.taker_receive.clone(),
+                self.temp.clone(),
+                self.maker.clone(),
+                self.maker_receive.clone(),
+                self.escrow.clone(),
+                self.token_program.clone(),
+                self.pda.clone(),
+                self.config.clone(),
+            ]
+        }
+
+        /// The maker and the escrow, followed by two token accounts for the
+        /// instructions taking more
+        fn settings_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.maker.clone(),
+                self.escrow.clone(),
+                self.maker_receive.clone(),
+                self.taker_receive.clone(),
+            ]
+        }
+
+        fn hook_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![self.maker.clone(), self.escrow.clone()]
+        }
+
+        fn finalize_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![self.maker.clone(), self.escrow.clone(), self.config.clone()]
+        }
+
+        /// A `BatchExchange` of this escrow alone
+        fn batch_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.taker.clone(),
+                self.taker_sent.clone(),
+                self.taker_receive.clone(),
+                self.token_program.clone(),
+                self.pda.clone(),
+                self.config.clone(),
+                self.escrow.clone(),
+                self.temp.clone(),
+                self.maker.clone(),
+                self.maker_receive.clone(),
+            ]
+        }
+
+        fn cancel_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.maker.clone(),
+                self.maker_refund.clone(),
+                self.temp.clone(),
+                self.escrow.clone(),
+                self.token_program.clone(),
+                self.pda.clone(),
+            ]
+        }
+
+        /// A `Relist` taking the refund account as the new temp account
+        fn relist_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.maker.clone(),
+                self.maker_refund.clone(),
+                self.escrow.clone(),
+                self.token_program.clone(),
+                self.clock.clone(),
+                self.config.clone(),
+                self.maker_receive.clone(),
+            ]
+        }
+
+        /// A `CancelAll` of this escrow alone
+        fn cancel_all_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![
+                self.maker.clone(),
+                self.token_program.clone(),
+                self.pda.clone(),
+                self.escrow.clone(),
+                self.temp.clone(),
+                self.maker_refund.clone(),
+            ]
+        }
+
+        fn mint_accounts(&self) -> Vec<AccountInfo<'a>> {
+            vec![self.temp_mint.clone(), self.receive_mint.clone()]
+        }
+
+        fn escrow_state(&self) -> Escrow {
+            Escrow::unpack(&self.escrow.data.borrow()).unwrap()
+        }
+
+        fn token_state(info: &AccountInfo) -> Account {
+            Account::unpack(&info.data.borrow()).unwrap()
+        }
+
+        fn set_token_amount(info: &AccountInfo, amount: u64) {
+            let mut account = Self::token_state(info);
+            account.amount = amount;
+            Account::pack(account, &mut info.data.borrow_mut()).unwrap();
+        }
+
+        fn set_token_mint(info: &AccountInfo, mint: &Pubkey) {
+            let mut account = Self::token_state(info);
+            account.mint = *mint;
+            Account::pack(account, &mut info.data.borrow_mut()).unwrap();
+        }
+    }
+
+    fn instruction_data(tag: u8, amount: u64) -> Vec<u8> {
+        let mut data = vec![tag];
+        data.extend_from_slice(&amount.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_init_strict_records_decimals() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        let accounts = [infos.init_accounts(), infos.mint_accounts()].concat();
+        process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+
+        let escrow = infos.escrow_state();
+        assert!(escrow.check_decimals);
+        assert_eq!(escrow.temp_token_decimals, 6);
+        assert_eq!(escrow.token_to_receive_decimals, 9);
+    }
+
+    #[test]
+    fn test_init_without_mints_skips_decimals() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (_, infos) = fixture.initialized();
+
+        assert!(!infos.escrow_state().check_decimals);
+
+        // a single mint is not enough to opt into strict mode
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let accounts = [infos.init_accounts(), vec![infos.temp_mint.clone()]].concat();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(0, 500)),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn test_exchange_strict_decimals() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        let accounts = [infos.init_accounts(), infos.mint_accounts()].concat();
+        process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+
+        // strict escrows can't be exchanged without the mints
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let accounts = [infos.exchange_accounts(), infos.mint_accounts()].concat();
+        process(&program_id, &accounts, &instruction_data(1, 100)).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+    }
+
+    #[test]
+    fn test_exchange_decimals_mismatch() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        let accounts = [infos.init_accounts(), infos.mint_accounts()].concat();
+        process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+
+        let mut mint = Mint::unpack(&infos.receive_mint.data.borrow()).unwrap();
+        mint.decimals = 6;
+        Mint::pack(mint, &mut infos.receive_mint.data.borrow_mut()).unwrap();
+
+        let accounts = [infos.exchange_accounts(), infos.mint_accounts()].concat();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::DecimalsMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_init_temp_not_rent_exempt() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.temp.lamports -= 1;
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500)
+            ),
+            Err(ProgramError::AccountNotRentExempt)
+        );
+        assert!(
+            !Escrow::try_from_account_data(&infos.escrow.data.borrow())
+                .unwrap()
+                .is_initialized
+        );
+    }
+
+    #[test]
+    fn test_set_frozen() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // only the maker may freeze
+        let accounts = vec![infos.taker.clone(), infos.escrow.clone()];
+        assert_eq!(
+            process(&program_id, &accounts, &[3, 1]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        let accounts = vec![infos.maker.clone(), infos.escrow.clone()];
+        process(&program_id, &accounts, &[3, 1]).unwrap();
+        assert!(infos.escrow_state().frozen);
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::EscrowFrozen.into())
+        );
+
+        process(&program_id, &accounts, &[3, 0]).unwrap();
+        assert!(!infos.escrow_state().frozen);
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cancel_while_frozen() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let accounts = vec![infos.maker.clone(), infos.escrow.clone()];
+        process(&program_id, &accounts, &[3, 1]).unwrap();
+
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_temp_token_receiver_mint_mismatch() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        Infos::set_token_mint(&infos.taker_receive, infos.receive_mint.key);
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::TakerMintMismatch.into())
+        );
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[1] = infos.maker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::RefundMintMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_cancel_twice() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut garbage_collected = TestAccount {
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![])
+        };
+        let mut uninitialized = TestAccount::new(&program_id, vec![0; Escrow::LEN]);
+        let (_, infos) = fixture.initialized();
+
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        let maker_lamports = infos.maker.lamports();
+
+        // the retry lands on the closed escrow, and once the runtime took it back
+        // on the system program's
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        let expected = format!("Escrow {} is already closed", infos.escrow.key);
+        assert!(LOGS.with(|logs| logs.borrow().contains(&expected)));
+        let mut accounts = infos.cancel_accounts();
+        accounts[3] = garbage_collected.info();
+        process(&program_id, &accounts, &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        assert_eq!(infos.maker.lamports(), maker_lamports);
+
+        // an escrow never initialized is no cancelled one
+        accounts[3] = uninitialized.info();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_cancel_retries_with_refund_mint() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let mut accounts = infos.cancel_accounts();
+        accounts[1] = infos.maker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::RefundMintMismatch.into())
+        );
+        let expected = format!("Expected a token account of mint {}", infos.temp_mint.key);
+        assert!(LOGS.with(|logs| logs.borrow().contains(&expected)));
+
+        // nothing moved, the maker retries with an account of that mint
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_cancel_confirm_amount() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+
+        // the client still saw the full 100 before the fill took 40
+        let cancel = |confirm_amount| {
+            EscrowInstruction::CancelEscrow {
+                unwrap_sol: false,
+                keep_escrow: false,
+                confirm_amount: Some(confirm_amount),
+            }
+            .pack()
+        };
+        assert_eq!(
+            process(&program_id, &infos.cancel_accounts(), &cancel(100)),
+            Err(EscrowError::StaleAmount.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 60);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 0);
+
+        process(&program_id, &infos.cancel_accounts(), &cancel(60)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 60);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_maker_side_mint_mismatch() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut taker_other = TestAccount::token(&Pubkey::new_unique(), &fixture.taker.key, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut accounts = infos.exchange_accounts();
+        accounts[1] = taker_other.info();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::MakerMintMismatch.into())
+        );
+        assert_ne!(
+            ProgramError::from(EscrowError::MakerMintMismatch),
+            ProgramError::from(EscrowError::TakerMintMismatch)
+        );
+    }
+
+    #[test]
+    fn test_exchange_zero_maker_payment() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &instruction_data(0, 1)).unwrap();
+
+        // the first token rounds the whole expected amount up into its price
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 1),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 1);
+        assert_eq!(infos.escrow_state().expected_amount, 0);
+
+        // turned away before the payment is priced
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 1),
+            ),
+            Err(EscrowError::ZeroAmount.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 99);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 1);
+    }
+
+    #[test]
+    fn test_exchange_zero_expected_amount() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let mut escrow = infos.escrow_state();
+        escrow.expected_amount = 0;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100),
+            ),
+            Err(EscrowError::ZeroAmount.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 0);
+
+        // the maker can still take the tokens back
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+    }
+
+    #[test]
+    fn test_load_token_account() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let mut token = TestAccount::token(&mint, &owner, 100);
+        let info = token.info();
+        let load = |expected_mint, expected_owner| {
+            Processor::load_token_account(
+                &info,
+                Some((expected_mint, EscrowError::TakerMintMismatch)),
+                Some((expected_owner, EscrowError::ReceiveAccountNotOwned)),
+            )
+        };
+
+        assert_eq!(load(&mint, &owner).unwrap().amount, 100);
+        assert_eq!(
+            load(&other, &owner),
+            Err(EscrowError::TakerMintMismatch.into())
+        );
+        assert_eq!(
+            load(&mint, &other),
+            Err(EscrowError::ReceiveAccountNotOwned.into())
+        );
+        // the mint is checked first
+        assert_eq!(
+            load(&other, &other),
+            Err(EscrowError::TakerMintMismatch.into())
+        );
+        assert_eq!(
+            Processor::load_token_account(&info, None, None)
+                .unwrap()
+                .owner,
+            owner
+        );
+
+        let mut wallet = TestAccount::wallet();
+        assert_eq!(
+            Processor::load_token_account(&wallet.info(), None, None),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_exchange_mint_mismatch_combinations() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let temp_mint = *infos.temp_mint.key;
+        let receive_mint = *infos.receive_mint.key;
+        let other_mint = Pubkey::new_unique();
+        // (taker sent, taker receive, maker receive) mints, the taker side is
+        // checked first
+        let cases = [
+            (
+                other_mint,
+                temp_mint,
+                receive_mint,
+                EscrowError::MakerMintMismatch,
+            ),
+            (
+                receive_mint,
+                temp_mint,
+                other_mint,
+                EscrowError::MakerMintMismatch,
+            ),
+            (
+                receive_mint,
+                other_mint,
+                receive_mint,
+                EscrowError::TakerMintMismatch,
+            ),
+            (
+                receive_mint,
+                receive_mint,
+                receive_mint,
+                EscrowError::TakerMintMismatch,
+            ),
+            (
+                other_mint,
+                other_mint,
+                receive_mint,
+                EscrowError::TakerMintMismatch,
+            ),
+            (
+                temp_mint,
+                receive_mint,
+                receive_mint,
+                EscrowError::TakerMintMismatch,
+            ),
+        ];
+        for (sent, receive, maker_receive, error) in cases {
+            Infos::set_token_mint(&infos.taker_sent, &sent);
+            Infos::set_token_mint(&infos.taker_receive, &receive);
+            Infos::set_token_mint(&infos.maker_receive, &maker_receive);
+            for tag in [6, 1] {
+                assert_eq!(
+                    process(
+                        &program_id,
+                        &infos.exchange_accounts(),
+                        &instruction_data(tag, 100)
+                    ),
+                    Err(error.into()),
+                    "instruction {} with {:?}",
+                    tag,
+                    (sent, receive, maker_receive)
+                );
+            }
+            assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+            assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+            assert_eq!(infos.escrow_state().expected_amount, 500);
+        }
+
+        Infos::set_token_mint(&infos.taker_sent, &receive_mint);
+        Infos::set_token_mint(&infos.taker_receive, &temp_mint);
+        Infos::set_token_mint(&infos.maker_receive, &receive_mint);
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_escrow_seed_prefix_used_throughout() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let (fork_pda, _bump_seed) =
+            Pubkey::find_program_address(&[&[ESCROW_SEED, b"-fork"].concat()], &program_id);
+        let mut fork = TestAccount {
+            key: fork_pda,
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let infos = fixture.infos();
+        let fork = fork.info();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[ESCROW_SEED], &program_id);
+        assert_eq!(*infos.pda.key, pda);
+
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, 500),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.temp).owner, pda);
+
+        // another prefix can't sign for the temp account
+        let mut accounts = infos.exchange_accounts();
+        accounts[8] = fork.clone();
+        assert!(process(&program_id, &accounts, &instruction_data(1, 40)).is_err());
+        // the runtime would have rolled the failed exchange back, lock included
+        let escrow = Escrow {
+            locked: false,
+            ..infos.escrow_state()
+        };
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        let mut accounts = infos.cancel_accounts();
+        accounts[5] = fork;
+        assert!(process(&program_id, &accounts, &[2]).is_err());
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 40);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 60);
+    }
+
+    #[test]
+    fn test_signer_seeds_match_init_authority() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        // the authority init hands the temp account to
+        let pda = Infos::token_state(&infos.temp).owner;
+
+        SIGNER_SEEDS.with(|recorded| recorded.borrow_mut().clear());
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+
+        let recorded = SIGNER_SEEDS.with(|recorded| recorded.take());
+        // the exchange's transfer, the cancel's refund and close
+        assert_eq!(recorded.len(), 3);
+        for seeds in recorded {
+            let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+            assert_eq!(seeds[0], ESCROW_SEED);
+            assert_eq!(seeds.len(), 2, "only the bump follows the seed");
+            assert_eq!(Pubkey::create_program_address(&seeds, &program_id), Ok(pda));
+        }
+    }
+
+    #[test]
+    fn test_init_hands_temp_to_pda() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        assert_eq!(*infos.temp.owner, spl_token::id());
+        let temp = Infos::token_state(&infos.temp);
+        assert_eq!(temp.owner, find_escrow_authority(&program_id).0);
+        assert_eq!(temp.amount, 100);
+
+        let escrow = infos.escrow_state();
+        assert!(escrow.is_initialized);
+        assert_eq!(escrow.initializer_pubkey, *infos.maker.key);
+        assert!(escrow.temp_token_account_pubkey.matches(infos.temp.key));
+        assert!(escrow
+            .initializer_token_to_receive_account_pubkey
+            .matches(infos.maker_receive.key));
+        assert_eq!(escrow.expected_amount, 500);
+        assert_eq!(escrow.min_fill, 0);
+        assert_eq!(escrow.nonce, None);
+        assert!(!escrow.frozen && !escrow.locked && !escrow.check_decimals);
+        assert_eq!(escrow.created_at, FIXTURE_NOW);
+    }
+
+    #[test]
+    fn test_init_stamps_created_at() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.created_at, FIXTURE_NOW);
+        assert_eq!(escrow.age(FIXTURE_NOW + 60), Some(60));
+
+        // the clock is required, another sysvar in its place fails to parse
+        let mut accounts = infos.init_accounts();
+        accounts[6] = infos.rent.clone();
+        assert!(process(&program_id, &accounts, &instruction_data(0, 500)).is_err());
+    }
+
+    #[test]
+    fn test_init_with_and_without_rent_account() {
+        for with_rent in [true, false] {
+            let mut fixture = Fixture::new(100, 1_000);
+            let program_id = fixture.program_id;
+            let infos = fixture.infos();
+            let mut accounts = infos.init_accounts();
+            if !with_rent {
+                accounts.remove(4);
+            }
+            process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+            assert!(
+                infos.escrow_state().is_initialized,
+                "with rent {}",
+                with_rent
+            );
+            assert_eq!(Infos::token_state(&infos.temp).owner, *infos.pda.key);
+
+            // either way an escrow short of rent is turned down
+            let mut fixture = Fixture::new(100, 1_000);
+            fixture.escrow.lamports -= 1;
+            let program_id = fixture.program_id;
+            let infos = fixture.infos();
+            let mut accounts = infos.init_accounts();
+            if !with_rent {
+                accounts.remove(4);
+            }
+            assert_eq!(
+                process(&program_id, &accounts, &instruction_data(0, 500)),
+                Err(ProgramError::AccountNotRentExempt)
+            );
+        }
+    }
+
+    #[test]
+    fn test_init_wrong_rent_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let mut accounts = infos.init_accounts();
+        accounts[4] = infos.clock.clone();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(0, 500)),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert!(LOGS.with(|logs| logs
+            .borrow()
+            .iter()
+            .any(|log| log.contains("neither the rent sysvar nor the token program"))));
+        assert!(infos.escrow.data.borrow().iter().all(|byte| *byte == 0));
+        assert_eq!(Infos::token_state(&infos.temp).owner, *infos.maker.key);
+    }
+
+    #[test]
+    fn test_init_underfunded_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let min_lamports = Rent::default().minimum_balance(Escrow::LEN);
+        fixture.escrow.lamports = min_lamports / 2;
+        let infos = fixture.infos();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500)
+            ),
+            Err(ProgramError::AccountNotRentExempt)
+        );
+        let expected = format!(
+            "The escrow account holds {} lamports, it needs at least {}",
+            min_lamports / 2,
+            min_lamports
+        );
+        assert!(LOGS.with(|logs| logs.borrow().contains(&expected)));
+        assert_eq!(Infos::token_state(&infos.temp).owner, *infos.maker.key);
+    }
+
+    #[test]
+    fn test_init_at_max_escrow_amount() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, MAX_ESCROW_AMOUNT),
+        )
+        .unwrap();
+        assert_eq!(infos.escrow_state().expected_amount, MAX_ESCROW_AMOUNT);
+    }
+
+    #[test]
+    fn test_exchange_closes_into_pda() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // an escrow whose maker is the PDA would close the temp account into it
+        let mut escrow = infos.escrow_state();
+        escrow.initializer_pubkey = *infos.pda.key;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        let mut accounts = infos.exchange_accounts();
+        accounts[4] = infos.pda.clone();
+
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+    }
+
+    #[test]
+    fn test_exchange_into_frozen_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let set_state = |info: &AccountInfo, state| {
+            let mut account = Infos::token_state(info);
+            account.state = state;
+            Account::pack(account, &mut info.data.borrow_mut()).unwrap();
+        };
+        for frozen in [&infos.maker_receive, &infos.taker_receive] {
+            set_state(frozen, AccountState::Frozen);
+            for tag in [6, 1] {
+                assert_eq!(
+                    process(
+                        &program_id,
+                        &infos.exchange_accounts(),
+                        &instruction_data(tag, 100)
+                    ),
+                    Err(EscrowError::AccountFrozen.into())
+                );
+            }
+            assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+            assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+            assert!(!infos.escrow_state().locked);
+            set_state(frozen, AccountState::Initialized);
+        }
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_failed_payment_leaves_temp_untouched() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // a frozen sent account passes the checks, the token program refuses it
+        let mut sent = Infos::token_state(&infos.taker_sent);
+        sent.state = AccountState::Frozen;
+        Account::pack(sent, &mut infos.taker_sent.data.borrow_mut()).unwrap();
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(spl_token::error::TokenError::AccountFrozen.into())
+        );
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 0);
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 0);
+    }
+
+    #[test]
+    fn test_accounts_of_another_instruction() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // cancel's accounts, padded to the exchange count
+        let accounts = [infos.cancel_accounts(), infos.mint_accounts()].concat();
+        let mut padded = accounts.clone();
+        padded.push(infos.rent.clone());
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        for tag in [1, 6] {
+            assert_eq!(
+                process(&program_id, &padded, &instruction_data(tag, 100)),
+                Err(EscrowError::InvalidAccountData.into())
+            );
+        }
+        assert!(LOGS.with(|logs| logs
+            .borrow()
+            .iter()
+            .any(|log| log.contains("isn't the Escrow"))));
+
+        // and exchange's for a cancel
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert!(!infos.escrow_state().locked);
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+    }
+
+    #[test]
+    fn test_stored_accounts_compared_to_their_roles() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut other_temp = TestAccount::token(&fixture.temp_mint.key, &fixture.pda.key, 100);
+        let mut other_receive =
+            TestAccount::token(&fixture.receive_mint.key, &fixture.maker.key, 0);
+        let (program_id, infos) = fixture.initialized();
+        let other_temp = other_temp.info();
+        let other_receive = other_receive.info();
+        let escrow = infos.escrow_state();
+        assert!(escrow.temp_token_account_pubkey.matches(infos.temp.key));
+        assert!(escrow
+            .initializer_token_to_receive_account_pubkey
+            .matches(infos.maker_receive.key));
+
+        // both lookalikes pass every check but the stored key
+        let mut accounts = infos.exchange_accounts();
+        accounts[3] = other_temp.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let mut accounts = infos.exchange_accounts();
+        accounts[5] = other_receive;
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let mut accounts = infos.cancel_accounts();
+        accounts[2] = other_temp;
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_relist_after_keeping_cancel() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let escrow_lamports = infos.escrow.lamports();
+        process(&program_id, &infos.cancel_accounts(), &[2, 0, 1]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        assert_eq!(infos.temp.lamports(), 0);
+        assert_eq!(infos.escrow.lamports(), escrow_lamports);
+        let escrow = infos.escrow_state();
+        assert!(escrow.is_initialized && escrow.is_empty());
+        // nothing is left to fill
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        process(
+            &program_id,
+            &infos.relist_accounts(),
+            &instruction_data(13, 300),
+        )
+        .unwrap();
+        let (pda, _bump_seed) = find_escrow_authority(&program_id);
+        assert_eq!(Infos::token_state(&infos.maker_refund).owner, pda);
+        let escrow = infos.escrow_state();
+        assert!(escrow
+            .temp_token_account_pubkey
+            .matches(infos.maker_refund.key));
+        assert_eq!(escrow.expected_amount, 300);
+        assert_eq!(escrow.created_at, FIXTURE_NOW);
+        assert_eq!(infos.escrow.lamports(), escrow_lamports);
+
+        // the relisted escrow trades like a fresh one
+        let mut accounts = infos.exchange_accounts();
+        accounts[3] = infos.maker_refund.clone();
+        process(&program_id, &accounts, &instruction_data(1, 100)).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 300);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_relist_active_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        Infos::set_token_amount(&infos.maker_refund, 100);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.relist_accounts(),
+                &instruction_data(13, 300)
+            ),
+            Err(EscrowError::EscrowActive.into())
+        );
+        assert!(infos
+            .escrow_state()
+            .temp_token_account_pubkey
+            .matches(infos.temp.key));
+        assert_eq!(
+            Infos::token_state(&infos.maker_refund).owner,
+            *infos.maker.key
+        );
+    }
+
+    #[test]
+    fn test_relist_checks_like_init() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut same_mint = TestAccount::token(&fixture.receive_mint.key, &fixture.maker.key, 100);
+        let (_, infos) = fixture.initialized();
+        let same_mint = same_mint.info();
+        process(&program_id, &infos.cancel_accounts(), &[2, 0, 1]).unwrap();
+
+        // the maker's token account to receive as the new temp account
+        let mut accounts = infos.relist_accounts();
+        accounts[1] = infos.maker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(13, 300)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        accounts[1] = same_mint.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(13, 300)),
+            Err(EscrowError::SameMint.into())
+        );
+
+        // the receive account the escrow doesn't keep
+        let mut accounts = infos.relist_accounts();
+        accounts[6] = infos.taker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(13, 300)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        // only a build lowering the cap has amounts past it
+        if let Some(over_max) = MAX_ESCROW_AMOUNT.checked_add(1) {
+            assert_eq!(
+                process(
+                    &program_id,
+                    &infos.relist_accounts(),
+                    &instruction_data(13, over_max)
+                ),
+                Err(EscrowError::AmountTooLarge.into())
+            );
+            Infos::set_token_amount(&infos.maker_refund, over_max);
+            assert_eq!(
+                process(
+                    &program_id,
+                    &infos.relist_accounts(),
+                    &instruction_data(13, 300)
+                ),
+                Err(EscrowError::AmountTooLarge.into())
+            );
+            Infos::set_token_amount(&infos.maker_refund, 0);
+        }
+
+        assert!(infos.escrow_state().is_empty());
+        assert_eq!(
+            Infos::token_state(&infos.maker_refund).owner,
+            *infos.maker.key
+        );
+        assert_eq!(Infos::token_state(&same_mint).owner, *infos.maker.key);
+
+        process(
+            &program_id,
+            &infos.relist_accounts(),
+            &instruction_data(13, MAX_ESCROW_AMOUNT),
+        )
+        .unwrap();
+        assert_eq!(infos.escrow_state().expected_amount, MAX_ESCROW_AMOUNT);
+    }
+
+    #[test]
+    fn test_log_state() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let mut escrow = infos.escrow_state();
+        escrow.min_price = 10;
+        escrow.max_price = 20;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        process(&program_id, std::slice::from_ref(&infos.escrow), &[14]).unwrap();
+        let expected = [
+            format!("initializer: {}", infos.maker.key),
+            format!("temp token account: {}", infos.temp.key),
+            format!("token to receive account: {}", infos.maker_receive.key),
+            "expected amount: 500".to_string(),
+            "frozen: false".to_string(),
+            format!("created at: {}", FIXTURE_NOW),
+            "min price: 10".to_string(),
+            "max price: 20".to_string(),
+        ];
+        LOGS.with(|logs| {
+            let logs = logs.borrow();
+            for line in &expected {
+                assert!(logs.contains(line), "missing {:?} in {:?}", line, logs);
+            }
+        });
+        // logging leaves the escrow as it was
+        assert_eq!(infos.escrow_state().expected_amount, 500);
+
+        assert_eq!(
+            process(&program_id, std::slice::from_ref(&infos.temp), &[14]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_init_with_delegated_temp() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let mut temp = Infos::token_state(&infos.temp);
+        temp.delegate = COption::Some(Pubkey::new_unique());
+        temp.delegated_amount = 40;
+        Account::pack(temp, &mut infos.temp.data.borrow_mut()).unwrap();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500)
+            ),
+            Err(EscrowError::TempAccountHasDelegate.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).owner, *infos.maker.key);
+
+        // a delegate with nothing left to spend is harmless
+        temp.delegated_amount = 0;
+        Account::pack(temp, &mut infos.temp.data.borrow_mut()).unwrap();
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, 500),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_init_same_mint() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let temp_mint = Infos::token_state(&infos.temp).mint;
+        Infos::set_token_mint(&infos.maker_receive, &temp_mint);
+
+        assert_eq!(
+            process(&program_id, &infos.init_accounts(), &init_data(500, 0)),
+            Err(EscrowError::SameMint.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).owner, *infos.maker.key);
+
+        let mut data = init_data(500, 0);
+        data.push(1);
+        process(&program_id, &infos.init_accounts(), &data).unwrap();
+        assert_eq!(
+            Escrow::unpack(&infos.escrow.data.borrow())
+                .unwrap()
+                .expected_amount,
+            500
+        );
+    }
+
+    fn memo_data(amount: u64, memo: &[u8]) -> Vec<u8> {
+        let mut data = instruction_data(1, amount);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.push(0);
+        data.extend_from_slice(memo);
+        data
+    }
+
+    #[test]
+    fn test_exchange_records_memo() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut memo_program = TestAccount {
+            key: MEMO_PROGRAM_ID,
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+
+        let data = memo_data(40, b"order 42");
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &data),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+        let accounts = [infos.exchange_accounts(), vec![memo_program.info()]].concat();
+        process(&program_id, &accounts, &data).unwrap();
+
+        let calls = HOOK_CALLS.with(|calls| calls.borrow().clone());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].program_id, MEMO_PROGRAM_ID);
+        assert_eq!(calls[0].data, b"order 42");
+        assert_eq!(
+            calls[0].accounts,
+            vec![AccountMeta::new_readonly(*infos.taker.key, true)]
+        );
+    }
+
+    #[test]
+    fn test_exchange_memo_too_long() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut memo_program = TestAccount {
+            key: MEMO_PROGRAM_ID,
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+
+        HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+        let accounts = [infos.exchange_accounts(), vec![memo_program.info()]].concat();
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                &memo_data(40, &[b'a'; MAX_MEMO_LEN + 1])
+            ),
+            Err(EscrowError::MemoTooLong.into())
+        );
+        assert_eq!(infos.escrow_state().expected_amount, 500);
+        assert!(HOOK_CALLS.with(|calls| calls.borrow().is_empty()));
+
+        process(
+            &program_id,
+            &accounts,
+            &memo_data(40, &[b'a'; MAX_MEMO_LEN]),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_exchange_calls_hook() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut hook = TestAccount {
+            key: RECORDING_HOOK,
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let set_hook = [&[15][..], RECORDING_HOOK.as_ref()].concat();
+        process(&program_id, &infos.hook_accounts(), &set_hook).unwrap();
+        assert_eq!(infos.escrow_state().hook_program, Some(RECORDING_HOOK));
+
+        HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 40)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let accounts = [infos.exchange_accounts(), vec![hook.info()]].concat();
+        process(&program_id, &accounts, &instruction_data(1, 40)).unwrap();
+
+        let calls = HOOK_CALLS.with(|calls| calls.borrow().clone());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].program_id, RECORDING_HOOK);
+        assert_eq!(
+            parse_settlement_hook(&calls[0].data),
+            Some(SettlementHook {
+                escrow: *infos.escrow.key,
+                taker: *infos.taker.key,
+                amount: 40,
+            })
+        );
+        assert!(calls[0]
+            .accounts
+            .iter()
+            .all(|meta| !meta.is_writable && !meta.is_signer));
+
+        // cleared, the rest fills without it
+        process(&program_id, &infos.hook_accounts(), &[15]).unwrap();
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 60),
+        )
+        .unwrap();
+        assert_eq!(HOOK_CALLS.with(|calls| calls.borrow().len()), 1);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+    }
+
+    #[test]
+    fn test_failing_hook_fails_exchange() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut hook = TestAccount {
+            key: FAILING_HOOK,
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let set_hook = [&[15][..], FAILING_HOOK.as_ref()].concat();
+        process(&program_id, &infos.hook_accounts(), &set_hook).unwrap();
+
+        let accounts = [infos.exchange_accounts(), vec![hook.info()]].concat();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(ProgramError::Custom(u32::MAX))
+        );
+    }
+
+    fn init_ratio_data(price_num: u64, price_den: u64) -> Vec<u8> {
+        let mut data = instruction_data(16, price_num);
+        data.extend_from_slice(&price_den.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_exchange_ratio_price() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_ratio_data(5, 3)).unwrap();
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.price(), Some((5, 3)));
+        // 100 * 5 / 3 rounded up
+        assert_eq!(escrow.expected_amount, 167);
+
+        // 10 * 5 / 3 = 16.67, the maker gets 17
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 10),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 17);
+        assert_eq!(infos.escrow_state().expected_amount, 150);
+
+        // a single token still pays 2, never less than the ratio
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 1),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 19);
+        assert_eq!(infos.escrow_state().expected_amount, 149);
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 89),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 168);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    /// A Pyth price account trading at `price * 10^expo`, last published at `slot`
+    fn pyth_feed(price: i64, expo: i32, slot: u64) -> Vec<u8> {
+        let mut data = vec![0; 240];
+        data[..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        data[4..8].copy_from_slice(&2u32.to_le_bytes());
+        data[8..12].copy_from_slice(&3u32.to_le_bytes());
+        data[20..24].copy_from_slice(&expo.to_le_bytes());
+        data[208..216].copy_from_slice(&price.to_le_bytes());
+        data[224..228].copy_from_slice(&1u32.to_le_bytes());
+        data[232..240].copy_from_slice(&slot.to_le_bytes());
+        data
+    }
+
+    fn init_oracle_data(price_feed: &Pubkey, min_price: u64, max_price: u64) -> Vec<u8> {
+        let mut data = vec![18];
+        data.extend_from_slice(price_feed.as_ref());
+        data.extend_from_slice(&min_price.to_le_bytes());
+        data.extend_from_slice(&max_price.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_exchange_oracle_price() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut feed = TestAccount::new(&Pubkey::new_unique(), pyth_feed(15, -1, 0));
+        let mut later_clock = TestAccount::sysvar(&Clock {
+            slot: MAX_ORACLE_AGE_SLOTS + 1,
+            unix_timestamp: FIXTURE_NOW,
+            ..Clock::default()
+        });
+        let infos = fixture.infos();
+        let feed = feed.info();
+        let data = init_oracle_data(feed.key, 10, 20);
+
+        assert_eq!(
+            process(&program_id, &infos.init_accounts(), &data),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let accounts = [infos.init_accounts(), vec![feed.clone()]].concat();
+        process(&program_id, &accounts, &data).unwrap();
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.price_feed, Some(*feed.key));
+        assert_eq!(escrow.price(), Some((15, 10)));
+        assert_eq!(escrow.expected_amount, 150);
+
+        let exchange = [infos.exchange_accounts(), vec![feed.clone()]].concat();
+        process(&program_id, &exchange, &instruction_data(1, 10)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 15);
+
+        // the next fill goes at the feed's new price
+        feed.data
+            .borrow_mut()
+            .copy_from_slice(&pyth_feed(20, -1, 0));
+        process(&program_id, &exchange, &instruction_data(1, 10)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 35);
+        assert_eq!(infos.escrow_state().expected_amount, 160);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 10)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        feed.data
+            .borrow_mut()
+            .copy_from_slice(&pyth_feed(25, -1, 0));
+        assert_eq!(
+            process(&program_id, &exchange, &instruction_data(1, 10)),
+            Err(EscrowError::OraclePriceOutOfBounds.into())
+        );
+        feed.data
+            .borrow_mut()
+            .copy_from_slice(&pyth_feed(20, -1, 0));
+        let stale = [exchange.clone(), vec![later_clock.info()]].concat();
+        assert_eq!(
+            process(&program_id, &stale, &instruction_data(1, 10)),
+            Err(EscrowError::StaleOracle.into())
+        );
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 35);
+    }
+
+    fn init_split_data(amount: u64, splits: &[(Pubkey, u16)]) -> Vec<u8> {
+        EscrowInstruction::InitEscrowSplit {
+            amount,
+            splits: splits
+                .iter()
+                .map(|&(account, bps)| MakerReceiveSplit { account, bps })
+                .collect(),
+        }
+        .pack()
+    }
+
+    #[test]
+    fn test_exchange_split_payment() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut partner = TestAccount::token(&fixture.receive_mint.key, &Pubkey::new_unique(), 0);
+        let infos = fixture.infos();
+        let partner = partner.info();
+
+        // the first share is the maker's token account to receive
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &init_split_data(
+                    500,
+                    &[(*partner.key, 3_000), (*infos.maker_receive.key, 7_000)]
+                )
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let data = init_split_data(
+            500,
+            &[(*infos.maker_receive.key, 7_000), (*partner.key, 3_000)],
+        );
+        process(&program_id, &infos.init_accounts(), &data).unwrap();
+        assert_eq!(infos.escrow_state().receive_splits().len(), 2);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 33)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let exchange = [infos.exchange_accounts(), vec![partner.clone()]].concat();
+
+        // 165 for the partial fill, the maker's share takes the rounding
+        process(&program_id, &exchange, &instruction_data(1, 33)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 116);
+        assert_eq!(Infos::token_state(&partner).amount, 49);
+
+        process(&program_id, &exchange, &instruction_data(1, 67)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 351);
+        assert_eq!(Infos::token_state(&partner).amount, 149);
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 500);
+    }
+
+    fn init_scaled_data(amount: u64, scale: u8) -> Vec<u8> {
+        EscrowInstruction::InitEscrowScaled { amount, scale }.pack()
+    }
+
+    #[test]
+    fn test_exchange_scaled_amount() {
+        let mut fixture = Fixture::new(100, 20_000_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        // 15 whole tokens of the 6 decimals the maker thinks in
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &init_scaled_data(15, 6),
+        )
+        .unwrap();
+        let escrow = infos.escrow_state();
+        assert_eq!((escrow.expected_amount, escrow.scale), (15, 6));
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 6_000_000);
+        // the rest is kept in base units from then on
+        let escrow = infos.escrow_state();
+        assert_eq!((escrow.expected_amount, escrow.scale), (9_000_000, 0));
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 60),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 15_000_000);
+    }
+
+    #[test]
+    fn test_init_scaled_overflow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        for (amount, scale) in [(19, 18), (1, 20), (1, u8::MAX)] {
+            assert_eq!(
+                process(
+                    &program_id,
+                    &infos.init_accounts(),
+                    &init_scaled_data(amount, scale)
+                ),
+                Err(EscrowError::AmountOverflow.into())
+            );
+        }
+        assert!(
+            !Escrow::try_from_account_data(&infos.escrow.data.borrow())
+                .unwrap()
+                .is_initialized
+        );
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &init_scaled_data(18, 18),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_init_ratio_overflow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &init_ratio_data(u64::MAX, 1)
+            ),
+            Err(EscrowError::AmountOverflow.into())
+        );
+        assert!(
+            !Escrow::try_from_account_data(&infos.escrow.data.borrow())
+                .unwrap()
+                .is_initialized
+        );
+    }
+
+    #[test]
+    fn test_exchange_max_escrow_age() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let exchange = |now| {
+            NOW.with(|clock| *clock.borrow_mut() = Some(now));
+            let result = process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 10),
+            );
+            NOW.with(|clock| *clock.borrow_mut() = Some(FIXTURE_NOW));
+            result
+        };
+
+        assert_eq!(exchange(FIXTURE_NOW + MAX_ESCROW_AGE_SECONDS - 1), Ok(()));
+        assert_eq!(
+            exchange(FIXTURE_NOW + MAX_ESCROW_AGE_SECONDS),
+            Err(EscrowError::EscrowExpired.into())
+        );
+
+        // a deadline of the maker's own replaces the age limit
+        let mut escrow = infos.escrow_state();
+        escrow.expires_at = FIXTURE_NOW + 2 * MAX_ESCROW_AGE_SECONDS;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        assert_eq!(exchange(FIXTURE_NOW + MAX_ESCROW_AGE_SECONDS), Ok(()));
+        assert_eq!(
+            exchange(FIXTURE_NOW + 2 * MAX_ESCROW_AGE_SECONDS),
+            Err(EscrowError::EscrowExpired.into())
+        );
+
+        // as does not knowing the escrow's age
+        let mut escrow = infos.escrow_state();
+        escrow.expires_at = 0;
+        escrow.created_at = 0;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        assert_eq!(exchange(FIXTURE_NOW + MAX_ESCROW_AGE_SECONDS), Ok(()));
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 30);
+    }
+
+    #[test]
+    fn test_exchange_past_deadline() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let mut escrow = infos.escrow_state();
+        escrow.expires_at = FIXTURE_NOW + 60;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        let at = |now, tag| {
+            NOW.with(|clock| *clock.borrow_mut() = Some(now));
+            let result = process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(tag, 10),
+            );
+            NOW.with(|clock| *clock.borrow_mut() = Some(FIXTURE_NOW));
+            result
+        };
+
+        // a simulation turns the taker away as the exchange would
+        for tag in [6, 1] {
+            assert_eq!(
+                at(FIXTURE_NOW + 60, tag),
+                Err(EscrowError::EscrowExpired.into())
+            );
+        }
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 0);
+
+        // an escrow that never saw its creation still stops at its deadline
+        let mut escrow = infos.escrow_state();
+        escrow.created_at = 0;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        assert_eq!(
+            at(FIXTURE_NOW + 60, 1),
+            Err(EscrowError::EscrowExpired.into())
+        );
+        assert_eq!(at(FIXTURE_NOW + 59, 1), Ok(()));
+    }
+
+    #[test]
+    fn test_exchange_clock_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut old_clock = TestAccount::sysvar(&Clock {
+            unix_timestamp: FIXTURE_NOW + MAX_ESCROW_AGE_SECONDS,
+            ..Clock::default()
+        });
+        let (program_id, infos) = fixture.initialized();
+
+        // a clock passed along wins over the runtime's
+        let accounts = [infos.exchange_accounts(), vec![old_clock.info()]].concat();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 10)),
+            Err(EscrowError::EscrowExpired.into())
+        );
+        let accounts = [infos.exchange_accounts(), vec![infos.clock.clone()]].concat();
+        process(&program_id, &accounts, &instruction_data(1, 10)).unwrap();
+
+        // without either, only an escrow that can't age still fills
+        NOW.with(|now| *now.borrow_mut() = None);
+        let without_clock = process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 10),
+        );
+        let mut escrow = infos.escrow_state();
+        escrow.created_at = 0;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        let untimed = process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 10),
+        );
+        NOW.with(|now| *now.borrow_mut() = Some(FIXTURE_NOW));
+
+        assert_eq!(without_clock, Err(ProgramError::NotEnoughAccountKeys));
+        assert_eq!(untimed, Ok(()));
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 20);
+    }
+
+    #[test]
+    fn test_cancel_all() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut temp2 = TestAccount::token(&fixture.temp_mint.key, &fixture.maker.key, 50);
+        let mut escrow2 = TestAccount::new(&program_id, vec![0; Escrow::LEN]);
+        let (_, infos) = fixture.initialized();
+        let temp2 = temp2.info();
+        let escrow2 = escrow2.info();
+        let mut accounts = infos.init_accounts();
+        accounts[1] = temp2.clone();
+        accounts[3] = escrow2.clone();
+        process(&program_id, &accounts, &instruction_data(0, 250)).unwrap();
+
+        let reclaimed =
+            infos.escrow.lamports() + infos.temp.lamports() + escrow2.lamports() + temp2.lamports();
+        let maker_lamports = infos.maker.lamports();
+        let accounts = [
+            infos.cancel_all_accounts(),
+            vec![escrow2.clone(), temp2.clone(), infos.maker_refund.clone()],
+        ]
+        .concat();
+        process(&program_id, &accounts, &[17]).unwrap();
+
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 150);
+        assert_eq!(infos.maker.lamports(), maker_lamports + reclaimed);
+        for closed in [&infos.escrow, &infos.temp, &escrow2, &temp2] {
+            assert_eq!(closed.lamports(), 0);
+        }
+
+        // every escrow has to be the maker's
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let mut accounts = infos.cancel_all_accounts();
+        accounts[0] = infos.taker.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[17]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_credit_lamports() {
+        let mut from = TestAccount {
+            lamports: 1_000,
+            ..TestAccount::wallet()
+        };
+        let mut to = TestAccount {
+            lamports: 500,
+            ..TestAccount::wallet()
+        };
+        let (from, to) = (from.info(), to.info());
+        credit_lamports(&from, &to).unwrap();
+        assert_eq!((from.lamports(), to.lamports()), (0, 1_500));
+
+        // nothing moves when the sum doesn't fit
+        **from.lamports.borrow_mut() = 2;
+        **to.lamports.borrow_mut() = u64::MAX - 1;
+        assert_eq!(
+            credit_lamports(&from, &to),
+            Err(EscrowError::AmountOverflow.into())
+        );
+        assert_eq!((from.lamports(), to.lamports()), (2, u64::MAX - 1));
+
+        assert_eq!(
+            move_lamports(&from, &to, 3),
+            Err(EscrowError::AmountOverflow.into())
+        );
+    }
+
+    #[test]
+    fn test_uninitialized_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        // even with the other fields in place
+        let escrow = Escrow {
+            initializer_pubkey: *infos.maker.key,
+            temp_token_account_pubkey: TempTokenAccount(*infos.temp.key),
+            initializer_token_to_receive_account_pubkey: MakerReceiveAccount(
+                *infos.maker_receive.key,
+            ),
+            expected_amount: 500,
+            ..Escrow::unpack_unchecked(&[0; Escrow::LEN]).unwrap()
+        };
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(
+            process(&program_id, &infos.cancel_accounts(), &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 0);
+    }
+
+    /// Run with `cargo test --features debug-compute`
+    #[cfg(feature = "debug-compute")]
+    #[test]
+    fn test_exchange_logs_compute_units() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        LOGS.with(|logs| {
+            let logs = logs.borrow();
+            for step in [
+                "the maker's payment",
+                "the taker's fill",
+                "closing the escrow",
+            ] {
+                let marker = format!("Compute units before {}:", step);
+                let at = logs.iter().position(|line| *line == marker);
+                let at = at.unwrap_or_else(|| panic!("missing {:?} in {:?}", marker, logs));
+                assert!(logs[at + 1].contains("compute_units"));
+            }
+        });
+    }
+
+    #[test]
+    fn test_init_receive_is_temp() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let mut accounts = infos.init_accounts();
+        accounts[2] = infos.temp.clone();
+
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(0, 500)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert!(
+            !Escrow::unpack_unchecked(&infos.escrow.data.borrow())
+                .unwrap()
+                .is_initialized
+        );
+        assert_eq!(Infos::token_state(&infos.temp).owner, *infos.maker.key);
+    }
+
+    #[test]
+    fn test_admin_pause() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.maker.lamports = 1_000_000_000;
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let admin_accounts = vec![infos.maker.clone(), infos.config.clone(), system.info()];
+
+        // the first call creates the config, its signer is the admin from then on
+        process(&program_id, &admin_accounts, &[19, 1]).unwrap();
+        assert_eq!(infos.config.owner, &program_id);
+        let config = Config::unpack(&infos.config.data.borrow()).unwrap();
+        assert_eq!(config.admin, *infos.maker.key);
+        assert!(config.paused);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 40)
+            ),
+            Err(EscrowError::ProgramPaused.into())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(6, 40)
+            ),
+            Err(EscrowError::ProgramPaused.into())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.batch_accounts(),
+                &[&[5, 1][..], &40u64.to_le_bytes()].concat()
+            ),
+            Err(EscrowError::ProgramPaused.into())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500)
+            ),
+            Err(EscrowError::ProgramPaused.into())
+        );
+        // leaving the config out, or passing another account in its place, doesn't
+        // get around the pause
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts()[..EXCHANGE_ACCOUNTS - 1],
+                &instruction_data(1, 40)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let mut accounts = infos.exchange_accounts();
+        accounts[EXCHANGE_ACCOUNTS - 1] = infos.clock.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 40)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        process(&program_id, &admin_accounts[..ADMIN_ACCOUNTS], &[19, 0]).unwrap();
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 40);
+
+        // makers can still get out while paused, but not list again
+        process(&program_id, &admin_accounts[..ADMIN_ACCOUNTS], &[19, 1]).unwrap();
+        process(&program_id, &infos.cancel_accounts(), &[2, 0, 1]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 60);
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.relist_accounts(),
+                &instruction_data(13, 500)
+            ),
+            Err(EscrowError::ProgramPaused.into())
+        );
+        assert!(infos.escrow_state().is_empty());
+        assert_eq!(
+            Infos::token_state(&infos.maker_refund).owner,
+            *infos.maker.key
+        );
+    }
+
+    #[test]
+    fn test_admin_by_non_admin() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        fixture.maker.lamports = 1_000_000_000;
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let infos = fixture.infos();
+        process(
+            &program_id,
+            &[infos.maker.clone(), infos.config.clone(), system.info()],
+            &[19, 1],
+        )
+        .unwrap();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &[infos.taker.clone(), infos.config.clone()],
+                &[19, 0]
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert!(Config::unpack(&infos.config.data.borrow()).unwrap().paused);
+
+        // only the config's address is taken
+        assert_eq!(
+            process(
+                &program_id,
+                &[infos.maker.clone(), infos.escrow.clone()],
+                &[19, 0]
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    /// Stands in for a quote program run before the exchange, leaving `quote` as
+    /// its return data
+    fn set_quote(quote_program: &Pubkey, quote: &[u8]) {
+        CALLER.with(|caller| *caller.borrow_mut() = *quote_program);
+        solana_program::program::set_return_data(quote);
+    }
+
+    #[test]
+    fn test_exchange_quoted_amount() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let quote_program = Pubkey::new_unique();
+        let (program_id, infos) = fixture.initialized();
+
+        // the amount in the data is ignored, the quote's taken
+        let mut data = instruction_data(1, 100);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.push(EXCHANGE_QUOTED_AMOUNT);
+        data.extend_from_slice(quote_program.as_ref());
+
+        set_quote(&Pubkey::new_unique(), &40u64.to_le_bytes());
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &data),
+            Err(EscrowError::InvalidQuote.into())
+        );
+        set_quote(&quote_program, &40u32.to_le_bytes());
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &data),
+            Err(EscrowError::InvalidQuote.into())
+        );
+
+        set_quote(&quote_program, &40u64.to_le_bytes());
+        process(&program_id, &infos.exchange_accounts(), &data).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 40);
+        assert_eq!(Infos::token_state(&infos.temp).amount, 60);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 200);
+    }
+
+    #[test]
+    fn test_exchange_amount_overflow() {
+        let mut fixture = Fixture::new(100, u64::MAX);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, u64::MAX - 1),
+        )
+        .unwrap();
+
+        Infos::set_token_amount(&infos.maker_receive, 2);
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::AmountOverflow.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_cancel_by_non_maker() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[0] = infos.taker.clone();
+        accounts[1] = infos.taker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 0);
+    }
+
+    #[test]
+    fn test_cancel_refund_account_not_owned() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[1] = infos.taker_receive.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::RefundAccountNotOwned.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_cancel_with_substituted_temp() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut other_temp = TestAccount::token(&fixture.temp_mint.key, &fixture.pda.key, 50);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[2] = other_temp.info();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 0);
+    }
+
+    #[test]
+    fn test_find_escrow_authority() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let (pda, _bump_seed) = find_escrow_authority(&program_id);
+        assert_eq!(Infos::token_state(&infos.temp).owner, pda);
+    }
+
+    #[test]
+    fn test_exchange_min_receive() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut data = instruction_data(1, 100);
+        data.extend_from_slice(&101u64.to_le_bytes());
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &data),
+            Err(EscrowError::SlippageExceeded.into())
+        );
+
+        let mut data = instruction_data(1, 100);
+        data.extend_from_slice(&100u64.to_le_bytes());
+        process(&program_id, &infos.exchange_accounts(), &data).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+    }
+
+    #[test]
+    fn test_exchange_checks_escrow_before_token_accounts() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut garbage = TestAccount::new(&spl_token::id(), vec![]);
+        let mut other_garbage = TestAccount::new(&spl_token::id(), vec![]);
+        let (program_id, infos) = fixture.initialized();
+        let accounts = vec![infos.maker.clone(), infos.escrow.clone()];
+        process(&program_id, &accounts, &[3, 1]).unwrap();
+
+        // the frozen escrow rejects the trade before the taker's accounts are unpacked
+        let mut accounts = infos.exchange_accounts();
+        accounts[1] = garbage.info();
+        accounts[2] = other_garbage.info();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::EscrowFrozen.into())
+        );
+    }
+
+    fn init_multi_data(amount: u64, mints: &[Pubkey]) -> Vec<u8> {
+        let mut data = instruction_data(4, amount);
+        data.push(mints.len() as u8);
+        for mint in mints {
+            data.extend_from_slice(mint.as_ref());
+        }
+        data
+    }
+
+    #[test]
+    fn test_exchange_multi_mint() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let other_mint = Pubkey::new_unique();
+        let third_mint = Pubkey::new_unique();
+        let mut maker_other = TestAccount::token(&other_mint, &fixture.maker.key, 0);
+        let mut taker_other = TestAccount::token(&other_mint, &fixture.taker.key, 1_000);
+        let mut maker_third = TestAccount::token(&third_mint, &fixture.maker.key, 0);
+        let mut taker_third = TestAccount::token(&third_mint, &fixture.taker.key, 1_000);
+        let infos = fixture.infos();
+        let data = init_multi_data(500, &[*infos.receive_mint.key, other_mint]);
+        process(&program_id, &infos.init_accounts(), &data).unwrap();
+        assert_eq!(
+            infos.escrow_state().accepted_mints(),
+            &[*infos.receive_mint.key, other_mint]
+        );
+
+        // a mint outside the accepted ones is rejected
+        let mut accounts = infos.exchange_accounts();
+        accounts[1] = taker_third.info();
+        accounts[5] = maker_third.info();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::MakerMintMismatch.into())
+        );
+
+        // the second accepted mint pays into the maker's account of that mint
+        let mut accounts = infos.exchange_accounts();
+        accounts[1] = taker_other.info();
+        accounts[5] = maker_other.info();
+        process(&program_id, &accounts, &instruction_data(1, 100)).unwrap();
+        assert_eq!(Infos::token_state(&accounts[5]).amount, 500);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+    }
+
+    #[test]
+    fn test_init_multi_requires_receive_mint() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        let data = init_multi_data(500, &[Pubkey::new_unique()]);
+        assert_eq!(
+            process(&program_id, &infos.init_accounts(), &data),
+            Err(EscrowError::ExpectedMintMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_batch_exchange() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut temp2 = TestAccount::token(&fixture.temp_mint.key, &fixture.maker.key, 50);
+        let mut escrow2 = TestAccount::new(&program_id, vec![0; Escrow::LEN]);
+        let (_, infos) = fixture.initialized();
+        let temp2 = temp2.info();
+        let escrow2 = escrow2.info();
+
+        let mut accounts = infos.init_accounts();
+        accounts[1] = temp2.clone();
+        accounts[3] = escrow2.clone();
+        process(&program_id, &accounts, &instruction_data(0, 300)).unwrap();
+
+        let accounts = vec![
+            infos.taker.clone(),
+            infos.taker_sent.clone(),
+            infos.taker_receive.clone(),
+            infos.token_program.clone(),
+            infos.pda.clone(),
+            infos.config.clone(),
+            infos.escrow.clone(),
+            infos.temp.clone(),
+            infos.maker.clone(),
+            infos.maker_receive.clone(),
+            escrow2.clone(),
+            temp2.clone(),
+            infos.maker.clone(),
+            infos.maker_receive.clone(),
+        ];
+        let mut data = vec![5, 2];
+        data.extend_from_slice(&100u64.to_le_bytes());
+        data.extend_from_slice(&50u64.to_le_bytes());
+
+        // one account short of the second fill
+        assert_eq!(
+            process(&program_id, &accounts[..13], &data),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        process(&program_id, &accounts, &data).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 150);
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 200);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 800);
+        assert_eq!(infos.escrow.data_len(), 0);
+        assert_eq!(escrow2.data_len(), 0);
+    }
+
+    /// Simulates then runs the same trade, both have to end the same way
+    fn simulate_and_exchange<F>(setup: F) -> ProgramResult
+    where
+        F: for<'a> Fn(&Infos<'a>) -> (Vec<AccountInfo<'a>>, u64),
+    {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let (accounts, amount) = setup(&infos);
+
+        let simulated = process(&program_id, &accounts, &instruction_data(6, amount));
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 0);
+        assert!(infos.escrow_state().is_initialized);
+
+        let exchanged = process(&program_id, &accounts, &instruction_data(1, amount));
+        assert_eq!(simulated, exchanged);
+        exchanged
+    }
+
+    #[test]
+    fn test_simulate_exchange_agrees() {
+        assert_eq!(
+            simulate_and_exchange(|infos| (infos.exchange_accounts(), 100)),
+            Ok(())
+        );
+        assert_eq!(
+            simulate_and_exchange(|infos| (infos.exchange_accounts(), 0)),
+            Err(EscrowError::ExpectedAmountMismatch.into())
+        );
+        assert_eq!(
+            simulate_and_exchange(|infos| (infos.exchange_accounts(), 99)),
+            Ok(())
+        );
+        assert_eq!(
+            simulate_and_exchange(|infos| {
+                Infos::set_token_amount(&infos.taker_sent, 499);
+                (infos.exchange_accounts(), 100)
+            }),
+            Err(EscrowError::NotEnoughBalanceToSent.into())
+        );
+        assert_eq!(
+            simulate_and_exchange(|infos| {
+                Infos::set_token_mint(&infos.taker_receive, infos.receive_mint.key);
+                (infos.exchange_accounts(), 100)
+            }),
+            Err(EscrowError::TakerMintMismatch.into())
+        );
+        assert_eq!(
+            simulate_and_exchange(|infos| {
+                let mut escrow = infos.escrow_state();
+                escrow.frozen = true;
+                Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+                (infos.exchange_accounts(), 100)
+            }),
+            Err(EscrowError::EscrowFrozen.into())
+        );
+    }
+
+    #[test]
+    fn test_exchange_drained_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let mut closed = TestAccount {
+            key: *infos.temp.key,
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![])
+        };
+        let closed = closed.info();
+
+        Infos::set_token_amount(&infos.temp, 0);
+        let mut accounts = infos.exchange_accounts();
+        for temp in [infos.temp.clone(), closed] {
+            accounts[3] = temp;
+            for tag in [6, 1] {
+                assert_eq!(
+                    process(&program_id, &accounts, &instruction_data(tag, 100)),
+                    Err(EscrowError::StaleAmount.into())
+                );
+            }
+        }
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 1_000);
+        assert!(infos.escrow_state().is_initialized);
+    }
+
+    #[test]
+    fn test_escrow_not_owned_by_program() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // a look-alike escrow with the same bytes under the system program
+        let mut forged =
+            TestAccount::new(&system_program::id(), infos.escrow.data.borrow().to_vec());
+        let forged = forged.info();
+
+        // turned down by the account roles, ahead of the handlers
+        let mut accounts = infos.exchange_accounts();
+        accounts[6] = forged.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[3] = forged;
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    fn init_data(amount: u64, min_fill: u64) -> Vec<u8> {
+        let mut data = instruction_data(0, amount);
+        data.extend_from_slice(&min_fill.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_exchange_below_min_fill() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 50)).unwrap();
+        assert_eq!(infos.escrow_state().min_fill, 50);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 49)
+            ),
+            Err(EscrowError::BelowMinimumFill.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_exchange_exact_min_fill() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 100)).unwrap();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+    }
+
+    #[test]
+    fn test_exchange_closing_fill_below_min_fill() {
+        let mut fixture = Fixture::new(30, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 50)).unwrap();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 30),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 30);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_permille() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 10)).unwrap();
+        let permille_data = |permille: u16| [&[24][..], &permille.to_le_bytes()].concat();
+
+        // half of the 100 left, at the escrow's price
+        process(&program_id, &infos.exchange_accounts(), &permille_data(500)).unwrap();
+        assert_eq!(Infos::token_state(&infos.temp).amount, 50);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 50);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 250);
+
+        // a third of 50 rounds down to 16, the maker's share still rounds up
+        process(&program_id, &infos.exchange_accounts(), &permille_data(333)).unwrap();
+        assert_eq!(Infos::token_state(&infos.temp).amount, 34);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 66);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 330);
+
+        // 3 is under the minimum fill, 0 is nothing to fill
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &permille_data(100)),
+            Err(EscrowError::BelowMinimumFill.into())
+        );
+        assert_eq!(
+            process(&program_id, &infos.exchange_accounts(), &permille_data(1)),
+            Err(EscrowError::ExpectedAmountMismatch.into())
+        );
+
+        // the whole of what's left closes the escrow
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &permille_data(1_000),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_cancel_unwraps_sol() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.temp = TestAccount::native_token(&fixture.maker.key, 100);
+        fixture.maker_refund = TestAccount::native_token(&fixture.maker.key, 0);
+        let (program_id, infos) = fixture.initialized();
+
+        let maker_lamports = infos.maker.lamports();
+        let closed_lamports =
+            infos.temp.lamports() + infos.maker_refund.lamports() + infos.escrow.lamports();
+        process(&program_id, &infos.cancel_accounts(), &[2, 1]).unwrap();
+        assert_eq!(infos.maker_refund.lamports(), 0);
+        assert_eq!(infos.maker.lamports(), maker_lamports + closed_lamports);
+    }
+
+    #[test]
+    fn test_cancel_unwrap_keeps_funded_refund_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.temp = TestAccount::native_token(&fixture.maker.key, 100);
+        fixture.maker_refund = TestAccount::native_token(&fixture.maker.key, 40);
+        let (program_id, infos) = fixture.initialized();
+
+        let maker_lamports = infos.maker.lamports();
+        let refund_lamports = infos.maker_refund.lamports();
+        let temp_lamports = infos.temp.lamports();
+        let escrow_lamports = infos.escrow.lamports();
+        process(&program_id, &infos.cancel_accounts(), &[2, 1]).unwrap();
+        // the 100 refunded stay wrapped next to the 40 already there
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 140);
+        assert_eq!(infos.maker_refund.lamports(), refund_lamports + 100);
+        assert_eq!(
+            infos.maker.lamports(),
+            maker_lamports + temp_lamports - 100 + escrow_lamports
+        );
+        assert_eq!(infos.temp.lamports(), 0);
+        assert_eq!(infos.escrow.lamports(), 0);
+    }
+
+    #[test]
+    fn test_cancel_unwrap_ignored_for_other_mints() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        process(&program_id, &infos.cancel_accounts(), &[2, 1]).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+    }
+
+    #[test]
+    fn test_exchange_locked_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // what a re-entrant exchange would find mid-way through the first one
+        let mut escrow = infos.escrow_state();
+        escrow.locked = true;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::EscrowBusy.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_process_logs_handler_error() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let accounts = vec![infos.taker.clone(), infos.escrow.clone()];
+        assert_eq!(
+            process(&program_id, &accounts, &[3, 1]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let expected = format!(
+            "Escrow error: {:?}",
+            ProgramError::from(EscrowError::InvalidAccountData)
+        );
+        assert!(LOGS.with(|logs| logs.borrow().contains(&expected)));
+    }
+
+    #[test]
+    fn test_exchange_more_than_temp_balance() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 150),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_equal_to_temp_balance() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_taker_sends_whole_balance() {
+        let mut fixture = Fixture::new(100, 500);
+        let (program_id, infos) = fixture.initialized();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 0);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_less_than_temp_balance() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 40);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 200);
+        assert_eq!(Infos::token_state(&infos.temp).amount, 60);
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.expected_amount, 300);
+        assert!(!escrow.locked);
+
+        // the rest is still on offer at the same price
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 60),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_maker_share_rounds_up() {
+        assert_eq!(maker_share(500, 100, 100), 500);
+        assert_eq!(maker_share(500, 1, 3), 167);
+        assert_eq!(maker_share(u64::MAX, u64::MAX - 1, u64::MAX), u64::MAX - 1);
+    }
+
+    fn multisig(signers: &[Pubkey], m: u8) -> TestAccount {
+        let mut multisig = Multisig {
+            m,
+            n: signers.len() as u8,
+            is_initialized: true,
+            ..Multisig::default()
+        };
+        multisig.signers[..signers.len()].copy_from_slice(signers);
+        let mut data = vec![0; Multisig::LEN];
+        Multisig::pack(multisig, &mut data).unwrap();
+        TestAccount::new(&spl_token::id(), data)
+    }
+
+    #[test]
+    fn test_init_multisig_maker() {
+        let mut signers = [
+            TestAccount::wallet(),
+            TestAccount::wallet(),
+            TestAccount::wallet(),
+        ];
+        let mut multisig = multisig(&[signers[0].key, signers[1].key, signers[2].key], 2);
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.temp = TestAccount::token(&fixture.temp_mint.key, &multisig.key, 100);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let multisig = multisig.info();
+        let [signer_a, signer_b, signer_c] = &mut signers;
+        let (signer_a, signer_b) = (signer_a.info(), signer_b.info());
+        let mut signer_c = signer_c.info();
+        signer_c.is_signer = false;
+
+        // the multisig is required, and every signer after it has to sign
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.init_accounts(),
+                &instruction_data(0, 500)
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let accounts = [
+            infos.init_accounts(),
+            vec![multisig.clone(), signer_a.clone(), signer_c],
+        ]
+        .concat();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(0, 500)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        let accounts = [infos.init_accounts(), vec![multisig, signer_a, signer_b]].concat();
+        process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+        let (pda, _bump_seed) = find_escrow_authority(&program_id);
+        assert_eq!(Infos::token_state(&infos.temp).owner, pda);
+    }
+
+    #[test]
+    fn test_exchange_receive_account_not_owned() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut accounts = infos.exchange_accounts();
+        accounts[2] = infos.maker_refund.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::ReceiveAccountNotOwned.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_temp_authority_changed() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let mut temp = Infos::token_state(&infos.temp);
+        temp.close_authority = COption::Some(Pubkey::new_unique());
+        Account::pack(temp, &mut infos.temp.data.borrow_mut()).unwrap();
+        assert_eq!(
+            process(&program_id, &infos.cancel_accounts(), &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        temp.close_authority = COption::None;
+        temp.owner = Pubkey::new_unique();
+        Account::pack(temp, &mut infos.temp.data.borrow_mut()).unwrap();
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(1, 100)
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_process_missing_signer() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        fixture.maker.is_signer = false;
+        fixture.taker.is_signer = false;
+        let infos = fixture.infos();
+
+        let cases: Vec<(Vec<AccountInfo>, Vec<u8>)> = vec![
+            (infos.init_accounts(), instruction_data(0, 500)),
+            (infos.exchange_accounts(), instruction_data(1, 100)),
+            // cancel only needs the signature before expiry, see the expiry tests
+            (vec![infos.maker.clone(), infos.escrow.clone()], vec![3, 1]),
+            (
+                infos.init_accounts(),
+                init_multi_data(500, &[*infos.receive_mint.key]),
+            ),
+            (infos.batch_accounts(), vec![5, 1, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (infos.exchange_accounts(), instruction_data(6, 100)),
+            (
+                vec![infos.maker.clone(), infos.escrow.clone()],
+                instruction_data(7, 1_000),
+            ),
+            (
+                vec![
+                    infos.maker.clone(),
+                    infos.maker_refund.clone(),
+                    infos.temp.clone(),
+                    infos.escrow.clone(),
+                    infos.token_program.clone(),
+                    infos.rent.clone(),
+                ],
+                instruction_data(8, 50),
+            ),
+            (infos.cancel_accounts(), instruction_data(9, 50)),
+            (infos.settings_accounts(), vec![10]),
+            (infos.settings_accounts()[..3].to_vec(), vec![11, 250, 0]),
+            (infos.settings_accounts(), [&[12][..], &[1; 32]].concat()),
+            (infos.relist_accounts(), instruction_data(13, 500)),
+            (infos.hook_accounts(), vec![15]),
+            (infos.init_accounts(), init_ratio_data(5, 3)),
+            (infos.cancel_all_accounts(), vec![17]),
+            (
+                infos.init_accounts(),
+                init_oracle_data(&Pubkey::new_unique(), 10, 20),
+            ),
+            (vec![infos.maker.clone(), infos.config.clone()], vec![19, 1]),
+            (
+                infos.init_accounts(),
+                init_split_data(500, &[(*infos.maker_receive.key, MAX_BPS)]),
+            ),
+            (infos.init_accounts(), init_scaled_data(5, 2)),
+            (infos.hook_accounts(), vec![22, 1]),
+            (infos.finalize_accounts(), vec![23]),
+            (infos.exchange_accounts(), vec![24, 244, 1]),
+        ];
+        for (accounts, data) in cases {
+            assert_eq!(
+                process(&program_id, &accounts, &data),
+                Err(ProgramError::MissingRequiredSignature),
+                "instruction {}",
+                data[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_read_only_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let cases: Vec<(Vec<AccountInfo>, Vec<u8>)> = vec![
+            (infos.init_accounts(), instruction_data(0, 500)),
+            (infos.exchange_accounts(), instruction_data(1, 100)),
+            (infos.cancel_accounts(), vec![2]),
+            (vec![infos.maker.clone(), infos.escrow.clone()], vec![3, 1]),
+            (
+                infos.init_accounts(),
+                init_multi_data(500, &[*infos.receive_mint.key]),
+            ),
+            (infos.batch_accounts(), vec![5, 1, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (
+                vec![infos.maker.clone(), infos.escrow.clone()],
+                instruction_data(7, 1_000),
+            ),
+            (
+                vec![
+                    infos.maker.clone(),
+                    infos.maker_refund.clone(),
+                    infos.temp.clone(),
+                    infos.escrow.clone(),
+                    infos.token_program.clone(),
+                    infos.rent.clone(),
+                ],
+                instruction_data(8, 50),
+            ),
+            (infos.cancel_accounts(), instruction_data(9, 50)),
+            (infos.settings_accounts(), vec![10]),
+            (infos.settings_accounts()[..3].to_vec(), vec![11, 250, 0]),
+            (infos.settings_accounts(), [&[12][..], &[1; 32]].concat()),
+            (infos.relist_accounts(), instruction_data(13, 500)),
+            (infos.hook_accounts(), vec![15]),
+            (infos.init_accounts(), init_ratio_data(5, 3)),
+            (
+                infos.init_accounts(),
+                init_oracle_data(&Pubkey::new_unique(), 10, 20),
+            ),
+            (vec![infos.maker.clone(), infos.config.clone()], vec![19, 1]),
+            (
+                infos.init_accounts(),
+                init_split_data(500, &[(*infos.maker_receive.key, MAX_BPS)]),
+            ),
+            (infos.init_accounts(), init_scaled_data(5, 2)),
+            (infos.hook_accounts(), vec![22, 1]),
+            (infos.finalize_accounts(), vec![23]),
+            (infos.exchange_accounts(), vec![24, 244, 1]),
+        ];
+        for (accounts, data) in cases {
+            let positions = writable_accounts(&EscrowInstruction::unpack(&data).unwrap());
+            assert!(!positions.is_empty(), "instruction {}", data[0]);
+            for position in positions {
+                let mut accounts = accounts.clone();
+                accounts[*position].is_writable = false;
+                assert_eq!(
+                    process(&program_id, &accounts, &data),
+                    Err(ProgramError::InvalidArgument),
+                    "instruction {} account {}",
+                    data[0],
+                    position
+                );
+            }
+        }
+        // nothing was written on the way
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(infos.escrow_state().expected_amount, 500);
+    }
+
+    #[test]
+    fn test_process_too_many_accounts() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let top_up = vec![
+            infos.maker.clone(),
+            infos.maker_refund.clone(),
+            infos.temp.clone(),
+            infos.escrow.clone(),
+            infos.token_program.clone(),
+            infos.rent.clone(),
+        ];
+        // The instructions taking exactly their count, with the accounts they take
+        let cases: Vec<(usize, Vec<AccountInfo>, Vec<u8>)> = vec![
+            (SET_FROZEN_ACCOUNTS, infos.hook_accounts(), vec![3, 1]),
+            (
+                SET_EXPIRY_ACCOUNTS,
+                infos.hook_accounts(),
+                instruction_data(7, 1_000),
+            ),
+            (TOP_UP_ACCOUNTS, top_up, instruction_data(8, 50)),
+            (
+                WITHDRAW_PARTIAL_ACCOUNTS,
+                infos.cancel_accounts(),
+                instruction_data(9, 50),
+            ),
+            (MIGRATE_ACCOUNTS, infos.settings_accounts(), vec![10]),
+            (
+                SET_RENT_FEE_ACCOUNTS,
+                infos.settings_accounts(),
+                vec![11, 250, 0],
+            ),
+            (
+                TRANSFER_MAKER_ACCOUNTS,
+                infos.settings_accounts(),
+                [&[12][..], &[1; 32]].concat(),
+            ),
+            (
+                RELIST_ACCOUNTS,
+                infos.relist_accounts(),
+                instruction_data(13, 500),
+            ),
+            (LOG_STATE_ACCOUNTS, vec![infos.escrow.clone()], vec![14]),
+            (SET_HOOK_ACCOUNTS, infos.hook_accounts(), vec![15]),
+            (SET_AUDIT_CLOSE_ACCOUNTS, infos.hook_accounts(), vec![22, 1]),
+        ];
+        for (count, accounts, data) in &cases {
+            let mut accounts = accounts[..*count].to_vec();
+            accounts.push(infos.taker.clone());
+            assert_eq!(
+                process(&program_id, &accounts, data),
+                Err(EscrowError::TooManyAccounts.into()),
+                "instruction {}",
+                data[0]
+            );
+        }
+        assert_eq!(infos.escrow_state().expected_amount, 500);
+        assert!(!infos.escrow_state().frozen);
+
+        // one fewer is covered by `test_process_not_enough_accounts`, the count
+        // itself gets past the check
+        for (count, accounts, data) in &cases {
+            let result = process(&program_id, &accounts[..*count], data);
+            assert_ne!(
+                result,
+                Err(ProgramError::NotEnoughAccountKeys),
+                "instruction {}",
+                data[0]
+            );
+            assert_ne!(
+                result,
+                Err(EscrowError::TooManyAccounts.into()),
+                "instruction {}",
+                data[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_unknown_instruction() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        assert_eq!(
+            process(&program_id, &infos.init_accounts(), &[]),
+            Err(EscrowError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            process(&program_id, &infos.init_accounts(), &[255]),
+            Err(EscrowError::InvalidInstruction.into())
+        );
+        assert!(
+            !Escrow::try_from_account_data(&infos.escrow.data.borrow())
+                .unwrap()
+                .is_initialized
+        );
+    }
+
+    #[test]
+    fn test_process_not_enough_accounts() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        let init = infos.init_accounts();
+        let exchange = infos.exchange_accounts();
+        let batch = infos.batch_accounts();
+        let cancel = infos.cancel_accounts();
+        let settings = infos.settings_accounts();
+        let relist = infos.relist_accounts();
+        let cancel_all = [
+            infos.cancel_all_accounts(),
+            infos.cancel_all_accounts()[CANCEL_ALL_ACCOUNTS..].to_vec(),
+        ]
+        .concat();
+
+        let cases: Vec<(&[AccountInfo], Vec<u8>)> = vec![
+            (&[], instruction_data(0, 500)),
+            (&init[..INIT_ESCROW_ACCOUNTS - 1], instruction_data(0, 500)),
+            (&exchange[..EXCHANGE_ACCOUNTS - 1], instruction_data(1, 100)),
+            (&cancel[..CANCEL_ESCROW_ACCOUNTS - 1], vec![2]),
+            (&cancel[..SET_FROZEN_ACCOUNTS - 1], vec![3, 1]),
+            (
+                &init[..INIT_ESCROW_ACCOUNTS - 1],
+                init_multi_data(500, &[*infos.receive_mint.key]),
+            ),
+            // one fill needs the shared accounts plus its own
+            (
+                &batch[..BATCH_EXCHANGE_ACCOUNTS + BATCH_FILL_ACCOUNTS - 1],
+                vec![5, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+            (&exchange[..EXCHANGE_ACCOUNTS - 1], instruction_data(6, 100)),
+            (
+                &cancel[..SET_EXPIRY_ACCOUNTS - 1],
+                instruction_data(7, 1_000),
+            ),
+            (&cancel[..TOP_UP_ACCOUNTS - 1], instruction_data(8, 50)),
+            (
+                &cancel[..WITHDRAW_PARTIAL_ACCOUNTS - 1],
+                instruction_data(9, 50),
+            ),
+            (&settings[..MIGRATE_ACCOUNTS - 1], vec![10]),
+            (&settings[..SET_RENT_FEE_ACCOUNTS - 1], vec![11, 250, 0]),
+            (
+                &settings[..TRANSFER_MAKER_ACCOUNTS - 1],
+                [&[12][..], &[1; 32]].concat(),
+            ),
+            (&relist[..RELIST_ACCOUNTS - 1], instruction_data(13, 500)),
+            (&[], vec![14]),
+            (&settings[..SET_HOOK_ACCOUNTS - 1], vec![15]),
+            (&init[..INIT_ESCROW_ACCOUNTS - 1], init_ratio_data(5, 3)),
+            (&cancel_all[..CANCEL_ALL_ACCOUNTS], vec![17]),
+            (&cancel_all[..cancel_all.len() - 1], vec![17]),
+            (
+                &init[..INIT_ESCROW_ACCOUNTS - 1],
+                init_oracle_data(&Pubkey::new_unique(), 10, 20),
+            ),
+            (&settings[..ADMIN_ACCOUNTS - 1], vec![19, 1]),
+            (
+                &init[..INIT_ESCROW_ACCOUNTS - 1],
+                init_split_data(500, &[(*infos.maker_receive.key, MAX_BPS)]),
+            ),
+            (&init[..INIT_ESCROW_ACCOUNTS - 1], init_scaled_data(5, 2)),
+            (&settings[..SET_AUDIT_CLOSE_ACCOUNTS - 1], vec![22, 1]),
+            (&settings[..FINALIZE_CLOSED_ACCOUNTS - 1], vec![23]),
+            (&exchange[..EXCHANGE_ACCOUNTS - 1], vec![24, 244, 1]),
+            (&cancel_all[..SCAN_EXPIRED_ACCOUNTS], vec![25, 0]),
+            (&cancel_all[..SCAN_EXPIRED_ACCOUNTS + 1], vec![25, 1]),
+        ];
+        for (accounts, data) in cases {
+            assert_eq!(
+                process(&program_id, accounts, &data),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "instruction {}",
+                data[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_init_with_nonce_is_idempotent() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let nonce = [7; 32];
+        let (escrow_address, _bump_seed) =
+            find_escrow_address(&program_id, &fixture.maker.key, &nonce);
+        fixture.maker.lamports = 1_000_000_000;
+        fixture.escrow = TestAccount {
+            key: escrow_address,
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![0; Escrow::LEN])
+        };
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let infos = fixture.infos();
+        let accounts = [infos.init_accounts(), vec![system.info()]].concat();
+        let mut data = init_data(500, 0);
+        data.extend_from_slice(&nonce);
+
+        process(&program_id, &accounts, &data).unwrap();
+        assert_eq!(*infos.escrow.owner, program_id);
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.nonce, Some(nonce));
+        assert_eq!(escrow.expected_amount, 500);
+
+        // the retried init lands on the same escrow
+        assert_eq!(
+            process(&program_id, &accounts, &data),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+
+        // and a nonce only fits its own address
+        let mut data = init_data(500, 0);
+        data.extend_from_slice(&[8; 32]);
+        assert_eq!(
+            process(&program_id, &accounts, &data),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+    }
+
+    #[test]
+    fn test_built_init_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let init = InitEscrowTransaction {
+            maker: fixture.maker.key,
+            temp_token_account: fixture.temp.key,
+            maker_source: fixture.maker_refund.key,
+            mint: fixture.temp_mint.key,
+            maker_receive: fixture.maker_receive.key,
+            deposit: 100,
+            expected_amount: 500,
+            nonce: [7; 32],
+        };
+        let (instructions, escrow_address) =
+            build_init_escrow_transaction(&program_id, &init).unwrap();
+        fixture.maker.lamports = 1_000_000_000;
+        // the temp account's keypair signs, the transaction creates it
+        fixture.temp = TestAccount {
+            key: fixture.temp.key,
+            lamports: 0,
+            is_signer: true,
+            ..TestAccount::new(&system_program::id(), vec![0; Account::LEN])
+        };
+        fixture.escrow = TestAccount {
+            key: escrow_address,
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![0; Escrow::LEN])
+        };
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let infos = fixture.infos();
+        Infos::set_token_amount(&infos.maker_refund, 100);
+
+        // each instruction in order, against the accounts it names
+        let available = [
+            infos.init_accounts(),
+            vec![
+                infos.maker_refund.clone(),
+                infos.temp_mint.clone(),
+                system.info(),
+            ],
+        ]
+        .concat();
+        for instruction in &instructions {
+            let accounts: Vec<_> = instruction
+                .accounts
+                .iter()
+                .map(|meta| {
+                    let info = available.iter().find(|info| *info.key == meta.pubkey);
+                    let info = info.unwrap().clone();
+                    assert!(!meta.is_signer || info.is_signer);
+                    info
+                })
+                .collect();
+            let result = if instruction.program_id == system_program::id() {
+                process_system(&accounts, &instruction.data)
+            } else if instruction.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &accounts,
+                    &instruction.data,
+                )
+            } else {
+                process(&program_id, &accounts, &instruction.data)
+            };
+            result.unwrap();
+        }
+
+        let temp = Infos::token_state(&infos.temp);
+        assert_eq!(temp.amount, 100);
+        assert_eq!(temp.owner, find_escrow_authority(&program_id).0);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 0);
+        assert_eq!(*infos.escrow.owner, program_id);
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.nonce, Some([7; 32]));
+        assert_eq!(escrow.expected_amount, 500);
+        assert_eq!(escrow.initializer_pubkey, init.maker);
+        assert!(escrow
+            .temp_token_account_pubkey
+            .matches(&init.temp_token_account));
+    }
+
+    #[test]
+    fn test_built_exchange_and_cancel() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let exchange = build_exchange_instruction(
+            &program_id,
+            &ExchangeKeys {
+                taker: fixture.taker.key,
+                taker_token_sent: fixture.taker_sent.key,
+                taker_token_to_receive: fixture.taker_receive.key,
+                temp_token_account: fixture.temp.key,
+                maker: fixture.maker.key,
+                maker_receive: fixture.maker_receive.key,
+                escrow: fixture.escrow.key,
+            },
+            40,
+            0,
+        );
+        let cancel = build_cancel_instruction(
+            &program_id,
+            &CancelKeys {
+                maker: fixture.maker.key,
+                maker_refund: fixture.maker_refund.key,
+                temp_token_account: fixture.temp.key,
+                escrow: fixture.escrow.key,
+            },
+        );
+        let (_, infos) = fixture.initialized();
+
+        // any account the processor reads from another position fails the fill
+        let available = [infos.exchange_accounts(), infos.cancel_accounts()].concat();
+        let accounts_of = |instruction: &Instruction| -> Vec<_> {
+            instruction
+                .accounts
+                .iter()
+                .map(|meta| {
+                    let info = available.iter().find(|info| *info.key == meta.pubkey);
+                    info.unwrap().clone()
+                })
+                .collect()
+        };
+        process(&program_id, &accounts_of(&exchange), &exchange.data).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 200);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 40);
+
+        process(&program_id, &accounts_of(&cancel), &cancel.data).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 60);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    /// Inits an escrow expiring at 1_000 and passes the cancel accounts, signed by
+    /// no one, followed by a clock at `now`
+    fn cancel_at(now: i64, test: impl FnOnce(&Infos, Vec<AccountInfo>)) {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut clock = TestAccount::sysvar(&Clock {
+            unix_timestamp: now,
+            ..Clock::default()
+        });
+        let (program_id, infos) = fixture.initialized();
+        let mut data = vec![7];
+        data.extend_from_slice(&1_000i64.to_le_bytes());
+        let accounts = vec![infos.maker.clone(), infos.escrow.clone()];
+        process(&program_id, &accounts, &data).unwrap();
+        assert_eq!(infos.escrow_state().expires_at, 1_000);
+
+        let mut accounts = [infos.cancel_accounts(), vec![clock.info()]].concat();
+        accounts[0].is_signer = false;
+        test(&infos, accounts);
+    }
+
+    #[test]
+    fn test_cancel_before_expiry_without_maker() {
+        cancel_at(999, |infos, accounts| {
+            let program_id = *infos.escrow.owner;
+            assert_eq!(
+                process(&program_id, &accounts, &[2]),
+                Err(ProgramError::MissingRequiredSignature)
+            );
+            assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        });
+    }
+
+    #[test]
+    fn test_cancel_after_expiry_without_maker() {
+        cancel_at(1_000, |infos, accounts| {
+            let program_id = *infos.escrow.owner;
+            // the maker's signature is still needed to unwrap
+            assert_eq!(
+                process(&program_id, &accounts, &[2, 1]),
+                Err(ProgramError::MissingRequiredSignature)
+            );
+
+            process(&program_id, &accounts, &[2]).unwrap();
+            assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+            assert_eq!(infos.escrow.data_len(), 0);
+        });
+    }
+
+    #[test]
+    fn test_cancel_before_expiry_by_maker() {
+        cancel_at(999, |infos, mut accounts| {
+            let program_id = *infos.escrow.owner;
+            accounts[0].is_signer = true;
+            process(&program_id, &accounts, &[2]).unwrap();
+            assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        });
+    }
+
+    #[test]
+    fn test_scan_expired() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut temp2 = TestAccount::token(&fixture.temp_mint.key, &fixture.maker.key, 50);
+        let mut escrow2 = TestAccount::new(&program_id, vec![0; Escrow::LEN]);
+        let (_, infos) = fixture.initialized();
+        let temp2 = temp2.info();
+        let escrow2 = escrow2.info();
+        let mut accounts = infos.init_accounts();
+        accounts[1] = temp2.clone();
+        accounts[3] = escrow2.clone();
+        process(&program_id, &accounts, &instruction_data(0, 250)).unwrap();
+
+        // the first expired a second ago, the second has a minute left
+        for (escrow, expires_at) in [
+            (&infos.escrow, FIXTURE_NOW - 1),
+            (&escrow2, FIXTURE_NOW + 60),
+        ] {
+            let state = Escrow {
+                expires_at,
+                ..Escrow::unpack(&escrow.data.borrow()).unwrap()
+            };
+            Escrow::pack(state, &mut escrow.data.borrow_mut()).unwrap();
+        }
+        let shared = [
+            infos.clock.clone(),
+            infos.token_program.clone(),
+            infos.pda.clone(),
+        ];
+        let logged = |escrow: &AccountInfo, outcome: &str| {
+            let expected = format!("Escrow {}: {}", escrow.key, outcome);
+            LOGS.with(|logs| logs.borrow().contains(&expected))
+        };
+
+        // listing only reports
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let accounts = [
+            &shared[..],
+            &[infos.escrow.clone(), escrow2.clone(), infos.taker.clone()],
+        ]
+        .concat();
+        process(&program_id, &accounts, &[25, 0]).unwrap();
+        assert!(logged(
+            &infos.escrow,
+            &format!("expired at {}", FIXTURE_NOW - 1)
+        ));
+        assert!(logged(&escrow2, "live"));
+        assert!(logged(&infos.taker, "skipped, not an open escrow"));
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+
+        // reclaiming cancels the expired one alone, a wallet in between is skipped
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let maker_lamports = infos.maker.lamports();
+        let reclaimed = infos.escrow.lamports() + infos.temp.lamports();
+        let mut maker = infos.maker.clone();
+        maker.is_signer = false;
+        let accounts = [
+            &shared[..],
+            &[
+                infos.escrow.clone(),
+                infos.temp.clone(),
+                infos.maker_refund.clone(),
+                maker.clone(),
+            ],
+            &[
+                infos.taker.clone(),
+                infos.temp.clone(),
+                infos.maker_refund.clone(),
+                maker.clone(),
+            ],
+            &[
+                escrow2.clone(),
+                temp2.clone(),
+                infos.maker_refund.clone(),
+                maker,
+            ],
+        ]
+        .concat();
+        process(&program_id, &accounts, &[25, 1]).unwrap();
+        assert!(logged(&infos.escrow, "reclaimed"));
+        assert!(logged(&escrow2, "live"));
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+        assert_eq!(infos.maker.lamports(), maker_lamports + reclaimed);
+        assert_eq!(infos.escrow.data_len(), 0);
+        assert_eq!(Infos::token_state(&temp2).amount, 50);
+        assert_eq!(
+            Escrow::unpack(&escrow2.data.borrow()).unwrap().expires_at,
+            FIXTURE_NOW + 60
+        );
+
+        // an expired escrow passed with another maker is left for a later scan
+        let state = Escrow {
+            expires_at: FIXTURE_NOW - 1,
+            ..Escrow::unpack(&escrow2.data.borrow()).unwrap()
+        };
+        Escrow::pack(state, &mut escrow2.data.borrow_mut()).unwrap();
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let accounts = [
+            &shared[..],
+            &[
+                escrow2.clone(),
+                temp2.clone(),
+                infos.maker_refund.clone(),
+                infos.taker.clone(),
+            ],
+        ]
+        .concat();
+        process(&program_id, &accounts, &[25, 1]).unwrap();
+        assert!(LOGS.with(|logs| logs
+            .borrow()
+            .iter()
+            .any(|log| log.starts_with(&format!("Escrow {}: not reclaimed", escrow2.key)))));
+        assert_eq!(Infos::token_state(&temp2).amount, 50);
+    }
+
+    #[test]
+    fn test_scan_expired_fails_once_reclaim_started() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+        let state = Escrow {
+            expires_at: FIXTURE_NOW - 1,
+            ..infos.escrow_state()
+        };
+        Escrow::pack(state, &mut infos.escrow.data.borrow_mut()).unwrap();
+        // too full for the temp account's lamports coming back
+        **infos.maker.lamports.borrow_mut() = u64::MAX;
+
+        // the refund went through before closing the temp account failed, the
+        // batch fails rather than skip a cancel left halfway
+        let mut maker = infos.maker.clone();
+        maker.is_signer = false;
+        let accounts = [
+            infos.clock.clone(),
+            infos.token_program.clone(),
+            infos.pda.clone(),
+            infos.escrow.clone(),
+            infos.temp.clone(),
+            infos.maker_refund.clone(),
+            maker,
+        ];
+        assert_eq!(
+            process(&program_id, &accounts, &[25, 1]),
+            Err(spl_token::error::TokenError::Overflow.into())
+        );
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 100);
+    }
+
+    #[test]
+    fn test_top_up_then_exchange() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut clock = TestAccount::sysvar(&Clock::default());
+        let (program_id, infos) = fixture.initialized();
+
+        Infos::set_token_amount(&infos.maker_refund, 50);
+        let mut accounts = vec![
+            infos.maker.clone(),
+            infos.maker_refund.clone(),
+            infos.temp.clone(),
+            infos.escrow.clone(),
+            infos.token_program.clone(),
+            clock.info(),
+        ];
+        accounts[0].is_signer = false;
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(8, 50)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        accounts[0].is_signer = true;
+        process(&program_id, &accounts, &instruction_data(8, 50)).unwrap();
+        assert_eq!(Infos::token_state(&infos.temp).amount, 150);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 0);
+        assert_eq!(infos.escrow_state().expected_amount, 750);
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 150),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 150);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 750);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_top_up_rejected() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut clock = TestAccount::sysvar(&Clock {
+            unix_timestamp: 1_000,
+            ..Clock::default()
+        });
+        let (program_id, infos) = fixture.initialized();
+        Infos::set_token_amount(&infos.maker_refund, 50);
+        let accounts = vec![
+            infos.maker.clone(),
+            infos.maker_refund.clone(),
+            infos.temp.clone(),
+            infos.escrow.clone(),
+            infos.token_program.clone(),
+            clock.info(),
+        ];
+
+        let mut escrow = infos.escrow_state();
+        escrow.expires_at = 1_000;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(8, 50)),
+            Err(EscrowError::EscrowExpired.into())
+        );
+
+        let mut escrow = infos.escrow_state();
+        escrow.expires_at = 0;
+        Escrow::pack(escrow, &mut infos.escrow.data.borrow_mut()).unwrap();
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+        assert!(infos.escrow_state().partially_filled);
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(8, 50)),
+            Err(EscrowError::PartiallyFilled.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 60);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 50);
+    }
+
+    #[test]
+    fn test_withdraw_partial() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 20)).unwrap();
+
+        process(
+            &program_id,
+            &infos.cancel_accounts(),
+            &instruction_data(9, 40),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.temp).amount, 60);
+        assert_eq!(Infos::token_state(&infos.maker_refund).amount, 40);
+        let escrow = infos.escrow_state();
+        assert!(escrow.is_initialized);
+        assert_eq!(escrow.expected_amount, 300);
+
+        // the rest still trades at the same price
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 60),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 60);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 300);
+    }
+
+    #[test]
+    fn test_withdraw_partial_rejected() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+        process(&program_id, &infos.init_accounts(), &init_data(500, 20)).unwrap();
+
+        let accounts = infos.cancel_accounts();
+        for amount in [101, 100] {
+            assert_eq!(
+                process(&program_id, &accounts, &instruction_data(9, amount)),
+                Err(EscrowError::ExpectedAmountMismatch.into())
+            );
+        }
+        // would leave 10, below the minimum fill of 20
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(9, 90)),
+            Err(EscrowError::BelowMinimumFill.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(infos.escrow_state().expected_amount, 500);
+    }
+
+    #[test]
+    fn test_exchange_creates_maker_receive_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        fixture.taker.lamports = 1_000_000_000;
+        fixture.maker_receive = TestAccount {
+            key: get_associated_token_address(&fixture.maker.key, &fixture.receive_mint.key),
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![0; Account::LEN])
+        };
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let mut ata_program = TestAccount {
+            key: spl_associated_token_account::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let infos = fixture.infos();
+        let system = system.info();
+        let ata_program = ata_program.info();
+
+        // the mint tells which associated account the address is
+        let accounts = [infos.init_accounts(), vec![infos.receive_mint.clone()]].concat();
+        process(&program_id, &accounts, &instruction_data(0, 500)).unwrap();
+
+        let accounts = [
+            infos.exchange_accounts(),
+            vec![
+                infos.receive_mint.clone(),
+                system,
+                infos.rent.clone(),
+                ata_program,
+            ],
+        ]
+        .concat();
+        let mut data = instruction_data(1, 100);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        assert_eq!(
+            process(&program_id, &accounts, &data),
+            Err(ProgramError::UninitializedAccount)
+        );
+
+        data.push(1);
+        process(&program_id, &accounts, &data).unwrap();
+        assert_eq!(*infos.maker_receive.owner, spl_token::id());
+        let maker_receive = Infos::token_state(&infos.maker_receive);
+        assert_eq!(maker_receive.owner, *infos.maker.key);
+        assert_eq!(maker_receive.mint, *infos.receive_mint.key);
+        assert_eq!(maker_receive.amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_wraps_sol() {
+        let mut fixture = Fixture::new(100, 0);
+        let native_mint = spl_token::native_mint::id();
+        fixture.receive_mint = TestAccount {
+            key: native_mint,
+            ..TestAccount::mint(9)
+        };
+        fixture.maker_receive = TestAccount::native_token(&fixture.maker.key, 0);
+        fixture.taker.lamports = 1_000_000_000;
+        fixture.taker_sent = TestAccount {
+            key: get_associated_token_address(&fixture.taker.key, &native_mint),
+            lamports: 0,
+            ..TestAccount::new(&system_program::id(), vec![0; Account::LEN])
+        };
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let mut ata_program = TestAccount {
+            key: spl_associated_token_account::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let system = system.info();
+        let ata_program = ata_program.info();
+
+        let data = |wrap_sol| {
+            EscrowInstruction::Exchange {
+                amount: 100,
+                min_receive: 0,
+                create_maker_receive: false,
+                wrap_sol,
+                quote_program: None,
+                memo: None,
+            }
+            .pack()
+        };
+        let accounts = [
+            infos.exchange_accounts(),
+            vec![
+                infos.receive_mint.clone(),
+                system,
+                infos.rent.clone(),
+                ata_program,
+            ],
+        ]
+        .concat();
+        assert_eq!(
+            process(&program_id, &accounts, &data(false)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        let taker_lamports = infos.taker.lamports();
+        process(&program_id, &accounts, &data(true)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        // the wrapped account is gone, its rent back with the taker
+        assert_eq!(infos.taker_sent.lamports(), 0);
+        assert_eq!(infos.taker.lamports(), taker_lamports - 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_wrap_sol_keeps_existing_account() {
+        let mut fixture = Fixture::new(100, 0);
+        let native_mint = spl_token::native_mint::id();
+        fixture.receive_mint = TestAccount {
+            key: native_mint,
+            ..TestAccount::mint(9)
+        };
+        fixture.maker_receive = TestAccount::native_token(&fixture.maker.key, 0);
+        fixture.taker.lamports = 1_000_000_000;
+        fixture.taker_sent = TestAccount {
+            key: get_associated_token_address(&fixture.taker.key, &native_mint),
+            ..TestAccount::native_token(&fixture.taker.key, 200)
+        };
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let system = system.info();
+
+        let data = EscrowInstruction::Exchange {
+            amount: 100,
+            min_receive: 0,
+            create_maker_receive: false,
+            wrap_sol: true,
+            quote_program: None,
+            memo: None,
+        }
+        .pack();
+        let accounts = [
+            infos.exchange_accounts(),
+            vec![infos.receive_mint.clone(), system],
+        ]
+        .concat();
+        let taker_lamports = infos.taker.lamports();
+        let sent_lamports = infos.taker_sent.lamports();
+        process(&program_id, &accounts, &data).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        // only the 300 lamports the account was short of are wrapped, and the
+        // taker's account stays open with its rent
+        assert_eq!(infos.taker.lamports(), taker_lamports - 300);
+        assert_eq!(infos.taker_sent.lamports(), sent_lamports - 200);
+        assert_eq!(Infos::token_state(&infos.taker_sent).amount, 0);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_migrate_legacy_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.maker.lamports = 1_000_000_000;
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::new(&Pubkey::default(), vec![])
+        };
+        let (program_id, infos) = fixture.initialized();
+        let system = system.info();
+
+        // the first layout, the runtime keeps the data length just ahead of the data
+        // and leaves room to grow after it
+        let legacy_len = ESCROW_LEGACY_LENS[0];
+        let mut buffer = vec![0; 8 + Escrow::LEN];
+        buffer[..8].copy_from_slice(&(legacy_len as u64).to_le_bytes());
+        buffer[8..8 + legacy_len].copy_from_slice(&infos.escrow.data.borrow()[..legacy_len]);
+        let key = Pubkey::new_unique();
+        let mut lamports = Rent::default().minimum_balance(legacy_len);
+        let legacy = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut buffer[8..8 + legacy_len],
+            &program_id,
+            false,
+            0,
+        );
+
+        let accounts = vec![
+            infos.maker.clone(),
+            legacy.clone(),
+            infos.rent.clone(),
+            system,
+        ];
+        let maker_lamports = infos.maker.lamports();
+        process(&program_id, &accounts, &[10]).unwrap();
+        assert_eq!(legacy.data_len(), Escrow::LEN);
+        let rent_exempt = Rent::default().minimum_balance(Escrow::LEN);
+        assert_eq!(legacy.lamports(), rent_exempt);
+        assert_eq!(
+            infos.maker.lamports(),
+            maker_lamports - (rent_exempt - Rent::default().minimum_balance(legacy_len))
+        );
+        // what the first layout held carries over, everything added since is zeroed
+        let escrow = infos.escrow_state();
+        assert_eq!(
+            Escrow::unpack(&legacy.data.borrow()).unwrap(),
+            Escrow {
+                is_initialized: true,
+                initializer_pubkey: escrow.initializer_pubkey,
+                temp_token_account_pubkey: escrow.temp_token_account_pubkey,
+                initializer_token_to_receive_account_pubkey: escrow
+                    .initializer_token_to_receive_account_pubkey,
+                expected_amount: escrow.expected_amount,
+                ..Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap()
+            }
+        );
+
+        assert_eq!(
+            process(&program_id, &accounts, &[10]),
+            Err(EscrowError::AlreadyMigrated.into())
+        );
+    }
+
+    #[test]
+    fn test_exchange_sets_receipt() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        let receipt = || {
+            let (program, data) = get_return_data().unwrap();
+            assert_eq!(program, program_id);
+            parse_exchange_receipt(&data).unwrap()
+        };
+        for (tag, amount, closed) in [(6, 100, true), (1, 40, false), (1, 60, true)] {
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(tag, amount),
+            )
+            .unwrap();
+            assert_eq!(
+                receipt(),
+                ExchangeReceipt {
+                    amount_in: amount * 5,
+                    amount_out: amount,
+                    closed,
+                }
+            );
+        }
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_account_in_two_roles() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let infos = fixture.infos();
+
+        let mut accounts = infos.init_accounts();
+        accounts[3] = infos.temp.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(0, 500)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, 500),
+        )
+        .unwrap();
+
+        let mut accounts = infos.exchange_accounts();
+        accounts[3] = infos.escrow.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        let mut accounts = infos.exchange_accounts();
+        accounts[2] = infos.taker_sent.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        let mut accounts = infos.cancel_accounts();
+        accounts[1] = infos.temp.clone();
+        assert_eq!(
+            process(&program_id, &accounts, &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+    }
+
+    #[test]
+    fn test_exchange_aliased_token_accounts() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        // taker receive, taker sent; each in turn as the maker's receive account
+        // and as the temp account
+        for (taker_pos, escrow_pos) in [(2, 5), (1, 5), (2, 3), (1, 3)] {
+            let mut accounts = infos.exchange_accounts();
+            accounts[taker_pos] = accounts[escrow_pos].clone();
+            assert_eq!(
+                process(&program_id, &accounts, &instruction_data(1, 100)),
+                Err(EscrowError::InvalidAccountData.into()),
+                "account {} as {}",
+                taker_pos,
+                escrow_pos
+            );
+        }
+        assert_eq!(Infos::token_state(&infos.temp).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 0);
+    }
+
+    #[test]
+    fn test_maker_fills_own_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        fixture.taker.key = fixture.maker.key;
+        for acc in [&mut fixture.taker_sent, &mut fixture.taker_receive] {
+            let mut token = Account::unpack(&acc.data).unwrap();
+            token.owner = fixture.maker.key;
+            Account::pack(token, &mut acc.data).unwrap();
+        }
+        let (program_id, infos) = fixture.initialized();
+
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    /// Makes the maker the config's admin as a first `Admin` would, who then has
+    /// `fee` paid `rent_fee_bps` of the rent of every escrow closing
+    fn set_rent_fee<'a>(
+        program_id: &Pubkey,
+        infos: &Infos<'a>,
+        fee: &AccountInfo<'a>,
+        rent_fee_bps: u16,
+    ) {
+        let config = Config {
+            is_initialized: true,
+            admin: *infos.maker.key,
+            ..Config::default()
+        };
+        Config::pack(config, &mut infos.config.data.borrow_mut()).unwrap();
+        infos.config.assign(program_id);
+        process(
+            program_id,
+            &[infos.maker.clone(), infos.config.clone(), fee.clone()],
+            &[&[11][..], &rent_fee_bps.to_le_bytes()].concat(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_set_rent_fee() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let program_id = fixture.program_id;
+        let mut fee = TestAccount::wallet();
+        let infos = fixture.infos();
+        let fee = fee.info();
+        let data = [&[11][..], &2_500u16.to_le_bytes()].concat();
+
+        // nothing to set before the admin's first call creates the config
+        assert_eq!(
+            process(
+                &program_id,
+                &[infos.maker.clone(), infos.config.clone(), fee.clone()],
+                &data
+            ),
+            Err(ProgramError::IncorrectProgramId)
+        );
+
+        set_rent_fee(&program_id, &infos, &fee, 2_500);
+        let config = Config::unpack(&infos.config.data.borrow()).unwrap();
+        assert_eq!(config.rent_fee_bps, 2_500);
+        assert_eq!(config.rent_fee_account, *fee.key);
+
+        // a maker can't set it on their own escrow, nor anyone but the admin
+        process(
+            &program_id,
+            &infos.init_accounts(),
+            &instruction_data(0, 500),
+        )
+        .unwrap();
+        assert_eq!(
+            process(
+                &program_id,
+                &[infos.maker.clone(), infos.escrow.clone(), fee.clone()],
+                &data
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &[
+                    infos.taker.clone(),
+                    infos.config.clone(),
+                    infos.taker.clone()
+                ],
+                &[11, 0, 0]
+            ),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let config = Config::unpack(&infos.config.data.borrow()).unwrap();
+        assert_eq!(config.rent_fee_bps, 2_500);
+        assert_eq!(config.rent_fee_account, *fee.key);
+    }
+
+    #[test]
+    fn test_cancel_pays_no_rent_fee() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut fee = TestAccount::wallet();
+        let (program_id, infos) = fixture.initialized();
+        let fee = fee.info();
+        set_rent_fee(&program_id, &infos, &fee, 2_500);
+
+        // the maker gets all the rent back, without the config or its fee account
+        let escrow_lamports = infos.escrow.lamports();
+        let fee_lamports = fee.lamports();
+        let maker_lamports = infos.maker.lamports();
+        let temp_lamports = infos.temp.lamports();
+        process(&program_id, &infos.cancel_accounts(), &[2]).unwrap();
+        assert_eq!(fee.lamports(), fee_lamports);
+        assert_eq!(
+            infos.maker.lamports(),
+            maker_lamports + temp_lamports + escrow_lamports
+        );
+        assert_eq!(infos.escrow.lamports(), 0);
+    }
+
+    #[test]
+    fn test_exchange_splits_rent_with_fee_account() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut fee = TestAccount::wallet();
+        let (program_id, infos) = fixture.initialized();
+        let fee = fee.info();
+        set_rent_fee(&program_id, &infos, &fee, 2_500);
+
+        // a partial fill leaves the rent in place
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 40),
+        )
+        .unwrap();
+
+        // closing needs the fee account
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.exchange_accounts(),
+                &instruction_data(6, 60),
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut accounts = infos.exchange_accounts();
+        accounts.push(fee.clone());
+        let drained = infos.escrow.lamports();
+        let temp_lamports = infos.temp.lamports();
+        let maker_lamports = infos.maker.lamports();
+        let fee_lamports = fee.lamports();
+        process(&program_id, &accounts, &instruction_data(1, 60)).unwrap();
+
+        let fee_share = drained * 2_500 / 10_000;
+        assert_eq!(fee.lamports(), fee_lamports + fee_share);
+        assert_eq!(
+            infos.maker.lamports(),
+            maker_lamports + temp_lamports + drained - fee_share
+        );
+        assert_eq!(infos.escrow.lamports(), 0);
+        assert_eq!(
+            fee.lamports() + infos.maker.lamports(),
+            fee_lamports + maker_lamports + temp_lamports + drained
+        );
+    }
+
+    #[test]
+    fn test_exchange_audit_close_and_finalize() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut fee = TestAccount::wallet();
+        let (program_id, infos) = fixture.initialized();
+        let fee = fee.info();
+        process(&program_id, &infos.hook_accounts(), &[22, 1]).unwrap();
+        set_rent_fee(&program_id, &infos, &fee, 2_500);
+
+        // a partial fill leaves the escrow trading as before
+        let mut accounts = infos.exchange_accounts();
+        accounts.push(infos.clock.clone());
+        process(&program_id, &accounts, &instruction_data(1, 40)).unwrap();
+        assert!(!infos.escrow_state().is_completed());
+
+        let escrow_lamports = infos.escrow.lamports();
+        process(&program_id, &accounts, &instruction_data(1, 60)).unwrap();
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        assert_eq!(infos.temp.lamports(), 0);
+        // kept with its rent, recording the taker
+        assert_eq!(infos.escrow.lamports(), escrow_lamports);
+        let escrow = infos.escrow_state();
+        assert!(escrow.is_initialized && escrow.is_completed() && !escrow.is_empty());
+        assert_eq!(escrow.completed_by, *infos.taker.key);
+        assert_eq!(escrow.completed_at, FIXTURE_NOW);
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 1)),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &infos.relist_accounts(),
+                &instruction_data(13, 500)
+            ),
+            Err(EscrowError::EscrowActive.into())
+        );
+
+        // only the maker finalizes, passing the config and its fee account
+        assert_eq!(
+            process(&program_id, &infos.hook_accounts(), &[23]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let mut finalize = vec![
+            infos.taker.clone(),
+            infos.escrow.clone(),
+            infos.config.clone(),
+            fee.clone(),
+        ];
+        assert_eq!(
+            process(&program_id, &finalize, &[23]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        finalize[0] = infos.maker.clone();
+        let maker_lamports = infos.maker.lamports();
+        let fee_lamports = fee.lamports();
+        process(&program_id, &finalize, &[23]).unwrap();
+        let fee_share = escrow_lamports * 2_500 / 10_000;
+        assert_eq!(fee.lamports(), fee_lamports + fee_share);
+        assert_eq!(
+            infos.maker.lamports(),
+            maker_lamports + escrow_lamports - fee_share
+        );
+        assert_eq!(infos.escrow.lamports(), 0);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_finalize_open_escrow() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let (program_id, infos) = fixture.initialized();
+
+        assert_eq!(
+            process(&program_id, &infos.finalize_accounts(), &[23]),
+            Err(EscrowError::EscrowActive.into())
+        );
+        // without the audit option the exchange closes the escrow outright
+        process(
+            &program_id,
+            &infos.exchange_accounts(),
+            &instruction_data(1, 100),
+        )
+        .unwrap();
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_rent_split() {
+        assert_eq!(rent_split(2_039_280, 0), Ok((2_039_280, 0)));
+        assert_eq!(rent_split(2_039_280, 2_500), Ok((1_529_460, 509_820)));
+        // rounded down in the maker's favour
+        assert_eq!(rent_split(999, 1), Ok((999, 0)));
+        assert_eq!(rent_split(u64::MAX, MAX_BPS), Ok((0, u64::MAX)));
+        assert_eq!(
+            rent_split(10_000, MAX_BPS + 1),
+            Err(EscrowError::AmountOverflow.into())
+        );
+    }
+
+    #[test]
+    fn test_transfer_maker() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut new_maker = TestAccount::wallet();
+        let mut new_receive = TestAccount::token(&fixture.receive_mint.key, &new_maker.key, 0);
+        let mut new_refund = TestAccount::token(&fixture.temp_mint.key, &new_maker.key, 0);
+        let mut wrong_mint = TestAccount::token(&fixture.temp_mint.key, &new_maker.key, 0);
+        let (program_id, infos) = fixture.initialized();
+        let new_maker = new_maker.info();
+        let new_receive = new_receive.info();
+        let new_refund = new_refund.info();
+        let wrong_mint = wrong_mint.info();
+
+        let transfer = |new_receive| {
+            process(
+                &program_id,
+                &[
+                    infos.maker.clone(),
+                    infos.escrow.clone(),
+                    infos.maker_receive.clone(),
+                    new_receive,
+                ],
+                &[&[12][..], new_maker.key.as_ref()].concat(),
+            )
+        };
+        assert_eq!(
+            transfer(wrong_mint.clone()),
+            Err(EscrowError::ExpectedMintMismatch.into())
+        );
+        assert_eq!(
+            transfer(infos.maker_receive.clone()),
+            Err(EscrowError::ReceiveAccountNotOwned.into())
+        );
+        transfer(new_receive.clone()).unwrap();
+        let escrow = infos.escrow_state();
+        assert_eq!(escrow.initializer_pubkey, *new_maker.key);
+        assert!(escrow
+            .initializer_token_to_receive_account_pubkey
+            .matches(new_receive.key));
+        // the escrow is no longer the old maker's to hand over
+        assert_eq!(
+            transfer(new_receive.clone()),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+
+        assert_eq!(
+            process(&program_id, &infos.cancel_accounts(), &[2]),
+            Err(EscrowError::InvalidAccountData.into())
+        );
+        let mut accounts = infos.cancel_accounts();
+        accounts[0] = new_maker.clone();
+        accounts[1] = new_refund.clone();
+        let new_maker_lamports = new_maker.lamports();
+        let closed_lamports = infos.temp.lamports() + infos.escrow.lamports();
+        process(&program_id, &accounts, &[2]).unwrap();
+        assert_eq!(Infos::token_state(&new_refund).amount, 100);
+        assert_eq!(new_maker.lamports(), new_maker_lamports + closed_lamports);
+        assert_eq!(infos.escrow.data_len(), 0);
+    }
+
+    #[test]
+    fn test_exchange_paid_by_delegate() {
+        let mut fixture = Fixture::new(100, 1_000);
+        let mut delegate = TestAccount::wallet();
+        let (program_id, infos) = fixture.initialized();
+        let delegate = delegate.info();
+
+        let mut sent = Infos::token_state(&infos.taker_sent);
+        sent.owner = Pubkey::new_unique();
+        sent.delegate = COption::Some(*delegate.key);
+        sent.delegated_amount = 600;
+        Account::pack(sent, &mut infos.taker_sent.data.borrow_mut()).unwrap();
+
+        let mut accounts = infos.exchange_accounts();
+        accounts.push(AccountInfo {
+            is_signer: false,
+            ..delegate.clone()
+        });
+        assert_eq!(
+            process(&program_id, &accounts, &instruction_data(1, 100)),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        accounts.pop();
+        accounts.push(delegate.clone());
+        process(&program_id, &accounts, &instruction_data(1, 100)).unwrap();
+        assert_eq!(Infos::token_state(&infos.maker_receive).amount, 500);
+        assert_eq!(Infos::token_state(&infos.taker_receive).amount, 100);
+        let sent = Infos::token_state(&infos.taker_sent);
+        assert_eq!(sent.amount, 500);
+        assert_eq!(sent.delegated_amount, 100);
+    }
+}
+
+#[cfg(test)]
+mod prop_test {
+    use super::*;
+    use crate::config::MAX_BPS;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn rent_split_conserves_lamports(drained: u64, rent_fee_bps in 0..=MAX_BPS) {
+            let (maker, fee) = rent_split(drained, rent_fee_bps).unwrap();
+            prop_assert_eq!(maker as u128 + fee as u128, drained as u128);
+        }
+
+        #[test]
+        fn fill_stays_within_the_escrow(
+            expected_amount: u64,
+            temp_amount: u64,
+            amount: u64,
+        ) {
+            if let Ok((fill, paid)) = fill_amounts(expected_amount, temp_amount, amount) {
+                prop_assert!(fill <= temp_amount && fill <= amount);
+                prop_assert!(paid <= expected_amount);
+                // never short of the pro rata price
+                prop_assert!(
+                    paid as u128 * temp_amount as u128 >= expected_amount as u128 * fill as u128
+                );
+                if fill == temp_amount {
+                    prop_assert_eq!(paid, expected_amount);
+                }
+            }
+        }
+
+        #[test]
+        fn partial_fills_conserve_tokens(
+            expected_amount: u64,
+            temp_amount in 1..=u64::MAX,
+            amounts in prop::collection::vec(1..=u64::MAX, 1..8),
+        ) {
+            let (mut expected, mut temp) = (expected_amount, temp_amount);
+            let (mut taken, mut paid) = (0u128, 0u128);
+            for amount in amounts.into_iter().chain([u64::MAX]) {
+                let (fill, maker_amount) = fill_amounts(expected, temp, amount).unwrap();
+                expected -= maker_amount;
+                temp -= fill;
+                taken += fill as u128;
+                paid += maker_amount as u128;
+                prop_assert_eq!(taken + temp as u128, temp_amount as u128);
+                prop_assert_eq!(paid + expected as u128, expected_amount as u128);
+                if temp == 0 {
+                    break;
+                }
+            }
+            // the last fill closes the escrow having paid all of it
+            prop_assert_eq!(temp, 0);
+            prop_assert_eq!(expected, 0);
+        }
+
+        #[test]
+        fn top_up_keeps_the_price(
+            expected_amount: u64,
+            temp_amount: u64,
+            amount: u64,
+        ) {
+            if let Ok(topped_up) = topped_up_expected(expected_amount, temp_amount, amount) {
+                prop_assert!(topped_up >= expected_amount);
+                prop_assert!(
+                    topped_up as u128 * temp_amount as u128
+                        >= expected_amount as u128 * (temp_amount as u128 + amount as u128)
+                );
+            }
+        }
+
+        #[test]
+        fn withdraw_never_raises_the_price(
+            expected_amount: u64,
+            temp_amount in 1..=u64::MAX,
+            withdrawn: u64,
+        ) {
+            let remaining = temp_amount - withdrawn % temp_amount;
+            let left = maker_share(expected_amount, remaining, temp_amount);
+            prop_assert!(left <= expected_amount);
+            prop_assert!(
+                left as u128 * temp_amount as u128 >= expected_amount as u128 * remaining as u128
+            );
+        }
     }
 }
@@ -5,16 +5,220 @@ use solana_program::{
 };
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use spl_token::state::Account;
 
+// Clients reach these from before `config` through here
+pub use crate::config::{
+    CONFIG_SEED, ESCROW_SEED, MAX_BPS, MAX_ESCROW_AGE_SECONDS, MAX_ESCROW_AMOUNT,
+    MAX_ORACLE_AGE_SLOTS,
+};
+
+/// The most receive mints a single escrow can accept, see `InitEscrowMulti`
+pub const MAX_ACCEPTED_MINTS: usize = 4;
+
+/// The most accounts a single escrow splits the maker's proceeds across, see
+/// `InitEscrowSplit`
+pub const MAX_RECEIVE_SPLITS: usize = 4;
+
+/// Derives the PDA that owns every escrow's temp token account.
+///
+/// Clients need it to check the temp account ends up under the right authority.
+pub fn find_escrow_authority(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_SEED], program_id)
+}
+
+/// What is left to take from an escrow, given its temp token account data.
+///
+/// Partial fills drain the temp account, so this is what clients show as remaining.
+pub fn available_amount(escrow_temp_data: &[u8]) -> Result<u64, ProgramError> {
+    Ok(Account::unpack(escrow_temp_data)?.amount)
+}
+
+/// Derives the address of an escrow initialized with `nonce`.
+///
+/// Retrying the same init lands on the same address, so it can't open a second escrow.
+pub fn find_escrow_address(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    nonce: &[u8; 32],
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_SEED, initializer.as_ref(), nonce], program_id)
+}
+
+/// Derives the address of the program-wide `Config`, created by the first `Admin`.
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// Packed sizes of the earlier `Escrow` layouts `Migrate` grows, see
+/// `Escrow::unpack_legacy`. Only the original layout, no escrow was ever packed in
+/// the ones in between
+pub const ESCROW_LEGACY_LENS: [usize; 1] = [105];
+
+/// The escrow's temp token account, a type of its own so it only compares to
+/// account keys and not to the escrow's other keys
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TempTokenAccount(pub Pubkey);
+
+/// The maker's token account exchanges pay into, see `TempTokenAccount`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MakerReceiveAccount(pub Pubkey);
+
+impl TempTokenAccount {
+    pub fn matches(&self, key: &Pubkey) -> bool {
+        self.0 == *key
+    }
+}
+
+impl MakerReceiveAccount {
+    pub fn matches(&self, key: &Pubkey) -> bool {
+        self.0 == *key
+    }
+}
+
+/// One of the maker's token accounts to receive and its share of every payment
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MakerReceiveSplit {
+    pub account: Pubkey,
+    // In basis points of `MAX_BPS`
+    pub bps: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Escrow {
     // Check whether ready to use ?
     pub is_initialized: bool,
     pub initializer_pubkey: Pubkey,
     // For convenience and for security check
-    pub temp_token_account_pubkey: Pubkey,
-    pub initializer_token_to_receive_account_pubkey: Pubkey,
+    pub temp_token_account_pubkey: TempTokenAccount,
+    pub initializer_token_to_receive_account_pubkey: MakerReceiveAccount,
     // To check the expected amount is sent
     pub expected_amount: u64,
+    // Strict mode: the decimals of both mints recorded at init, checked again on exchange
+    pub check_decimals: bool,
+    pub temp_token_decimals: u8,
+    pub token_to_receive_decimals: u8,
+    // Set by the maker to block exchanges, cancel still works
+    pub frozen: bool,
+    // Receive mints accepted besides the one of the token to receive account, see
+    // `accepted_mints()`
+    pub accepted_mints_len: u8,
+    pub accepted_mints: [Pubkey; MAX_ACCEPTED_MINTS],
+    // Smallest partial fill accepted, 0 turns the check off
+    pub min_fill: u64,
+    // Set while an exchange is running, see `EscrowError::EscrowBusy`
+    pub locked: bool,
+    // Set by a client retrying init, see `find_escrow_address`
+    pub nonce: Option<[u8; 32]>,
+    // Unix timestamp after which anyone can cancel, 0 never expires
+    pub expires_at: i64,
+    // Set by the first fill that leaves the escrow open, see `TopUp`
+    pub partially_filled: bool,
+    // Unix timestamp of the init, 0 for escrows migrated from before it was kept
+    pub created_at: i64,
+    // Program invoked after every fill, see `SetHook`
+    pub hook_program: Option<Pubkey>,
+    // Set by `InitEscrowRatio`, the maker gets `price_num / price_den` per temp token
+    // and `expected_amount` follows the temp balance. 0 prices by `expected_amount`
+    pub price_num: u64,
+    pub price_den: u64,
+    // Set by `InitEscrowOracle`, every exchange reprices the escrow off this feed,
+    // see `validation::oracle_price`
+    pub price_feed: Option<Pubkey>,
+    // The feed's price has to stay within these, in the feed's own units
+    pub min_price: u64,
+    pub max_price: u64,
+    // Set by `InitEscrowSplit`, the first is the maker's token account to receive,
+    // see `receive_splits()`
+    pub receive_splits_len: u8,
+    pub receive_splits: [MakerReceiveSplit; MAX_RECEIVE_SPLITS],
+    // Set by `InitEscrowScaled`, `expected_amount` counts `10^scale` base units.
+    // Handlers store it back in base units with the scale reset to 0
+    pub scale: u8,
+    // Set by the maker with `SetAuditClose`, the exchange emptying the escrow then
+    // records its taker and leaves it for `FinalizeClosed` instead of closing it
+    pub audit_close: bool,
+    // The taker of the exchange that emptied an `audit_close` escrow and when,
+    // see `is_completed()`
+    pub completed_by: Pubkey,
+    pub completed_at: i64,
+}
+
+impl Escrow {
+    /// Deserializes escrow state from raw account data, e.g. as fetched over RPC.
+    ///
+    /// The data is not required to be initialized, callers should check
+    /// `is_initialized` before trusting the other fields.
+    pub fn try_from_account_data(data: &[u8]) -> Result<Escrow, ProgramError> {
+        Escrow::unpack_unchecked(data)
+    }
+
+    /// Deserializes escrow state packed in one of `ESCROW_LEGACY_LENS`.
+    ///
+    /// Fields were only ever appended, the ones added since are left zeroed,
+    /// which is the default of each.
+    pub fn unpack_legacy(data: &[u8]) -> Result<Escrow, ProgramError> {
+        if !ESCROW_LEGACY_LENS.contains(&data.len()) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut current = [0; Escrow::LEN];
+        current[..data.len()].copy_from_slice(data);
+        Escrow::unpack(&current)
+    }
+
+    /// The receive mints of a multi-mint escrow, empty for a single-mint one
+    pub fn accepted_mints(&self) -> &[Pubkey] {
+        &self.accepted_mints[..self.accepted_mints_len as usize]
+    }
+
+    /// The accounts splitting the maker's proceeds, empty when the maker's token
+    /// account to receive takes them all
+    pub fn receive_splits(&self) -> &[MakerReceiveSplit] {
+        &self.receive_splits[..self.receive_splits_len as usize]
+    }
+
+    /// Seconds the escrow has been open at unix timestamp `now`, `None` if it
+    /// predates `created_at`
+    pub fn age(&self, now: i64) -> Option<i64> {
+        if self.created_at == 0 {
+            return None;
+        }
+        Some(now.saturating_sub(self.created_at))
+    }
+
+    /// Whether a cancel kept the escrow open without a temp account, ready for `Relist`
+    pub fn is_empty(&self) -> bool {
+        self.temp_token_account_pubkey == TempTokenAccount::default() && !self.is_completed()
+    }
+
+    /// Whether an exchange emptied this `audit_close` escrow, which then only
+    /// records `completed_by` and `completed_at` until `FinalizeClosed`
+    pub fn is_completed(&self) -> bool {
+        self.completed_by != Pubkey::default()
+    }
+
+    /// The `(price_num, price_den)` of an escrow priced by ratio, `None` for one
+    /// priced by `expected_amount`
+    pub fn price(&self) -> Option<(u64, u64)> {
+        if self.price_den == 0 {
+            return None;
+        }
+        Some((self.price_num, self.price_den))
+    }
+
+    /// Whether the escrow's deadline has passed at unix timestamp `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+
+    /// Whether an escrow without a deadline is `MAX_ESCROW_AGE_SECONDS` old at unix
+    /// timestamp `now`, never for one predating `created_at`
+    pub fn is_too_old(&self, now: i64) -> bool {
+        self.expires_at == 0
+            && self
+                .age(now)
+                .is_some_and(|age| age >= MAX_ESCROW_AGE_SECONDS)
+    }
 }
 
 impl Sealed for Escrow {}
@@ -25,8 +229,69 @@ impl IsInitialized for Escrow {
     }
 }
 
+fn unpack_bool(src: &[u8; 1]) -> Result<bool, ProgramError> {
+    match src {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Byte size of every packed field in layout order, keep in step with the
+/// `array_refs!` in `Pack`
+const FIELD_LENS: [usize; 32] = [
+    1,
+    32,
+    32,
+    32,
+    8,
+    1,
+    1,
+    1,
+    1,
+    1,
+    32 * MAX_ACCEPTED_MINTS,
+    8,
+    1,
+    1,
+    32,
+    8,
+    1,
+    8,
+    1,
+    32,
+    8,
+    8,
+    1,
+    32,
+    8,
+    8,
+    1,
+    34 * MAX_RECEIVE_SPLITS,
+    1,
+    1,
+    32,
+    8,
+];
+
+const fn sum(lens: &[usize]) -> usize {
+    let mut total = 0;
+    let mut i = 0;
+    while i < lens.len() {
+        total += lens[i];
+        i += 1;
+    }
+    total
+}
+
+// Adding a field without bumping `LEN` fails the build
+const _: () = assert!(
+    sum(&FIELD_LENS) == Escrow::LEN,
+    "Escrow::LEN is out of date"
+);
+
 impl Pack for Escrow {
-    const LEN: usize = 105;
+    const LEN: usize = 574;
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, solana_program::program_error::ProgramError> {
         let src = array_ref![src, 0, Escrow::LEN];
@@ -36,21 +301,139 @@ impl Pack for Escrow {
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
-        ) = array_refs![src, 1, 32, 32, 32, 8];
+            check_decimals,
+            temp_token_decimals,
+            token_to_receive_decimals,
+            frozen,
+            accepted_mints_len,
+            accepted_mints_src,
+            min_fill,
+            locked,
+            has_nonce,
+            nonce,
+            expires_at,
+            partially_filled,
+            created_at,
+            has_hook_program,
+            hook_program,
+            price_num,
+            price_den,
+            has_price_feed,
+            price_feed,
+            min_price,
+            max_price,
+            receive_splits_len,
+            receive_splits_src,
+            scale,
+            audit_close,
+            completed_by,
+            completed_at,
+        ) = array_refs![
+            src,
+            1,
+            32,
+            32,
+            32,
+            8,
+            1,
+            1,
+            1,
+            1,
+            1,
+            32 * MAX_ACCEPTED_MINTS,
+            8,
+            1,
+            1,
+            32,
+            8,
+            1,
+            8,
+            1,
+            32,
+            8,
+            8,
+            1,
+            32,
+            8,
+            8,
+            1,
+            34 * MAX_RECEIVE_SPLITS,
+            1,
+            1,
+            32,
+            8
+        ];
+
+        if accepted_mints_len[0] as usize > MAX_ACCEPTED_MINTS
+            || receive_splits_len[0] as usize > MAX_RECEIVE_SPLITS
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut accepted_mints = [Pubkey::default(); MAX_ACCEPTED_MINTS];
+        for (mint, src) in accepted_mints
+            .iter_mut()
+            .zip(accepted_mints_src.chunks_exact(32))
+        {
+            *mint = Pubkey::new(src);
+        }
+        let mut receive_splits = [MakerReceiveSplit::default(); MAX_RECEIVE_SPLITS];
+        for (split, src) in receive_splits
+            .iter_mut()
+            .zip(receive_splits_src.chunks_exact(34))
+        {
+            let (account, bps) = array_refs![array_ref![src, 0, 34], 32, 2];
+            *split = MakerReceiveSplit {
+                account: Pubkey::new_from_array(*account),
+                bps: u16::from_le_bytes(*bps),
+            };
+        }
 
-        let is_initialized = match is_initialized {
-            [0] => false,
-            [1] => true,
-            _ => return Err(ProgramError::InvalidAccountData),
-        };
         Ok(Escrow {
-            is_initialized,
+            is_initialized: unpack_bool(is_initialized)?,
             initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
-            temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
-            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(
-                *initializer_token_to_receive_account_pubkey,
+            temp_token_account_pubkey: TempTokenAccount(Pubkey::new_from_array(
+                *temp_token_account_pubkey,
+            )),
+            initializer_token_to_receive_account_pubkey: MakerReceiveAccount(
+                Pubkey::new_from_array(*initializer_token_to_receive_account_pubkey),
             ),
             expected_amount: u64::from_le_bytes(*expected_amount),
+            check_decimals: unpack_bool(check_decimals)?,
+            temp_token_decimals: temp_token_decimals[0],
+            token_to_receive_decimals: token_to_receive_decimals[0],
+            frozen: unpack_bool(frozen)?,
+            accepted_mints_len: accepted_mints_len[0],
+            accepted_mints,
+            min_fill: u64::from_le_bytes(*min_fill),
+            locked: unpack_bool(locked)?,
+            nonce: if unpack_bool(has_nonce)? {
+                Some(*nonce)
+            } else {
+                None
+            },
+            expires_at: i64::from_le_bytes(*expires_at),
+            partially_filled: unpack_bool(partially_filled)?,
+            created_at: i64::from_le_bytes(*created_at),
+            hook_program: if unpack_bool(has_hook_program)? {
+                Some(Pubkey::new_from_array(*hook_program))
+            } else {
+                None
+            },
+            price_num: u64::from_le_bytes(*price_num),
+            price_den: u64::from_le_bytes(*price_den),
+            price_feed: if unpack_bool(has_price_feed)? {
+                Some(Pubkey::new_from_array(*price_feed))
+            } else {
+                None
+            },
+            min_price: u64::from_le_bytes(*min_price),
+            max_price: u64::from_le_bytes(*max_price),
+            receive_splits_len: receive_splits_len[0],
+            receive_splits,
+            scale: scale[0],
+            audit_close: unpack_bool(audit_close)?,
+            completed_by: Pubkey::new_from_array(*completed_by),
+            completed_at: i64::from_le_bytes(*completed_at),
         })
     }
 
@@ -63,7 +446,68 @@ impl Pack for Escrow {
             temp_token_account_pubkey_dst,
             initializer_token_to_receive_account_pubkey_dst,
             expected_amount_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
+            check_decimals_dst,
+            temp_token_decimals_dst,
+            token_to_receive_decimals_dst,
+            frozen_dst,
+            accepted_mints_len_dst,
+            accepted_mints_dst,
+            min_fill_dst,
+            locked_dst,
+            has_nonce_dst,
+            nonce_dst,
+            expires_at_dst,
+            partially_filled_dst,
+            created_at_dst,
+            has_hook_program_dst,
+            hook_program_dst,
+            price_num_dst,
+            price_den_dst,
+            has_price_feed_dst,
+            price_feed_dst,
+            min_price_dst,
+            max_price_dst,
+            receive_splits_len_dst,
+            receive_splits_dst,
+            scale_dst,
+            audit_close_dst,
+            completed_by_dst,
+            completed_at_dst,
+        ) = mut_array_refs![
+            dst,
+            1,
+            32,
+            32,
+            32,
+            8,
+            1,
+            1,
+            1,
+            1,
+            1,
+            32 * MAX_ACCEPTED_MINTS,
+            8,
+            1,
+            1,
+            32,
+            8,
+            1,
+            8,
+            1,
+            32,
+            8,
+            8,
+            1,
+            32,
+            8,
+            8,
+            1,
+            34 * MAX_RECEIVE_SPLITS,
+            1,
+            1,
+            32,
+            8
+        ];
 
         let Escrow {
             is_initialized,
@@ -71,13 +515,383 @@ impl Pack for Escrow {
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
+            check_decimals,
+            temp_token_decimals,
+            token_to_receive_decimals,
+            frozen,
+            accepted_mints_len,
+            accepted_mints,
+            min_fill,
+            locked,
+            nonce,
+            expires_at,
+            partially_filled,
+            created_at,
+            hook_program,
+            price_num,
+            price_den,
+            price_feed,
+            min_price,
+            max_price,
+            receive_splits_len,
+            receive_splits,
+            scale,
+            audit_close,
+            completed_by,
+            completed_at,
         } = self;
 
         is_initialized_dst[0] = *is_initialized as u8;
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
-        temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
+        temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.0.as_ref());
         initializer_token_to_receive_account_pubkey_dst
-            .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
+            .copy_from_slice(initializer_token_to_receive_account_pubkey.0.as_ref());
         *expected_amount_dst = expected_amount.to_le_bytes();
+        check_decimals_dst[0] = *check_decimals as u8;
+        temp_token_decimals_dst[0] = *temp_token_decimals;
+        token_to_receive_decimals_dst[0] = *token_to_receive_decimals;
+        frozen_dst[0] = *frozen as u8;
+        accepted_mints_len_dst[0] = *accepted_mints_len;
+        for (dst, mint) in accepted_mints_dst.chunks_exact_mut(32).zip(accepted_mints) {
+            dst.copy_from_slice(mint.as_ref());
+        }
+        *min_fill_dst = min_fill.to_le_bytes();
+        locked_dst[0] = *locked as u8;
+        has_nonce_dst[0] = nonce.is_some() as u8;
+        *nonce_dst = nonce.unwrap_or_default();
+        *expires_at_dst = expires_at.to_le_bytes();
+        partially_filled_dst[0] = *partially_filled as u8;
+        *created_at_dst = created_at.to_le_bytes();
+        has_hook_program_dst[0] = hook_program.is_some() as u8;
+        hook_program_dst.copy_from_slice(hook_program.unwrap_or_default().as_ref());
+        *price_num_dst = price_num.to_le_bytes();
+        *price_den_dst = price_den.to_le_bytes();
+        has_price_feed_dst[0] = price_feed.is_some() as u8;
+        price_feed_dst.copy_from_slice(price_feed.unwrap_or_default().as_ref());
+        *min_price_dst = min_price.to_le_bytes();
+        *max_price_dst = max_price.to_le_bytes();
+        receive_splits_len_dst[0] = *receive_splits_len;
+        for (dst, split) in receive_splits_dst.chunks_exact_mut(34).zip(receive_splits) {
+            dst[..32].copy_from_slice(split.account.as_ref());
+            dst[32..].copy_from_slice(&split.bps.to_le_bytes());
+        }
+        scale_dst[0] = *scale;
+        audit_close_dst[0] = *audit_close as u8;
+        completed_by_dst.copy_from_slice(completed_by.as_ref());
+        *completed_at_dst = completed_at.to_le_bytes();
+    }
+}
+
+/// Program-wide settings, one per deployment at `find_config_address`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Config {
+    pub is_initialized: bool,
+    // The only signer `Admin` accepts once the config exists
+    pub admin: Pubkey,
+    // Blocks new escrows and exchanges, cancels still work so makers can exit
+    pub paused: bool,
+    // Share of an escrow's rent paid to `rent_fee_account` once an exchange completes
+    // it, in basis points of `MAX_BPS`. Set by the admin with `SetRentFee`
+    pub rent_fee_bps: u16,
+    pub rent_fee_account: Pubkey,
+}
+
+impl Sealed for Config {}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Config {
+    const LEN: usize = 68;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Config::LEN];
+        let (is_initialized, admin, paused, rent_fee_bps, rent_fee_account) =
+            array_refs![src, 1, 32, 1, 2, 32];
+        Ok(Config {
+            is_initialized: unpack_bool(is_initialized)?,
+            admin: Pubkey::new_from_array(*admin),
+            paused: unpack_bool(paused)?,
+            rent_fee_bps: u16::from_le_bytes(*rent_fee_bps),
+            rent_fee_account: Pubkey::new_from_array(*rent_fee_account),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Config::LEN];
+        let (is_initialized_dst, admin_dst, paused_dst, rent_fee_bps_dst, rent_fee_account_dst) =
+            mut_array_refs![dst, 1, 32, 1, 2, 32];
+        is_initialized_dst[0] = self.is_initialized as u8;
+        admin_dst.copy_from_slice(self.admin.as_ref());
+        paused_dst[0] = self.paused as u8;
+        *rent_fee_bps_dst = self.rent_fee_bps.to_le_bytes();
+        rent_fee_account_dst.copy_from_slice(self.rent_fee_account.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_try_from_account_data() {
+        let escrow = Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            temp_token_account_pubkey: TempTokenAccount(Pubkey::new_unique()),
+            initializer_token_to_receive_account_pubkey: MakerReceiveAccount(Pubkey::new_unique()),
+            expected_amount: 42,
+            check_decimals: true,
+            temp_token_decimals: 6,
+            token_to_receive_decimals: 9,
+            frozen: true,
+            accepted_mints_len: 2,
+            accepted_mints: [
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::default(),
+                Pubkey::default(),
+            ],
+            min_fill: 7,
+            locked: true,
+            nonce: Some([7; 32]),
+            expires_at: 1_700_000_000,
+            partially_filled: true,
+            created_at: 1_600_000_000,
+            hook_program: Some(Pubkey::new_unique()),
+            price_num: 3,
+            price_den: 2,
+            price_feed: Some(Pubkey::new_unique()),
+            min_price: 90,
+            max_price: 110,
+            receive_splits_len: 2,
+            receive_splits: [
+                MakerReceiveSplit {
+                    account: Pubkey::new_unique(),
+                    bps: 7_000,
+                },
+                MakerReceiveSplit {
+                    account: Pubkey::new_unique(),
+                    bps: 3_000,
+                },
+                MakerReceiveSplit::default(),
+                MakerReceiveSplit::default(),
+            ],
+            scale: 6,
+            audit_close: true,
+            completed_by: Pubkey::new_unique(),
+            completed_at: 1_650_000_000,
+        };
+        let mut data = vec![0; Escrow::LEN];
+        Escrow::pack(escrow.clone(), &mut data).unwrap();
+
+        let read = Escrow::try_from_account_data(&data).unwrap();
+        assert!(read.is_initialized());
+        assert_eq!(read.initializer_pubkey, escrow.initializer_pubkey);
+        assert_eq!(
+            read.temp_token_account_pubkey,
+            escrow.temp_token_account_pubkey
+        );
+        assert_eq!(
+            read.initializer_token_to_receive_account_pubkey,
+            escrow.initializer_token_to_receive_account_pubkey
+        );
+        assert_eq!(read.expected_amount, 42);
+        assert!(read.check_decimals);
+        assert_eq!(read.temp_token_decimals, 6);
+        assert_eq!(read.token_to_receive_decimals, 9);
+        assert!(read.frozen);
+        assert_eq!(read.accepted_mints(), &escrow.accepted_mints[..2]);
+        assert_eq!(read.min_fill, 7);
+        assert!(read.locked);
+        assert_eq!(read.nonce, Some([7; 32]));
+        assert_eq!(read.expires_at, 1_700_000_000);
+        assert!(read.partially_filled);
+        assert_eq!(read.created_at, 1_600_000_000);
+        assert_eq!(read.hook_program, escrow.hook_program);
+        assert_eq!(read.price(), Some((3, 2)));
+        assert_eq!(read.price_feed, escrow.price_feed);
+        assert_eq!((read.min_price, read.max_price), (90, 110));
+        assert_eq!(read.receive_splits(), &escrow.receive_splits[..2]);
+        assert_eq!(read.scale, 6);
+        assert!(read.audit_close);
+        assert!(read.is_completed());
+        assert_eq!(read.completed_by, escrow.completed_by);
+        assert_eq!(read.completed_at, 1_650_000_000);
+        assert_eq!(read, escrow);
+
+        let read = Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap();
+        assert!(!read.is_initialized());
+
+        assert_eq!(
+            Escrow::try_from_account_data(&data[1..]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_pack_fully_populated_len() {
+        let escrow = Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            temp_token_account_pubkey: TempTokenAccount(Pubkey::new_unique()),
+            initializer_token_to_receive_account_pubkey: MakerReceiveAccount(Pubkey::new_unique()),
+            expected_amount: u64::MAX,
+            check_decimals: true,
+            temp_token_decimals: u8::MAX,
+            token_to_receive_decimals: u8::MAX,
+            frozen: true,
+            accepted_mints_len: MAX_ACCEPTED_MINTS as u8,
+            accepted_mints: [Pubkey::new_unique(); MAX_ACCEPTED_MINTS],
+            min_fill: u64::MAX,
+            locked: true,
+            nonce: Some([u8::MAX; 32]),
+            expires_at: i64::MIN,
+            partially_filled: true,
+            created_at: i64::MIN,
+            hook_program: Some(Pubkey::new_unique()),
+            price_num: u64::MAX,
+            price_den: u64::MAX,
+            price_feed: Some(Pubkey::new_unique()),
+            min_price: u64::MAX,
+            max_price: u64::MAX,
+            receive_splits_len: MAX_RECEIVE_SPLITS as u8,
+            receive_splits: [MakerReceiveSplit {
+                account: Pubkey::new_unique(),
+                bps: u16::MAX,
+            }; MAX_RECEIVE_SPLITS],
+            scale: u8::MAX,
+            audit_close: true,
+            completed_by: Pubkey::new_unique(),
+            completed_at: i64::MIN,
+        };
+        assert_eq!(Escrow::get_packed_len(), sum(&FIELD_LENS));
+
+        let mut data = vec![0; Escrow::LEN];
+        Escrow::pack(escrow.clone(), &mut data).unwrap();
+        assert_eq!(Escrow::unpack(&data).unwrap(), escrow);
+
+        let mut data = vec![0; Escrow::LEN + 1];
+        assert_eq!(
+            Escrow::pack(escrow, &mut data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_pack_config() {
+        let config = Config {
+            is_initialized: true,
+            admin: Pubkey::new_unique(),
+            paused: true,
+            rent_fee_bps: 250,
+            rent_fee_account: Pubkey::new_unique(),
+        };
+        let mut data = vec![0; Config::LEN];
+        Config::pack(config, &mut data).unwrap();
+        assert_eq!(Config::unpack(&data), Ok(config));
+        assert_eq!(
+            Config::unpack_unchecked(&[0; Config::LEN]),
+            Ok(Config::default())
+        );
+
+        // the paused flag
+        data[33] = 2;
+        assert_eq!(Config::unpack(&data), Err(ProgramError::InvalidAccountData));
+    }
+
+    #[test]
+    fn test_available_amount() {
+        let account = Account {
+            mint: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            amount: 60,
+            state: spl_token::state::AccountState::Initialized,
+            ..Account::default()
+        };
+        let mut data = vec![0; Account::LEN];
+        Account::pack(account, &mut data).unwrap();
+        assert_eq!(available_amount(&data), Ok(60));
+
+        assert!(available_amount(&[0; Account::LEN]).is_err());
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let mut escrow = Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap();
+        assert!(!escrow.is_expired(i64::MAX));
+
+        escrow.expires_at = 1_000;
+        assert!(!escrow.is_expired(999));
+        assert!(escrow.is_expired(1_000));
+    }
+
+    #[test]
+    fn test_stored_account_matches() {
+        let temp = Pubkey::new_unique();
+        let receive = Pubkey::new_unique();
+        let escrow = Escrow {
+            temp_token_account_pubkey: TempTokenAccount(temp),
+            initializer_token_to_receive_account_pubkey: MakerReceiveAccount(receive),
+            ..Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap()
+        };
+        assert!(escrow.temp_token_account_pubkey.matches(&temp));
+        assert!(!escrow.temp_token_account_pubkey.matches(&receive));
+        assert!(escrow
+            .initializer_token_to_receive_account_pubkey
+            .matches(&receive));
+        assert!(!escrow
+            .initializer_token_to_receive_account_pubkey
+            .matches(&temp));
+
+        // the layout is still two plain keys
+        let mut data = vec![0; Escrow::LEN];
+        Escrow::pack(escrow, &mut data).unwrap();
+        assert_eq!(&data[33..65], temp.as_ref());
+        assert_eq!(&data[65..97], receive.as_ref());
+    }
+
+    #[test]
+    fn test_age() {
+        let mut escrow = Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap();
+        assert_eq!(escrow.age(1_000), None);
+
+        escrow.created_at = 1_000;
+        assert_eq!(escrow.age(1_600), Some(600));
+    }
+
+    #[test]
+    fn test_escrow_seed_signs_for_authority() {
+        let program_id = Pubkey::new_unique();
+        let (pda, bump_seed) = find_escrow_authority(&program_id);
+        // the seeds handlers pass to `invoke_signed`
+        assert_eq!(
+            Pubkey::create_program_address(&[ESCROW_SEED, &[bump_seed]], &program_id),
+            Ok(pda)
+        );
+    }
+
+    #[test]
+    fn test_unpack_legacy() {
+        let escrow = Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            expected_amount: 42,
+            ..Escrow::try_from_account_data(&[0; Escrow::LEN]).unwrap()
+        };
+        let mut data = vec![0; Escrow::LEN];
+        Escrow::pack(escrow.clone(), &mut data).unwrap();
+
+        for len in ESCROW_LEGACY_LENS {
+            assert_eq!(Escrow::unpack_legacy(&data[..len]), Ok(escrow.clone()));
+        }
+        assert_eq!(
+            Escrow::unpack_legacy(&data),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert!(ESCROW_LEGACY_LENS.iter().all(|len| *len < Escrow::LEN));
     }
 }
@@ -0,0 +1,275 @@
+//! The pure checks behind the processor, they take unpacked state rather than
+//! accounts so they run without a runtime. Public with the `test-internals`
+//! feature, the escrow authority comes from `state::find_escrow_authority`.
+
+use solana_program::{program_option::COption, pubkey::Pubkey};
+use spl_token::state::Account;
+use std::convert::{TryFrom, TryInto};
+
+use crate::{
+    config::{MAX_BPS, MAX_ORACLE_AGE_SLOTS, MAX_PERMILLE},
+    error::{EscrowError, EscrowResult},
+    state::{Escrow, MakerReceiveSplit},
+};
+
+// The Pyth v2 price account fields `oracle_price` reads, by offset
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+const PYTH_VERSION: u32 = 2;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+const PYTH_TRADING: u32 = 1;
+const PYTH_EXPO_OFFSET: usize = 20;
+const PYTH_AGG_PRICE_OFFSET: usize = 208;
+const PYTH_AGG_STATUS_OFFSET: usize = 224;
+const PYTH_AGG_PUB_SLOT_OFFSET: usize = 232;
+
+/// Checks the escrow's temp token account against the account receiving its
+/// tokens, failing with `mint_mismatch` on another mint
+///
+/// Moving and closing the temp account both need the `pda`'s signature alone, fail
+/// clearly rather than in the token program.
+pub fn check_temp_token(
+    temp_token: &Account,
+    receiver_mint: &Pubkey,
+    mint_mismatch: EscrowError,
+    pda: &Pubkey,
+) -> EscrowResult<()> {
+    if *receiver_mint != temp_token.mint {
+        return Err(mint_mismatch.into());
+    }
+    if temp_token.owner != *pda
+        || matches!(temp_token.close_authority, COption::Some(authority) if authority != *pda)
+    {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+    Ok(())
+}
+
+/// Checks the mint a taker pays in against the maker's receive account and, in
+/// strict mode, the escrow's accepted mints
+pub fn check_maker_mint(
+    escrow: &Escrow,
+    sent_mint: &Pubkey,
+    maker_receive: &Account,
+) -> EscrowResult<()> {
+    if *sent_mint != maker_receive.mint {
+        return Err(EscrowError::MakerMintMismatch.into());
+    }
+
+    let accepted_mints = escrow.accepted_mints();
+    if !accepted_mints.is_empty() {
+        if maker_receive.owner != escrow.initializer_pubkey {
+            return Err(EscrowError::InvalidAccountData.into());
+        }
+        if !accepted_mints.contains(sent_mint) {
+            return Err(EscrowError::MakerMintMismatch.into());
+        }
+    }
+    Ok(())
+}
+
+/// What the maker gets for `fill` out of `temp_amount` temp tokens, rounded up so
+/// partial fills never shave the maker's price
+pub fn maker_share(expected_amount: u64, fill: u64, temp_amount: u64) -> u64 {
+    let share = (expected_amount as u128 * fill as u128).div_ceil(temp_amount as u128);
+    // fill <= temp_amount keeps the share within expected_amount
+    share as u64
+}
+
+/// What the maker gets for `temp_tokens` at `price_num / price_den` each, rounded
+/// up like `maker_share`
+///
+/// The product can't overflow 128 bits, a share past `u64` fails.
+pub fn ratio_share(price_num: u64, price_den: u64, temp_tokens: u64) -> EscrowResult<u64> {
+    if price_den == 0 {
+        return Err(EscrowError::ExpectedAmountMismatch.into());
+    }
+    let share = (temp_tokens as u128 * price_num as u128).div_ceil(price_den as u128);
+    u64::try_from(share).map_err(|_| EscrowError::AmountOverflow.into())
+}
+
+/// How many of `temp_amount` temp tokens a taker asking for `amount` gets
+fn fill_of(temp_amount: u64, amount: u64) -> EscrowResult<u64> {
+    if amount == 0 {
+        return Err(EscrowError::ExpectedAmountMismatch.into());
+    }
+    let fill = amount.min(temp_amount);
+    if fill == 0 {
+        return Err(EscrowError::StaleAmount.into());
+    }
+    Ok(fill)
+}
+
+/// Fails with `AmountTooLarge` on any of `amounts` over `max`, the processor passes
+/// `MAX_ESCROW_AMOUNT`
+pub fn check_amounts_within(amounts: &[u64], max: u64) -> EscrowResult<()> {
+    if amounts.iter().any(|amount| *amount > max) {
+        return Err(EscrowError::AmountTooLarge.into());
+    }
+    Ok(())
+}
+
+/// What a taker asking for `amount` gets out of `temp_amount` temp tokens, and what
+/// they pay the maker for it
+///
+/// The taker gets at most what's left and pays the maker pro rata.
+pub fn fill_amounts(
+    expected_amount: u64,
+    temp_amount: u64,
+    amount: u64,
+) -> EscrowResult<(u64, u64)> {
+    let fill = fill_of(temp_amount, amount)?;
+    Ok((fill, maker_share(expected_amount, fill, temp_amount)))
+}
+
+/// `fill_amounts` of an escrow priced by ratio, see `Escrow::price`
+pub fn ratio_fill_amounts(
+    (price_num, price_den): (u64, u64),
+    temp_amount: u64,
+    amount: u64,
+) -> EscrowResult<(u64, u64)> {
+    let fill = fill_of(temp_amount, amount)?;
+    Ok((fill, ratio_share(price_num, price_den, fill)?))
+}
+
+/// `permille` thousandths of `temp_amount` temp tokens, rounded down, the amount a
+/// `PartialExchangePermille` asks for
+pub fn permille_share(temp_amount: u64, permille: u16) -> EscrowResult<u64> {
+    if permille > MAX_PERMILLE {
+        return Err(EscrowError::AmountOverflow.into());
+    }
+    let share = (temp_amount as u128)
+        .checked_mul(permille as u128)
+        .ok_or(EscrowError::AmountOverflow)?
+        / MAX_PERMILLE as u128;
+    u64::try_from(share).map_err(|_| EscrowError::AmountOverflow.into())
+}
+
+/// The expected amount once `amount` temp tokens join `temp_amount`, at the same
+/// price rounded up
+pub fn topped_up_expected(
+    expected_amount: u64,
+    temp_amount: u64,
+    amount: u64,
+) -> EscrowResult<u64> {
+    // Nothing to scale the price from
+    if amount == 0 || temp_amount == 0 {
+        return Err(EscrowError::ExpectedAmountMismatch.into());
+    }
+    temp_amount
+        .checked_add(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    let added_share = (expected_amount as u128 * amount as u128).div_ceil(temp_amount as u128);
+    let expected_amount = u64::try_from(added_share)
+        .ok()
+        .and_then(|share| expected_amount.checked_add(share))
+        .ok_or(EscrowError::AmountOverflow)?;
+    Ok(expected_amount)
+}
+
+/// Splits the lamports of a closing escrow into the maker's and the rent fee
+/// account's, the fee rounded down
+pub fn rent_split(drained: u64, rent_fee_bps: u16) -> EscrowResult<(u64, u64)> {
+    let fee = drained as u128 * rent_fee_bps as u128 / MAX_BPS as u128;
+    let fee = u64::try_from(fee).map_err(|_| EscrowError::AmountOverflow)?;
+    // A stored rate over `MAX_BPS` would take more than there is
+    let maker = drained
+        .checked_sub(fee)
+        .ok_or(EscrowError::AmountOverflow)?;
+    if maker.checked_add(fee) != Some(drained) {
+        return Err(EscrowError::AmountOverflow.into());
+    }
+    Ok((maker, fee))
+}
+
+/// What each of `splits` gets of a payment of `amount`, its basis points rounded
+/// down and the remainder to the first. Without splits the one receive account
+/// gets it all
+pub fn split_payouts(amount: u64, splits: &[MakerReceiveSplit]) -> EscrowResult<Vec<u64>> {
+    if splits.is_empty() {
+        return Ok(vec![amount]);
+    }
+    let mut payouts = splits
+        .iter()
+        .map(|split| {
+            let share = amount as u128 * split.bps as u128 / MAX_BPS as u128;
+            u64::try_from(share).map_err(|_| EscrowError::AmountOverflow.into())
+        })
+        .collect::<EscrowResult<Vec<_>>>()?;
+    // Stored shares over `MAX_BPS` in total would pay out more than there is
+    let paid = payouts
+        .iter()
+        .try_fold(0u64, |paid, payout| paid.checked_add(*payout))
+        .ok_or(EscrowError::AmountOverflow)?;
+    let remainder = amount
+        .checked_sub(paid)
+        .ok_or(EscrowError::AmountOverflow)?;
+    payouts[0] += remainder;
+    Ok(payouts)
+}
+
+/// `value * 10^scale`, in 128 bits so only a result over `u64::MAX` fails with
+/// `AmountOverflow`
+pub fn scaled_amount(value: u64, scale: u8) -> EscrowResult<u64> {
+    10u128
+        .checked_pow(scale as u32)
+        .and_then(|factor| (value as u128).checked_mul(factor))
+        .and_then(|amount| u64::try_from(amount).ok())
+        .ok_or_else(|| EscrowError::AmountOverflow.into())
+}
+
+/// The `(price_num, price_den)` a Pyth price account quotes at `slot`, for
+/// `ratio_share`. The feed prices a temp token in the receive token's base units.
+///
+/// Fails with `StaleOracle` unless the price is trading and published within
+/// `MAX_ORACLE_AGE_SLOTS`, and with `OraclePriceOutOfBounds` outside
+/// `min_price..=max_price`, both in the feed's units.
+pub fn oracle_price(
+    data: &[u8],
+    slot: u64,
+    min_price: u64,
+    max_price: u64,
+) -> EscrowResult<(u64, u64)> {
+    let field = |offset: usize| -> EscrowResult<[u8; 4]> {
+        data.get(offset..offset + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| EscrowError::InvalidAccountData.into())
+    };
+    let wide_field = |offset: usize| -> EscrowResult<[u8; 8]> {
+        data.get(offset..offset + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| EscrowError::InvalidAccountData.into())
+    };
+    if u32::from_le_bytes(field(0)?) != PYTH_MAGIC
+        || u32::from_le_bytes(field(4)?) != PYTH_VERSION
+        || u32::from_le_bytes(field(8)?) != PYTH_PRICE_ACCOUNT
+    {
+        return Err(EscrowError::InvalidAccountData.into());
+    }
+
+    let published = u64::from_le_bytes(wide_field(PYTH_AGG_PUB_SLOT_OFFSET)?);
+    if u32::from_le_bytes(field(PYTH_AGG_STATUS_OFFSET)?) != PYTH_TRADING
+        || slot.saturating_sub(published) > MAX_ORACLE_AGE_SLOTS
+    {
+        return Err(EscrowError::StaleOracle.into());
+    }
+
+    let price = i64::from_le_bytes(wide_field(PYTH_AGG_PRICE_OFFSET)?);
+    let price = u64::try_from(price).map_err(|_| EscrowError::OraclePriceOutOfBounds)?;
+    if price < min_price || price > max_price {
+        return Err(EscrowError::OraclePriceOutOfBounds.into());
+    }
+
+    // `price * 10^expo` receive tokens per temp token
+    let expo = i32::from_le_bytes(field(PYTH_EXPO_OFFSET)?);
+    let scale = 10u64
+        .checked_pow(expo.unsigned_abs())
+        .ok_or(EscrowError::AmountOverflow)?;
+    if expo < 0 {
+        Ok((price, scale))
+    } else {
+        let price = price
+            .checked_mul(scale)
+            .ok_or(EscrowError::AmountOverflow)?;
+        Ok((price, 1))
+    }
+}
@@ -0,0 +1,392 @@
+//! The program under the `ProgramTest` runtime. Natively it runs without compute
+//! metering, the compute unit checks need the BPF build: run them with
+//! `cargo test-bpf`, which turns on `test-bpf`.
+#![cfg(not(feature = "no-entrypoint"))]
+
+use {
+    assert_matches::*,
+    solana_escrow::{
+        error::EscrowError,
+        instruction::{build_init_escrow_transaction, InitEscrowTransaction},
+        processor::Processor,
+        state::{find_config_address, find_escrow_address, find_escrow_authority, Escrow},
+    },
+    solana_program::{
+        instruction::{AccountMeta, Instruction, InstructionError},
+        program_pack::Pack,
+        pubkey::Pubkey,
+        rent::Rent,
+        system_program, sysvar,
+    },
+    solana_program_test::*,
+    solana_sdk::{
+        account::Account,
+        signature::{Keypair, Signer},
+        transaction::{Transaction, TransactionError},
+    },
+    spl_token::state::{Account as TokenAccount, AccountState, Mint},
+};
+
+fn packed<T: Pack>(state: T, owner: &Pubkey) -> Account {
+    let mut data = vec![0; T::LEN];
+    T::pack(state, &mut data).unwrap();
+    Account {
+        lamports: Rent::default().minimum_balance(T::LEN),
+        data,
+        owner: *owner,
+        ..Account::default()
+    }
+}
+
+fn add_wallet(program_test: &mut ProgramTest) -> Keypair {
+    let wallet = Keypair::new();
+    program_test.add_account(
+        wallet.pubkey(),
+        Account::new(1_000_000_000, 0, &system_program::id()),
+    );
+    wallet
+}
+
+fn add_mint(program_test: &mut ProgramTest, decimals: u8) -> Pubkey {
+    let key = Pubkey::new_unique();
+    let mint = Mint {
+        decimals,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    program_test.add_account(key, packed(mint, &spl_token::id()));
+    key
+}
+
+fn add_token(program_test: &mut ProgramTest, mint: Pubkey, owner: &Keypair, amount: u64) -> Pubkey {
+    let key = Pubkey::new_unique();
+    let account = TokenAccount {
+        mint,
+        owner: owner.pubkey(),
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    program_test.add_account(key, packed(account, &spl_token::id()));
+    key
+}
+
+/// An escrow account allocated for the program, as the maker creates it ahead of
+/// a plain `InitEscrow`
+fn add_escrow(program_test: &mut ProgramTest, key: Pubkey, program_id: &Pubkey) {
+    program_test.add_account(
+        key,
+        Account::new(
+            Rent::default().minimum_balance(Escrow::LEN),
+            Escrow::LEN,
+            program_id,
+        ),
+    );
+}
+
+/// A plain `InitEscrow` asking 500 for the temp account's tokens
+fn init_instruction(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    temp: &Pubkey,
+    maker_receive: &Pubkey,
+    escrow: &Pubkey,
+) -> Instruction {
+    let mut data = vec![0];
+    data.extend_from_slice(&500u64.to_le_bytes());
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(*temp, false),
+            AccountMeta::new_readonly(*maker_receive, false),
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+        data,
+    }
+}
+
+fn program_test(program_id: Pubkey) -> ProgramTest {
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(Processor::process));
+    program_test.add_program(
+        "spl_token",
+        spl_token::id(),
+        processor!(spl_token::processor::Processor::process),
+    );
+    program_test
+}
+
+#[tokio::test]
+async fn test_unparsable_instruction() {
+    let program_id = Pubkey::new_unique();
+
+    let (mut banks_client, payer, recent_blockhash) =
+        ProgramTest::new("solana_escrow", program_id, processor!(Processor::process))
+            .start()
+            .await;
+
+    // an `Exchange` cut short of its amount
+    let mut transaction = Transaction::new_with_payer(
+        &[Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new(payer.pubkey(), false)],
+            data: vec![1, 2, 3],
+        }],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+
+    assert_matches!(
+        banks_client.process_transaction(transaction).await,
+        Err(error) if error.unwrap() == TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(EscrowError::InvalidInstruction as u32),
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_built_init_escrow_transaction() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = program_test(program_id);
+    let maker = add_wallet(&mut program_test);
+    let (deposit_mint, receive_mint) = (
+        add_mint(&mut program_test, 6),
+        add_mint(&mut program_test, 9),
+    );
+    let maker_source = add_token(&mut program_test, deposit_mint, &maker, 100);
+    let maker_receive = add_token(&mut program_test, receive_mint, &maker, 0);
+    // The native runtime can't grow an account inside a CPI, so the program finds
+    // its escrow already allocated at the nonce's address, as a retried init would
+    let nonce = [7; 32];
+    let (escrow, _bump_seed) = find_escrow_address(&program_id, &maker.pubkey(), &nonce);
+    add_escrow(&mut program_test, escrow, &program_id);
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let temp = Keypair::new();
+    let (instructions, built_escrow) = build_init_escrow_transaction(
+        &program_id,
+        &InitEscrowTransaction {
+            maker: maker.pubkey(),
+            temp_token_account: temp.pubkey(),
+            maker_source,
+            mint: deposit_mint,
+            maker_receive,
+            deposit: 100,
+            expected_amount: 500,
+            nonce,
+        },
+    )
+    .unwrap();
+    assert_eq!(built_escrow, escrow);
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &maker, &temp], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let escrow = banks_client.get_account(escrow).await.unwrap().unwrap();
+    let escrow = Escrow::unpack(&escrow.data).unwrap();
+    assert_eq!(escrow.temp_token_account_pubkey.0, temp.pubkey());
+    assert_eq!(escrow.expected_amount, 500);
+    let temp = banks_client
+        .get_account(temp.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let temp = TokenAccount::unpack(&temp.data).unwrap();
+    assert_eq!(temp.amount, 100);
+    assert_eq!(temp.owner, find_escrow_authority(&program_id).0);
+}
+
+#[tokio::test]
+async fn test_init_hands_temp_to_pda() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = program_test(program_id);
+    let maker = add_wallet(&mut program_test);
+    let (temp_mint, receive_mint) = (
+        add_mint(&mut program_test, 6),
+        add_mint(&mut program_test, 9),
+    );
+    let temp = add_token(&mut program_test, temp_mint, &maker, 100);
+    let maker_receive = add_token(&mut program_test, receive_mint, &maker, 0);
+    let escrow = Pubkey::new_unique();
+    add_escrow(&mut program_test, escrow, &program_id);
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[init_instruction(
+            &program_id,
+            &maker.pubkey(),
+            &temp,
+            &maker_receive,
+            &escrow,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &maker], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let temp_account = banks_client.get_account(temp).await.unwrap().unwrap();
+    assert_eq!(temp_account.owner, spl_token::id());
+    let temp_account = TokenAccount::unpack(&temp_account.data).unwrap();
+    assert_eq!(temp_account.owner, find_escrow_authority(&program_id).0);
+    assert_eq!(temp_account.amount, 100);
+
+    let escrow = banks_client.get_account(escrow).await.unwrap().unwrap();
+    let escrow = Escrow::unpack(&escrow.data).unwrap();
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, maker.pubkey());
+    assert!(escrow.temp_token_account_pubkey.matches(&temp));
+    assert!(escrow
+        .initializer_token_to_receive_account_pubkey
+        .matches(&maker_receive));
+    assert_eq!(escrow.expected_amount, 500);
+}
+
+#[cfg(feature = "test-bpf")]
+mod compute_units {
+    use {
+        super::*,
+        solana_escrow::config::{CANCEL_CU, EXCHANGE_CU, INIT_CU},
+    };
+
+    /// How far an instruction may go over its documented compute units
+    const CU_MARGIN_PERCENT: u64 = 10;
+
+    /// An escrow of 100 temp tokens for 500 and the taker filling it
+    struct Setup {
+        program_id: Pubkey,
+        maker: Keypair,
+        taker: Keypair,
+        temp: Pubkey,
+        maker_receive: Pubkey,
+        maker_refund: Pubkey,
+        taker_sent: Pubkey,
+        taker_receive: Pubkey,
+        escrow: Pubkey,
+    }
+
+    impl Setup {
+        fn new(program_test: &mut ProgramTest, program_id: Pubkey) -> Self {
+            let maker = add_wallet(program_test);
+            let taker = add_wallet(program_test);
+            let (temp_mint, receive_mint) = (add_mint(program_test, 6), add_mint(program_test, 9));
+            let temp = add_token(program_test, temp_mint, &maker, 100);
+            let maker_receive = add_token(program_test, receive_mint, &maker, 0);
+            let maker_refund = add_token(program_test, temp_mint, &maker, 0);
+            let taker_sent = add_token(program_test, receive_mint, &taker, 1_000);
+            let taker_receive = add_token(program_test, temp_mint, &taker, 0);
+            let escrow = Pubkey::new_unique();
+            add_escrow(program_test, escrow, &program_id);
+
+            Self {
+                program_id,
+                maker,
+                taker,
+                temp,
+                maker_receive,
+                maker_refund,
+                taker_sent,
+                taker_receive,
+                escrow,
+            }
+        }
+
+        fn init(&self) -> Instruction {
+            init_instruction(
+                &self.program_id,
+                &self.maker.pubkey(),
+                &self.temp,
+                &self.maker_receive,
+                &self.escrow,
+            )
+        }
+
+        fn exchange(&self) -> Instruction {
+            let mut data = vec![1];
+            data.extend_from_slice(&100u64.to_le_bytes());
+            let (pda, _bump_seed) = find_escrow_authority(&self.program_id);
+            Instruction {
+                program_id: self.program_id,
+                accounts: vec![
+                    AccountMeta::new(self.taker.pubkey(), true),
+                    AccountMeta::new(self.taker_sent, false),
+                    AccountMeta::new(self.taker_receive, false),
+                    AccountMeta::new(self.temp, false),
+                    AccountMeta::new(self.maker.pubkey(), false),
+                    AccountMeta::new(self.maker_receive, false),
+                    AccountMeta::new(self.escrow, false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                    AccountMeta::new_readonly(pda, false),
+                    AccountMeta::new_readonly(find_config_address(&self.program_id).0, false),
+                ],
+                data,
+            }
+        }
+
+        fn cancel(&self) -> Instruction {
+            let (pda, _bump_seed) = find_escrow_authority(&self.program_id);
+            Instruction {
+                program_id: self.program_id,
+                accounts: vec![
+                    AccountMeta::new(self.maker.pubkey(), true),
+                    AccountMeta::new(self.maker_refund, false),
+                    AccountMeta::new(self.temp, false),
+                    AccountMeta::new(self.escrow, false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                    AccountMeta::new_readonly(pda, false),
+                ],
+                data: vec![2],
+            }
+        }
+    }
+
+    /// Runs `steps` against a fresh `Setup`, each in its own transaction, with no
+    /// more than `max_units` compute units plus the margin
+    async fn run_within(
+        max_units: u32,
+        steps: impl FnOnce(&Setup) -> Vec<(Instruction, &Keypair)>,
+    ) {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = program_test(program_id);
+        program_test.set_compute_max_units(max_units as u64 * (100 + CU_MARGIN_PERCENT) / 100);
+        let setup = Setup::new(&mut program_test, program_id);
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+        for (instruction, signer) in steps(&setup) {
+            let mut transaction =
+                Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+            transaction.sign(&[&payer, signer], recent_blockhash);
+            assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+        }
+    }
+
+    // Init fits in the other limits, so it runs ahead of each
+    #[tokio::test]
+    async fn test_init_within_compute_units() {
+        run_within(INIT_CU, |setup| vec![(setup.init(), &setup.maker)]).await;
+    }
+
+    #[tokio::test]
+    async fn test_exchange_within_compute_units() {
+        run_within(EXCHANGE_CU, |setup| {
+            vec![
+                (setup.init(), &setup.maker),
+                (setup.exchange(), &setup.taker),
+            ]
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_cancel_within_compute_units() {
+        run_within(CANCEL_CU, |setup| {
+            vec![(setup.init(), &setup.maker), (setup.cancel(), &setup.maker)]
+        })
+        .await;
+    }
+}
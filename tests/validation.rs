@@ -0,0 +1,274 @@
+//! Run with `cargo test --features test-internals`
+#![cfg(feature = "test-internals")]
+
+use solana_escrow::{
+    config::{MAX_BPS, MAX_ORACLE_AGE_SLOTS, MAX_PERMILLE},
+    error::EscrowError,
+    state::{find_escrow_authority, Escrow, MakerReceiveSplit},
+    validation::{
+        check_amounts_within, check_maker_mint, check_temp_token, fill_amounts, oracle_price,
+        permille_share, ratio_fill_amounts, ratio_share, rent_split, scaled_amount, split_payouts,
+        topped_up_expected,
+    },
+};
+use solana_program::{program_option::COption, program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::{Account, AccountState};
+
+fn token(mint: Pubkey, owner: Pubkey) -> Account {
+    Account {
+        mint,
+        owner,
+        state: AccountState::Initialized,
+        ..Account::default()
+    }
+}
+
+#[test]
+fn test_check_temp_token() {
+    let (pda, _bump_seed) = find_escrow_authority(&Pubkey::new_unique());
+    let mint = Pubkey::new_unique();
+    let mut temp = token(mint, pda);
+
+    assert_eq!(
+        check_temp_token(&temp, &mint, EscrowError::TakerMintMismatch, &pda),
+        Ok(())
+    );
+    assert_eq!(
+        check_temp_token(
+            &temp,
+            &Pubkey::new_unique(),
+            EscrowError::TakerMintMismatch,
+            &pda
+        ),
+        Err(EscrowError::TakerMintMismatch.into())
+    );
+
+    temp.close_authority = COption::Some(Pubkey::new_unique());
+    assert_eq!(
+        check_temp_token(&temp, &mint, EscrowError::TakerMintMismatch, &pda),
+        Err(EscrowError::InvalidAccountData.into())
+    );
+    temp.close_authority = COption::Some(pda);
+    temp.owner = Pubkey::new_unique();
+    assert_eq!(
+        check_temp_token(&temp, &mint, EscrowError::TakerMintMismatch, &pda),
+        Err(EscrowError::InvalidAccountData.into())
+    );
+}
+
+#[test]
+fn test_check_maker_mint() {
+    let maker = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let mut escrow = Escrow {
+        is_initialized: true,
+        initializer_pubkey: maker,
+        ..Escrow::unpack_unchecked(&[0; Escrow::LEN]).unwrap()
+    };
+    let receive = token(mint, maker);
+
+    assert_eq!(check_maker_mint(&escrow, &mint, &receive), Ok(()));
+    assert_eq!(
+        check_maker_mint(&escrow, &Pubkey::new_unique(), &receive),
+        Err(EscrowError::MakerMintMismatch.into())
+    );
+
+    // strict mode only takes the accepted mints, paid to the maker
+    escrow.accepted_mints_len = 1;
+    escrow.accepted_mints[0] = Pubkey::new_unique();
+    assert_eq!(
+        check_maker_mint(&escrow, &mint, &receive),
+        Err(EscrowError::MakerMintMismatch.into())
+    );
+    escrow.accepted_mints[0] = mint;
+    assert_eq!(check_maker_mint(&escrow, &mint, &receive), Ok(()));
+    assert_eq!(
+        check_maker_mint(&escrow, &mint, &token(mint, Pubkey::new_unique())),
+        Err(EscrowError::InvalidAccountData.into())
+    );
+}
+
+#[test]
+fn test_amount_checks() {
+    assert_eq!(fill_amounts(500, 100, 40), Ok((40, 200)));
+    assert_eq!(fill_amounts(500, 100, 1_000), Ok((100, 500)));
+    assert_eq!(
+        fill_amounts(500, 100, 0),
+        Err(EscrowError::ExpectedAmountMismatch.into())
+    );
+    assert_eq!(
+        fill_amounts(500, 0, 40),
+        Err(EscrowError::StaleAmount.into())
+    );
+
+    assert_eq!(topped_up_expected(500, 100, 50), Ok(750));
+    assert_eq!(
+        topped_up_expected(500, u64::MAX, 1),
+        Err(EscrowError::AmountOverflow.into())
+    );
+
+    assert_eq!(rent_split(10_000, 2_500), Ok((7_500, 2_500)));
+    assert_eq!(
+        rent_split(10_000, MAX_BPS + 1),
+        Err(EscrowError::AmountOverflow.into())
+    );
+}
+
+#[test]
+fn test_check_amounts_within() {
+    assert_eq!(check_amounts_within(&[1_000, 0], 1_000), Ok(()));
+    assert_eq!(
+        check_amounts_within(&[1_000, 1_001], 1_000),
+        Err(EscrowError::AmountTooLarge.into())
+    );
+    assert_eq!(check_amounts_within(&[u64::MAX], u64::MAX), Ok(()));
+}
+
+#[test]
+fn test_scaled_amount() {
+    assert_eq!(scaled_amount(15, 0), Ok(15));
+    assert_eq!(scaled_amount(15, 6), Ok(15_000_000));
+    assert_eq!(scaled_amount(1, 19), Ok(10_000_000_000_000_000_000));
+    assert_eq!(
+        scaled_amount(u64::MAX, 1),
+        Err(EscrowError::AmountOverflow.into())
+    );
+    // 10^39 is past even the 128-bit intermediate
+    assert_eq!(
+        scaled_amount(1, 39),
+        Err(EscrowError::AmountOverflow.into())
+    );
+}
+
+#[test]
+fn test_split_payouts() {
+    let split = |bps| MakerReceiveSplit {
+        account: Pubkey::new_unique(),
+        bps,
+    };
+    assert_eq!(split_payouts(500, &[]), Ok(vec![500]));
+    assert_eq!(
+        split_payouts(500, &[split(7_000), split(3_000)]),
+        Ok(vec![350, 150])
+    );
+    // the first takes what rounding leaves over
+    assert_eq!(
+        split_payouts(10, &[split(3_334), split(3_333), split(3_333)]),
+        Ok(vec![4, 3, 3])
+    );
+    assert_eq!(
+        split_payouts(u64::MAX, &[split(5_000), split(5_000)]),
+        Ok(vec![u64::MAX / 2 + 1, u64::MAX / 2])
+    );
+    assert_eq!(
+        split_payouts(10, &[split(MAX_BPS), split(MAX_BPS)]),
+        Err(EscrowError::AmountOverflow.into())
+    );
+}
+
+#[test]
+fn test_ratio_checks() {
+    assert_eq!(ratio_share(5, 3, 10), Ok(17));
+    assert_eq!(ratio_share(5, 3, 0), Ok(0));
+    // the product needs the 128-bit intermediate, the share still fits
+    assert_eq!(ratio_share(u64::MAX, u64::MAX, u64::MAX), Ok(u64::MAX));
+    assert_eq!(
+        ratio_share(u64::MAX, 1, 2),
+        Err(EscrowError::AmountOverflow.into())
+    );
+    assert_eq!(
+        ratio_share(5, 0, 10),
+        Err(EscrowError::ExpectedAmountMismatch.into())
+    );
+
+    assert_eq!(ratio_fill_amounts((5, 3), 100, 1_000), Ok((100, 167)));
+    assert_eq!(
+        ratio_fill_amounts((5, 3), 0, 10),
+        Err(EscrowError::StaleAmount.into())
+    );
+}
+
+#[test]
+fn test_permille_share() {
+    assert_eq!(permille_share(100, 500), Ok(50));
+    assert_eq!(permille_share(100, MAX_PERMILLE), Ok(100));
+    assert_eq!(permille_share(50, 333), Ok(16));
+    assert_eq!(permille_share(999, 1), Ok(0));
+    assert_eq!(permille_share(u64::MAX, MAX_PERMILLE), Ok(u64::MAX));
+    assert_eq!(
+        permille_share(100, MAX_PERMILLE + 1),
+        Err(EscrowError::AmountOverflow.into())
+    );
+}
+
+/// A Pyth price account at `price * 10^expo` with the aggregate's `status`,
+/// last published at `slot`
+fn pyth_feed(price: i64, expo: i32, status: u32, slot: u64) -> Vec<u8> {
+    let mut data = vec![0; 240];
+    data[..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    data[4..8].copy_from_slice(&2u32.to_le_bytes());
+    data[8..12].copy_from_slice(&3u32.to_le_bytes());
+    data[20..24].copy_from_slice(&expo.to_le_bytes());
+    data[208..216].copy_from_slice(&price.to_le_bytes());
+    data[224..228].copy_from_slice(&status.to_le_bytes());
+    data[232..240].copy_from_slice(&slot.to_le_bytes());
+    data
+}
+
+#[test]
+fn test_oracle_price() {
+    assert_eq!(
+        oracle_price(&pyth_feed(15, -1, 1, 100), 100, 10, 20),
+        Ok((15, 10))
+    );
+    assert_eq!(
+        oracle_price(&pyth_feed(15, 2, 1, 100), 100, 10, 20),
+        Ok((1_500, 1))
+    );
+    assert_eq!(
+        oracle_price(
+            &pyth_feed(15, -1, 1, 100),
+            100 + MAX_ORACLE_AGE_SLOTS,
+            10,
+            20
+        ),
+        Ok((15, 10))
+    );
+
+    // stale, by age or by status
+    assert_eq!(
+        oracle_price(
+            &pyth_feed(15, -1, 1, 100),
+            101 + MAX_ORACLE_AGE_SLOTS,
+            10,
+            20
+        ),
+        Err(EscrowError::StaleOracle.into())
+    );
+    assert_eq!(
+        oracle_price(&pyth_feed(15, -1, 2, 100), 100, 10, 20),
+        Err(EscrowError::StaleOracle.into())
+    );
+
+    for price in [9, 21, -15] {
+        assert_eq!(
+            oracle_price(&pyth_feed(price, -1, 1, 100), 100, 10, 20),
+            Err(EscrowError::OraclePriceOutOfBounds.into())
+        );
+    }
+    assert_eq!(
+        oracle_price(&pyth_feed(15, 19, 1, 100), 100, 10, 20),
+        Err(EscrowError::AmountOverflow.into())
+    );
+
+    let mut not_pyth = pyth_feed(15, -1, 1, 100);
+    not_pyth[0] = 0;
+    assert_eq!(
+        oracle_price(&not_pyth, 100, 10, 20),
+        Err(EscrowError::InvalidAccountData.into())
+    );
+    assert_eq!(
+        oracle_price(&pyth_feed(15, -1, 1, 100)[..239], 100, 10, 20),
+        Err(EscrowError::InvalidAccountData.into())
+    );
+}